@@ -0,0 +1,181 @@
+use crate::{
+    Float,
+    hh::{self, Axon, Params, Pulse, Setup},
+};
+
+/// A virtual conductance whose current is computed from the cell's own state at every step,
+/// as in a dynamic clamp experiment.
+#[derive(Clone, PartialEq)]
+pub enum Clamp {
+    /// instantly activating sodium, `g m_∞(V)³ (E_Na − V)` with the model's `m_∞`
+    Sodium { conductance: Float },
+    /// a train of alpha-shaped conductance events peaking at `conductance`
+    Synapse {
+        conductance: Float,
+        /// mV relative to rest
+        reversal: Float,
+        tau: Float,
+        start: Float,
+        interval: Float,
+        count: usize,
+    },
+    /// coupling `g (V_cell − V)` to a model cell driven by `pulse`, simulated beforehand and
+    /// so unaffected by the clamped cell
+    Cell {
+        conductance: Float,
+        pulse: Pulse,
+        /// voltage of the model cell every `dt`, empty until `record_cell`
+        v: Vec<Float>,
+        dt: Float,
+    },
+}
+
+impl Clamp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Clamp::Sodium { .. } => "synthetic Na conductance",
+            Clamp::Synapse { .. } => "virtual synapse",
+            Clamp::Cell { .. } => "model cell",
+        }
+    }
+
+    /// the examples to pick from, with their defaults
+    pub fn all() -> [Clamp; 3] {
+        [
+            Clamp::Sodium { conductance: 40.0 },
+            Clamp::Synapse {
+                conductance: 0.5,
+                reversal: 65.0,
+                tau: 2.0,
+                start: 2.0,
+                interval: 10.0,
+                count: 5,
+            },
+            Clamp::Cell {
+                conductance: 0.5,
+                pulse: Pulse {
+                    start: 5.0,
+                    end: 6.0,
+                    magnitude: 20.0,
+                },
+                v: Vec::new(),
+                dt: 0.01,
+            },
+        ]
+    }
+
+    /// Simulate the model cell of `Clamp::Cell` for the duration and step of `setup`, with the
+    /// parameters `params`.
+    pub fn record_cell(&mut self, setup: &Setup, params: &Params) {
+        if let Clamp::Cell { pulse, v, dt, .. } = self {
+            let cell = Setup {
+                pulse: pulse.clone(),
+                replay: None,
+                clamp: None,
+                ..setup.clone()
+            };
+            *v = hh::simulate(&cell, params).iter().map(Axon::v).collect();
+            *dt = setup.dt;
+        }
+    }
+
+    pub fn current(&self, params: &Params, t: Float, axon: &Axon) -> Float {
+        let v = axon.v();
+        match self {
+            Clamp::Sodium { conductance } => {
+                conductance * params.m_inf(v).powi(3) * (params.e_na - v)
+            }
+            Clamp::Synapse {
+                conductance,
+                reversal,
+                tau,
+                start,
+                interval,
+                count,
+            } => {
+                let open: Float = (0..*count)
+                    .map(|k| t - start - interval * k as Float)
+                    .filter(|&s| s > 0.0)
+                    .map(|s| s / tau * (1.0 - s / tau).exp())
+                    .sum();
+                conductance * open * (reversal - v)
+            }
+            Clamp::Cell {
+                conductance,
+                v: cell,
+                dt,
+                ..
+            } => {
+                let Some(&last) = cell.last() else {
+                    return 0.0;
+                };
+                let idx = t / dt;
+                let left = idx.floor() as usize;
+                let cell_v = match (cell.get(left), cell.get(left + 1)) {
+                    (Some(a), Some(b)) => a + (b - a) * (idx - left as Float),
+                    _ => last,
+                };
+                conductance * (cell_v - v)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spikes;
+
+    fn spike_times(setup: &Setup, params: &Params) -> Vec<Float> {
+        spikes::of_history(&hh::simulate(setup, params), setup.dt)
+            .iter()
+            .map(|s| s.t)
+            .collect()
+    }
+
+    #[test]
+    fn current_follows_the_state() {
+        let params = Params::default();
+        let (rest, up) = (params.steady_state(0.0), params.steady_state(30.0));
+        let sodium = Clamp::Sodium { conductance: 10.0 };
+        assert!(sodium.current(&params, 0.0, &up) > 100.0 * sodium.current(&params, 0.0, &rest));
+
+        let [_, synapse, _] = Clamp::all();
+        assert_eq!(synapse.current(&params, 1.0, &up), 0.0);
+        let (i_rest, i_up) = (
+            synapse.current(&params, 4.0, &rest),
+            synapse.current(&params, 4.0, &up),
+        );
+        // ohmic: halfway to the reversal potential, half the current
+        assert!((i_up / i_rest - (65.0 - 30.0) / 65.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clamps_excite_the_cell() {
+        let params = Params::default();
+        // too weak a pulse to fire by itself
+        let mut setup = Setup {
+            end: 60.0,
+            ..Default::default()
+        };
+        setup.pulse.magnitude = 3.0;
+        assert!(spike_times(&setup, &params).is_empty());
+
+        for mut clamp in Clamp::all() {
+            let name = clamp.name();
+            clamp.record_cell(&setup, &params);
+            let setup = Setup {
+                clamp: Some(clamp),
+                ..setup.clone()
+            };
+            let spikes = spike_times(&setup, &params);
+            assert!(!spikes.is_empty(), "{name}");
+            if name == "virtual synapse" {
+                assert!(spikes.iter().all(|&t| (t - 2.0) % 10.0 < 5.0));
+            }
+            if name == "model cell" {
+                assert!(spikes[0] > 5.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::ops::RangeInclusive;
+
+/// first index in `0..len` for which `pred` is false, `pred` being true then false
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// Reduce `len` samples with ascending x to about `2 * buckets` points for drawing, keeping
+/// the minimum and maximum of every bucket so that no peak is lost.
+///
+/// Only samples within `x` are considered, plus one on either side so the line runs to the
+/// edges of the view. With few enough of them, they are returned at full resolution.
+pub fn min_max(
+    len: usize,
+    sample: impl Fn(usize) -> [f64; 2],
+    x: RangeInclusive<f64>,
+    buckets: usize,
+) -> Vec<[f64; 2]> {
+    let start = partition_point(len, |i| sample(i)[0] < *x.start()).saturating_sub(1);
+    let end = (partition_point(len, |i| sample(i)[0] <= *x.end()) + 1).min(len);
+    if end <= start {
+        return Vec::new();
+    }
+    let count = end - start;
+    let buckets = buckets.max(1);
+    if count <= 2 * buckets {
+        return (start..end).map(sample).collect();
+    }
+
+    let mut points = Vec::with_capacity(2 * buckets + 2);
+    points.push(sample(start));
+    for bucket in 0..buckets {
+        let lo = start + count * bucket / buckets;
+        let hi = start + count * (bucket + 1) / buckets;
+        let (mut min, mut max) = (lo, lo);
+        for i in lo + 1..hi {
+            let y = sample(i)[1];
+            if y < sample(min)[1] {
+                min = i;
+            }
+            if y > sample(max)[1] {
+                max = i;
+            }
+        }
+        // in the order they occur, so the line does not run backwards
+        let (first, second) = if min <= max { (min, max) } else { (max, min) };
+        for i in [first, second] {
+            if i != start && points.last() != Some(&sample(i)) {
+                points.push(sample(i));
+            }
+        }
+    }
+    if points.last() != Some(&sample(end - 1)) {
+        points.push(sample(end - 1));
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_every_peak() {
+        let y = |i: usize| match i {
+            123_457 => 100.0,
+            654_321 => -100.0,
+            _ => (i as f64 * 0.01).sin(),
+        };
+        let points = min_max(1_000_000, |i| [i as f64, y(i)], f64::MIN..=f64::MAX, 300);
+        assert!(points.len() <= 2 * 300 + 2);
+        assert!(points.contains(&[123_457.0, 100.0]));
+        assert!(points.contains(&[654_321.0, -100.0]));
+        assert_eq!(points[0], [0.0, 0.0]);
+        assert_eq!(points.last().unwrap()[0], 999_999.0);
+        assert!(points.windows(2).all(|w| w[0][0] < w[1][0]));
+    }
+
+    #[test]
+    fn zooming_in_gives_full_resolution() {
+        let sample = |i: usize| [i as f64 * 0.01, i as f64];
+        let points = min_max(100_000, sample, 10.0..=10.5, 300);
+        // 10.0 through 10.5 plus one sample either side
+        assert_eq!(points.len(), 51 + 2);
+        assert_eq!(points[0], sample(999));
+        assert_eq!(points[52], sample(1051));
+
+        assert!(min_max(0, sample, 0.0..=1.0, 300).is_empty());
+        assert!(min_max(100, sample, 5.0..=6.0, 300).len() == 1);
+    }
+}
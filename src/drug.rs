@@ -0,0 +1,190 @@
+use crate::Float;
+
+/// Concentration-dependent block of one channel, following the Hill equation.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Block {
+    /// µM blocking half the channels
+    pub ic50: Float,
+    pub hill: Float,
+}
+
+impl Block {
+    /// fraction of channels blocked at `concentration` µM
+    pub fn fraction(&self, concentration: Float) -> Float {
+        if concentration <= 0.0 {
+            return 0.0;
+        }
+        let x = (concentration / self.ic50).powf(self.hill);
+        x / (1.0 + x)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Drug {
+    pub name: String,
+    /// block of the sodium channels, if any
+    pub na: Option<Block>,
+    /// block of the potassium channels, if any
+    pub k: Option<Block>,
+}
+
+impl Drug {
+    pub fn presets() -> [Drug; 3] {
+        [
+            Drug {
+                name: "TTX".to_string(),
+                na: Some(Block {
+                    ic50: 0.01,
+                    hill: 1.0,
+                }),
+                k: None,
+            },
+            Drug {
+                name: "TEA".to_string(),
+                na: None,
+                k: Some(Block {
+                    ic50: 5000.0,
+                    hill: 1.0,
+                }),
+            },
+            Drug {
+                name: "4-AP".to_string(),
+                na: None,
+                k: Some(Block {
+                    ic50: 1000.0,
+                    hill: 1.3,
+                }),
+            },
+        ]
+    }
+}
+
+/// A drug washed in at `on` and out at `off`, its concentration at the membrane approaching the
+/// bath concentration with time constant `tau`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Application {
+    pub drug: Drug,
+    /// µM in the bath
+    pub concentration: Float,
+    pub on: Float,
+    pub off: Float,
+    /// ms, 0 for an instant exchange
+    pub tau: Float,
+}
+
+impl Application {
+    pub fn new(drug: Drug) -> Self {
+        // a hundred times the IC50 blocks ~99% of the channels
+        let ic50 = drug.na.or(drug.k).map_or(1.0, |b| b.ic50);
+        Self {
+            drug,
+            concentration: 100.0 * ic50,
+            on: 0.0,
+            off: Float::INFINITY,
+            tau: 0.0,
+        }
+    }
+
+    /// µM at the membrane at time `t`
+    pub fn concentration(&self, t: Float) -> Float {
+        let approach = |elapsed: Float| {
+            if self.tau > 0.0 {
+                1.0 - (-elapsed / self.tau).exp()
+            } else {
+                1.0
+            }
+        };
+        if t < self.on {
+            0.0
+        } else if t < self.off {
+            self.concentration * approach(t - self.on)
+        } else {
+            let washed_in = self.concentration * approach(self.off - self.on);
+            washed_in * (1.0 - approach(t - self.off))
+        }
+    }
+}
+
+/// Fractions of sodium and potassium channels left unblocked at time `t` by `drugs`, binding
+/// independently of one another.
+pub fn unblocked(drugs: &[Application], t: Float) -> [Float; 2] {
+    let mut open = [1.0; 2];
+    for application in drugs {
+        let c = application.concentration(t);
+        for (open, block) in open
+            .iter_mut()
+            .zip([application.drug.na, application.drug.k])
+        {
+            if let Some(block) = block {
+                *open *= 1.0 - block.fraction(c);
+            }
+        }
+    }
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hh::{self, Params, Setup},
+        spikes,
+    };
+
+    #[test]
+    fn hill_block_and_wash() {
+        let [ttx, tea, _] = Drug::presets();
+        let block = ttx.na.unwrap();
+        assert_eq!(block.fraction(0.0), 0.0);
+        assert!((block.fraction(block.ic50) - 0.5).abs() < 1e-12);
+
+        let application = Application {
+            on: 10.0,
+            off: 30.0,
+            tau: 2.0,
+            ..Application::new(ttx)
+        };
+        assert_eq!(application.concentration(5.0), 0.0);
+        let c = application.concentration(12.0);
+        assert!((c / application.concentration - (1.0 - (-1.0 as Float).exp())).abs() < 1e-12);
+        assert!(application.concentration(29.9) > 0.99 * application.concentration);
+        assert!(application.concentration(40.0) < 0.01 * application.concentration);
+
+        let [na, k] = unblocked(&[application.clone(), Application::new(tea)], 25.0);
+        assert!(na < 0.02 && k < 0.02);
+        assert_eq!(unblocked(&[application], 0.0), [1.0; 2]);
+    }
+
+    #[test]
+    fn ttx_abolishes_spikes_until_washed_out() {
+        let count = |params: &Params, setup: &Setup| {
+            spikes::of_history(&hh::simulate(setup, params), setup.dt).len()
+        };
+        let setup = Setup {
+            end: 20.0,
+            ..Default::default()
+        };
+        let [ttx, ..] = Drug::presets();
+        let blocked = Params {
+            drugs: vec![Application::new(ttx.clone())],
+            ..Default::default()
+        };
+        assert_eq!(count(&Params::default(), &setup), 1);
+        assert_eq!(count(&blocked, &setup), 0);
+
+        // washed out well before a late pulse
+        let mut late = setup.clone();
+        late.end = 60.0;
+        late.pulse.start = 40.0;
+        late.pulse.end = 41.0;
+        let washed = Params {
+            drugs: vec![Application {
+                off: 10.0,
+                tau: 3.0,
+                ..Application::new(ttx)
+            }],
+            ..Default::default()
+        };
+        assert_eq!(count(&washed, &late), 1);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{
+    Float,
+    hh::{self, Axon, Params, Setup},
+    impedance, spikes,
+};
+
+/// integration steps per call of `Analysis::step`
+const STEPS_PER_CALL: usize = 1000;
+
+/// C, the charge of one ion
+const ELEMENTARY_CHARGE: Float = 1.602_176_634e-19;
+
+/// Na⁺ ions the Na⁺/K⁺ pump extrudes per ATP it hydrolyses.
+pub const NA_PER_ATP: Float = 3.0;
+
+/// Ionic charge moved during one spike, per cm² of membrane. Charges are in nC/cm², i.e. the
+/// time integral of µA/cm² over ms.
+#[derive(Clone, Copy, Debug)]
+pub struct SpikeCost {
+    /// threshold crossing, ms
+    pub t: Float,
+    /// inward sodium charge
+    pub na: Float,
+    /// outward potassium charge
+    pub k: Float,
+    /// `C ΔV`, the least charge that depolarises the membrane from its trough before the spike
+    /// to the peak
+    pub min: Float,
+    /// charge carried while sodium flows in and potassium out at once, wasted as they cancel
+    pub overlap: Float,
+}
+
+impl SpikeCost {
+    /// sodium charge over the least needed, 1 for a perfectly efficient spike
+    pub fn excess_ratio(&self) -> Float {
+        self.na / self.min
+    }
+
+    /// ATP molecules per cm² the pump spends to extrude the sodium that came in
+    pub fn atp(&self) -> Float {
+        self.na * 1e-9 / ELEMENTARY_CHARGE / NA_PER_ATP
+    }
+}
+
+/// Costs of every spike of `history`, sampled every `dt`, each spike taking the charge between
+/// the midpoints to its neighbours. Only the currents beyond those at rest count, lest a spike's
+/// cost grow with the run. None for custom models and mechanisms, whose currents are not the
+/// built-in sodium and potassium, or without a resting potential.
+pub fn spike_costs(history: &[Axon], dt: Float, params: &Params) -> Option<Vec<SpikeCost>> {
+    if params.custom.is_some() || params.mechanism.is_some() {
+        return None;
+    }
+    let resting = params.steady_state(impedance::rest(params)?);
+    let t = |k: usize| k as Float * dt;
+    let crossings: Vec<Float> = spikes::of_history(history, dt)
+        .iter()
+        .map(|s| s.t)
+        .collect();
+
+    let mut costs = Vec::with_capacity(crossings.len());
+    for (idx, &crossing) in crossings.iter().enumerate() {
+        let from = match idx {
+            0 => 0,
+            _ => (0.5 * (crossings[idx - 1] + crossing) / dt).ceil() as usize,
+        };
+        let to = match crossings.get(idx + 1) {
+            Some(next) => (0.5 * (crossing + next) / dt).ceil() as usize,
+            None => history.len(),
+        };
+        let window = &history[from..to.min(history.len())];
+
+        let (mut na, mut k, mut overlap) = (0.0, 0.0, 0.0);
+        let mut previous: Option<[Float; 3]> = None;
+        for (j, axon) in window.iter().enumerate() {
+            let t = t(from + j);
+            let i_na = axon.i_na(params, t) - resting.i_na(params, t);
+            let i_k = axon.i_k(params, t) - resting.i_k(params, t);
+            // inward sodium is negative, outward potassium positive
+            let sample = [
+                (-i_na).max(0.0),
+                i_k.max(0.0),
+                Float::min(-i_na, i_k).max(0.0),
+            ];
+            if let Some(previous) = previous {
+                na += 0.5 * (previous[0] + sample[0]) * dt;
+                k += 0.5 * (previous[1] + sample[1]) * dt;
+                overlap += 0.5 * (previous[2] + sample[2]) * dt;
+            }
+            previous = Some(sample);
+        }
+
+        let peak_idx = window
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.v().total_cmp(&b.1.v()))
+            .map_or(0, |(j, _)| j);
+        let peak = window.get(peak_idx).map_or(0.0, Axon::v);
+        let trough = window[..=peak_idx.min(window.len().saturating_sub(1))]
+            .iter()
+            .map(Axon::v)
+            .fold(peak, Float::min);
+        costs.push(SpikeCost {
+            t: crossing,
+            na,
+            k,
+            min: params.c_m * (peak - trough),
+            overlap,
+        });
+    }
+    Some(costs)
+}
+
+/// Spike costs of a setup, simulated a bounded number of steps per `step`.
+pub struct Analysis {
+    setup: Setup,
+    params: Params,
+    history: Vec<Axon>,
+}
+
+impl Analysis {
+    pub fn new(setup: &Setup, params: &Params) -> Self {
+        Self {
+            setup: setup.clone(),
+            params: params.clone(),
+            history: Vec::with_capacity(setup.total_steps()),
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.history.len() >= self.setup.total_steps()
+    }
+
+    /// fraction of the simulation done
+    pub fn progress(&self) -> Float {
+        self.history.len() as Float / self.setup.total_steps().max(1) as Float
+    }
+
+    pub fn step(&mut self) {
+        let (setup, params) = (&self.setup, &self.params);
+        let remaining = setup.total_steps().saturating_sub(self.history.len());
+        for _ in 0..STEPS_PER_CALL.min(remaining) {
+            let axon = match self.history.last() {
+                None => params.steady_state(setup.v0),
+                Some(&last) => {
+                    let t = setup.dt * (self.history.len() - 1) as Float;
+                    hh::integrate(params, last, t, setup.dt, |t, a| {
+                        setup.stimulus(params, t, a)
+                    })
+                }
+            };
+            self.history.push(axon);
+        }
+    }
+
+    /// costs of the spikes, once finished
+    pub fn costs(&self) -> Option<Vec<SpikeCost>> {
+        spike_costs(&self.history, self.setup.dt, &self.params)
+    }
+}
+
+/// Average cost of the spikes of one run.
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    pub spikes: usize,
+    pub na: Float,
+    pub k: Float,
+    pub excess_ratio: Float,
+    pub atp: Float,
+}
+
+impl Summary {
+    /// simulate `setup` with `params` and average over its spikes, NaN without any
+    pub fn of(setup: &Setup, params: &Params) -> Self {
+        let costs = spike_costs(&hh::simulate(setup, params), setup.dt, params).unwrap_or_default();
+        let mean = |f: &dyn Fn(&SpikeCost) -> Float| {
+            costs.iter().map(f).sum::<Float>() / costs.len() as Float
+        };
+        Self {
+            spikes: costs.len(),
+            na: mean(&|c| c.na),
+            k: mean(&|c| c.k),
+            excess_ratio: mean(&SpikeCost::excess_ratio),
+            atp: mean(&SpikeCost::atp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squid_spike_is_wasteful() {
+        let (setup, params) = (Setup::default(), Params::default());
+        let mut analysis = Analysis::new(&setup, &params);
+        while !analysis.finished() {
+            analysis.step();
+        }
+        let costs = analysis.costs().unwrap();
+        assert_eq!(costs.len(), 1);
+        let cost = costs[0];
+        assert!(cost.excess_ratio() > 1.0);
+        assert!(cost.overlap > 0.0 && cost.overlap < cost.na);
+        // at rest before and after, the charge moved in comes back out
+        assert!((cost.k - cost.na).abs() < 0.2 * cost.na);
+        let ions = cost.na * 1e-9 / ELEMENTARY_CHARGE;
+        assert!((cost.atp() * NA_PER_ATP - ions).abs() < 1e-6 * ions);
+    }
+
+    #[test]
+    fn cost_is_independent_of_run_length() {
+        let params = Params::default();
+        let cost = |end| {
+            let setup = Setup {
+                end,
+                ..Default::default()
+            };
+            spike_costs(&hh::simulate(&setup, &params), setup.dt, &params).unwrap()[0]
+        };
+        let (short, long) = (cost(30.0), cost(150.0));
+        assert!((long.na - short.na).abs() < 0.01 * short.na);
+        assert!((long.k - short.k).abs() < 0.01 * short.k);
+    }
+
+    #[test]
+    fn warmer_spikes_are_cheaper() {
+        let setup = Setup {
+            end: 20.0,
+            ..Default::default()
+        };
+        let at = |temperature| {
+            let params = Params {
+                temperature,
+                ..Default::default()
+            };
+            Summary::of(&setup, &params)
+        };
+        let (cold, warm) = (at(6.3), at(18.5));
+        assert_eq!((cold.spikes, warm.spikes), (1, 1));
+        assert!(warm.excess_ratio < cold.excess_ratio);
+        assert!(warm.atp < cold.atp);
+    }
+}
@@ -0,0 +1,756 @@
+use crate::Float;
+
+use std::ops::Range;
+
+pub type Span = Range<usize>;
+
+/// A compile error pointing at the offending part of the source.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Error {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Error {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// `line:column: message`, followed by the source line with the span underlined
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count();
+        let width = source[start..self.span.end.clamp(start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+        format!(
+            "{line}:{}: {}\n{}\n{}{}",
+            column + 1,
+            self.message,
+            &source[line_start..line_end],
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    Num(Float),
+    Ident(String),
+    /// operators and delimiters
+    Punct(&'static str),
+    Newline,
+    Eof,
+}
+
+const PUNCTS: [&str; 22] = [
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "^", "(", ")", ",", "=", "'", "<", ">",
+    "{", "}", "!", ";",
+];
+
+/// Split `source` into tokens. `#` starts a comment running to the end of the line.
+pub fn tokenize(source: &str) -> Result<Vec<(Token, Span)>, Error> {
+    let mut tokens = Vec::new();
+    let bytes = source.as_bytes();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let c = source[pos..]
+            .chars()
+            .next()
+            .expect("pos is on a char boundary");
+        let start = pos;
+        if c == '\n' {
+            tokens.push((Token::Newline, start..start + 1));
+            pos += 1;
+        } else if c.is_whitespace() {
+            pos += c.len_utf8();
+        } else if c == '#' {
+            pos = source[pos..].find('\n').map_or(source.len(), |i| pos + i);
+        } else if c.is_ascii_digit()
+            || (c == '.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit))
+        {
+            while pos < bytes.len() && (bytes[pos].is_ascii_digit() || bytes[pos] == b'.') {
+                pos += 1;
+            }
+            if pos < bytes.len() && (bytes[pos] == b'e' || bytes[pos] == b'E') {
+                let mut exp = pos + 1;
+                if exp < bytes.len() && (bytes[exp] == b'+' || bytes[exp] == b'-') {
+                    exp += 1;
+                }
+                if exp < bytes.len() && bytes[exp].is_ascii_digit() {
+                    pos = exp;
+                    while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                        pos += 1;
+                    }
+                }
+            }
+            let text = &source[start..pos];
+            let value = text
+                .parse()
+                .map_err(|_| Error::new(format!("malformed number `{text}`"), start..pos))?;
+            tokens.push((Token::Num(value), start..pos));
+        } else if c.is_alphabetic() || c == '_' {
+            while let Some(c) = source[pos..].chars().next() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                pos += c.len_utf8();
+            }
+            tokens.push((Token::Ident(source[start..pos].to_string()), start..pos));
+        } else if let Some(p) = PUNCTS.iter().find(|p| source[pos..].starts_with(**p)) {
+            pos += p.len();
+            tokens.push((Token::Punct(p), start..pos));
+        } else {
+            return Err(Error::new(
+                format!("unexpected character `{c}`"),
+                start..start + c.len_utf8(),
+            ));
+        }
+    }
+    tokens.push((Token::Eof, source.len()..source.len()));
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+impl BinOp {
+    pub fn apply(&self, l: Float, r: Float) -> Float {
+        match self {
+            BinOp::Add => l + r,
+            BinOp::Sub => l - r,
+            BinOp::Mul => l * r,
+            BinOp::Div => l / r,
+            BinOp::Pow => l.powf(r),
+        }
+    }
+}
+
+/// Parsed but unresolved expression: names are still strings.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Ast {
+    Num(Float, Span),
+    Name(String, Span),
+    Neg(Box<Ast>, Span),
+    Bin(BinOp, Box<Ast>, Box<Ast>),
+    Call(String, Vec<Ast>, Span),
+}
+
+impl Ast {
+    pub fn span(&self) -> Span {
+        match self {
+            Ast::Num(_, s) | Ast::Name(_, s) | Ast::Neg(_, s) | Ast::Call(_, _, s) => s.clone(),
+            Ast::Bin(_, l, r) => l.span().start..r.span().end,
+        }
+    }
+}
+
+/// Recursive descent over a token stream, shared by every text format built on these
+/// expressions.
+pub struct Parser {
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<(Token, Span)>) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    pub fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    pub fn span(&self) -> Span {
+        self.tokens[self.pos].1.clone()
+    }
+
+    pub fn next(&mut self) -> (Token, Span) {
+        let token = self.tokens[self.pos].clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    pub fn at_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Token::Punct(q) if *q == p)
+    }
+
+    pub fn eat_punct(&mut self, p: &str) -> bool {
+        let found = self.at_punct(p);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    pub fn expect_punct(&mut self, p: &str) -> Result<Span, Error> {
+        if self.at_punct(p) {
+            Ok(self.next().1)
+        } else {
+            Err(Error::new(format!("expected `{p}`"), self.span()))
+        }
+    }
+
+    pub fn ident(&mut self) -> Result<(String, Span), Error> {
+        match self.next() {
+            (Token::Ident(name), span) => Ok((name, span)),
+            (_, span) => Err(Error::new("expected a name", span)),
+        }
+    }
+
+    pub fn skip_newlines(&mut self) {
+        while *self.peek() == Token::Newline {
+            self.next();
+        }
+    }
+
+    pub fn expr(&mut self) -> Result<Ast, Error> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat_punct("+") {
+                BinOp::Add
+            } else if self.eat_punct("-") {
+                BinOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Ast::Bin(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Ast, Error> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat_punct("*") {
+                BinOp::Mul
+            } else if self.eat_punct("/") {
+                BinOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Ast::Bin(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Ast, Error> {
+        if self.at_punct("-") {
+            let start = self.next().1.start;
+            let operand = self.unary()?;
+            let span = start..operand.span().end;
+            return Ok(Ast::Neg(Box::new(operand), span));
+        }
+        if self.eat_punct("+") {
+            return self.unary();
+        }
+        self.power()
+    }
+
+    /// `^` binds tighter than unary minus and associates to the right
+    fn power(&mut self) -> Result<Ast, Error> {
+        let base = self.atom()?;
+        if self.eat_punct("^") {
+            let exponent = self.unary()?;
+            return Ok(Ast::Bin(BinOp::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Ast, Error> {
+        match self.next() {
+            (Token::Num(x), span) => Ok(Ast::Num(x, span)),
+            (Token::Ident(name), span) => {
+                if !self.eat_punct("(") {
+                    return Ok(Ast::Name(name, span));
+                }
+                let mut args = Vec::new();
+                if !self.at_punct(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if !self.eat_punct(",") {
+                            break;
+                        }
+                    }
+                }
+                let end = self.expect_punct(")")?.end;
+                Ok(Ast::Call(name, args, span.start..end))
+            }
+            (Token::Punct("("), _) => {
+                let inner = self.expr()?;
+                self.expect_punct(")")?;
+                Ok(inner)
+            }
+            (_, span) => Err(Error::new("expected a number, a name or `(`", span)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Func {
+    Exp,
+    Log,
+    Sqrt,
+    Abs,
+    Tanh,
+    Sin,
+    Cos,
+    Pow,
+    Min,
+    Max,
+    /// `x / (1 - exp(-x))`, safe at `x = 0`
+    Linoid,
+}
+
+impl Func {
+    pub fn lookup(name: &str) -> Option<(Func, usize)> {
+        Some(match name {
+            "exp" => (Func::Exp, 1),
+            "log" => (Func::Log, 1),
+            "sqrt" => (Func::Sqrt, 1),
+            "abs" | "fabs" => (Func::Abs, 1),
+            "tanh" => (Func::Tanh, 1),
+            "sin" => (Func::Sin, 1),
+            "cos" => (Func::Cos, 1),
+            "pow" => (Func::Pow, 2),
+            "min" => (Func::Min, 2),
+            "max" => (Func::Max, 2),
+            "linoid" => (Func::Linoid, 1),
+            _ => return None,
+        })
+    }
+
+    pub fn apply(&self, args: &[Float]) -> Float {
+        match self {
+            Func::Exp => args[0].exp(),
+            Func::Log => args[0].ln(),
+            Func::Sqrt => args[0].sqrt(),
+            Func::Abs => args[0].abs(),
+            Func::Tanh => args[0].tanh(),
+            Func::Sin => args[0].sin(),
+            Func::Cos => args[0].cos(),
+            Func::Pow => args[0].powf(args[1]),
+            Func::Min => args[0].min(args[1]),
+            Func::Max => args[0].max(args[1]),
+            Func::Linoid => {
+                crate::rate::Rate::new(crate::rate::Family::Linoid, 1.0, 0.0, 1.0).eval(args[0])
+            }
+        }
+    }
+}
+
+/// Resolved expression over a slice of slot values.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    Num(Float),
+    Slot(usize),
+    Neg(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+impl Expr {
+    /// Resolve names with `lookup`, which returns the expression a name stands for.
+    pub fn resolve(ast: &Ast, lookup: &impl Fn(&str) -> Option<Expr>) -> Result<Expr, Error> {
+        Ok(match ast {
+            Ast::Num(x, _) => Expr::Num(*x),
+            Ast::Name(name, span) => lookup(name)
+                .ok_or_else(|| Error::new(format!("unknown name `{name}`"), span.clone()))?,
+            Ast::Neg(operand, _) => Expr::Neg(Box::new(Expr::resolve(operand, lookup)?)),
+            Ast::Bin(op, l, r) => Expr::Bin(
+                *op,
+                Box::new(Expr::resolve(l, lookup)?),
+                Box::new(Expr::resolve(r, lookup)?),
+            ),
+            Ast::Call(name, args, span) => {
+                let (func, arity) = Func::lookup(name).ok_or_else(|| {
+                    Error::new(format!("unknown function `{name}`"), span.clone())
+                })?;
+                if args.len() != arity {
+                    return Err(Error::new(
+                        format!("`{name}` takes {arity} argument(s), got {}", args.len()),
+                        span.clone(),
+                    ));
+                }
+                Expr::Call(
+                    func,
+                    args.iter()
+                        .map(|a| Expr::resolve(a, lookup))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+        })
+    }
+
+    pub fn eval(&self, slots: &[Float]) -> Float {
+        match self {
+            Expr::Num(x) => *x,
+            Expr::Slot(idx) => slots[*idx],
+            Expr::Neg(e) => -e.eval(slots),
+            Expr::Bin(op, l, r) => op.apply(l.eval(slots), r.eval(slots)),
+            Expr::Call(func, args) => {
+                let mut values = [0.0; 2];
+                for (v, a) in values.iter_mut().zip(args) {
+                    *v = a.eval(slots);
+                }
+                func.apply(&values)
+            }
+        }
+    }
+}
+
+/// slots: `t`, `I`, the states, then the `let` definitions
+const FIXED_SLOTS: usize = 2;
+const MAX_SLOTS: usize = 256;
+
+/// Names of `hh::Axon` slots, in order. Other states take whichever slot is left.
+const AXON_STATES: [&str; 4] = ["V", "m", "h", "n"];
+
+/// A model compiled from text such as `HODGKIN_HUXLEY`:
+///
+/// - `param NAME = EXPR` defines a constant, from numbers and earlier parameters
+/// - `state NAME = EXPR` declares a state variable and its initial value; `V` must be declared
+///   and starts at the setup's initial voltage instead
+/// - `let NAME = EXPR` defines an intermediate value, from anything declared before it
+/// - `NAME' = EXPR` gives the time derivative of a state
+///
+/// Built in are the time `t`, the injected current `I` and the functions exp, log, sqrt, abs,
+/// tanh, sin, cos, pow, min, max and linoid.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Model {
+    states: Vec<String>,
+    /// `hh::Axon` slot of each state
+    axon_slot: Vec<usize>,
+    inits: Vec<Option<Expr>>,
+    lets: Vec<Expr>,
+    derivatives: Vec<Expr>,
+}
+
+pub const HODGKIN_HUXLEY: &str = "\
+# Hodgkin–Huxley, voltages relative to rest
+param c_m = 1
+param g_na = 120
+param g_k = 36
+param g_l = 0.3
+param e_na = 115
+param e_k = -12
+param e_l = 10.6
+
+state V
+let alpha_m = linoid((V - 25) / 10)
+let beta_m = 4 * exp(-V / 18)
+let alpha_h = 0.07 * exp(-V / 20)
+let beta_h = 1 / (1 + exp(-(V - 30) / 10))
+let alpha_n = 0.1 * linoid((V - 10) / 10)
+let beta_n = 0.125 * exp(-V / 80)
+
+state m = alpha_m / (alpha_m + beta_m)
+state h = alpha_h / (alpha_h + beta_h)
+state n = alpha_n / (alpha_n + beta_n)
+
+let i_na = g_na * m^3 * h * (V - e_na)
+let i_k = g_k * n^4 * (V - e_k)
+let i_l = g_l * (V - e_l)
+
+V' = (I - i_na - i_k - i_l) / c_m
+m' = alpha_m * (1 - m) - beta_m * m
+h' = alpha_h * (1 - h) - beta_h * h
+n' = alpha_n * (1 - n) - beta_n * n
+";
+
+#[derive(Clone, PartialEq)]
+enum Name {
+    Param(Float),
+    State(usize),
+    Let(usize),
+}
+
+pub fn compile(source: &str) -> Result<Model, Error> {
+    let mut parser = Parser::new(tokenize(source)?);
+    let mut names: Vec<(String, Name)> = Vec::new();
+    let mut states: Vec<(String, Span, Option<Ast>)> = Vec::new();
+    let mut lets: Vec<Ast> = Vec::new();
+    let mut derivatives: Vec<Option<(Ast, usize)>> = Vec::new();
+    let mut pending_derivatives: Vec<(String, Span, Ast, usize)> = Vec::new();
+
+    let declare = |names: &mut Vec<(String, Name)>, name: String, span: Span, value: Name| {
+        if name == "t" || name == "I" || Func::lookup(&name).is_some() {
+            return Err(Error::new(format!("`{name}` is built in"), span));
+        }
+        if names.iter().any(|(n, _)| *n == name) {
+            return Err(Error::new(format!("`{name}` is already defined"), span));
+        }
+        names.push((name, value));
+        Ok(())
+    };
+
+    loop {
+        parser.skip_newlines();
+        if *parser.peek() == Token::Eof {
+            break;
+        }
+        let (word, span) = parser.ident()?;
+        match word.as_str() {
+            "param" => {
+                let (name, span) = parser.ident()?;
+                parser.expect_punct("=")?;
+                let ast = parser.expr()?;
+                let value = Expr::resolve(&ast, &|n| match names.iter().find(|(m, _)| m == n) {
+                    Some((_, Name::Param(x))) => Some(Expr::Num(*x)),
+                    _ => None,
+                })?
+                .eval(&[]);
+                declare(&mut names, name, span, Name::Param(value))?;
+            }
+            "state" => {
+                let (name, span) = parser.ident()?;
+                let init = if parser.eat_punct("=") {
+                    Some(parser.expr()?)
+                } else {
+                    None
+                };
+                declare(
+                    &mut names,
+                    name.clone(),
+                    span.clone(),
+                    Name::State(states.len()),
+                )?;
+                states.push((name, span, init));
+                derivatives.push(None);
+            }
+            "let" => {
+                let (name, span) = parser.ident()?;
+                parser.expect_punct("=")?;
+                lets.push(parser.expr()?);
+                declare(&mut names, name, span, Name::Let(lets.len() - 1))?;
+            }
+            _ => {
+                parser.expect_punct("'")?;
+                parser.expect_punct("=")?;
+                let ast = parser.expr()?;
+                // resolved at the end, against every definition seen so far
+                pending_derivatives.push((word, span, ast, names.len()));
+            }
+        }
+        if !matches!(parser.peek(), Token::Newline | Token::Eof) {
+            return Err(Error::new("expected the end of the line", parser.span()));
+        }
+    }
+
+    for (name, span, ast, visible) in pending_derivatives {
+        let Some(idx) = names.iter().position(|(n, _)| *n == name) else {
+            return Err(Error::new(format!("unknown state `{name}`"), span));
+        };
+        let Name::State(state) = names[idx].1 else {
+            return Err(Error::new(format!("`{name}` is not a state"), span));
+        };
+        if derivatives[state].is_some() {
+            return Err(Error::new(format!("`{name}'` is already defined"), span));
+        }
+        derivatives[state] = Some((ast, visible));
+    }
+
+    let v = states
+        .iter()
+        .position(|(n, _, _)| n == "V")
+        .ok_or_else(|| Error::new("the model must declare `state V`", 0..0))?;
+    if states.len() > AXON_STATES.len() {
+        return Err(Error::new(
+            format!("at most {} states are supported", AXON_STATES.len()),
+            states[AXON_STATES.len()].1.clone(),
+        ));
+    }
+    if FIXED_SLOTS + states.len() + lets.len() > MAX_SLOTS {
+        return Err(Error::new("too many definitions", 0..0));
+    }
+
+    // names resolve only against what was declared before them
+    let slot_of = |visible: usize| {
+        let names = &names[..visible];
+        let n_states = states.len();
+        move |n: &str| -> Option<Expr> {
+            match n {
+                "t" => return Some(Expr::Slot(0)),
+                "I" => return Some(Expr::Slot(1)),
+                _ => {}
+            }
+            names
+                .iter()
+                .find(|(m, _)| m == n)
+                .map(|(_, name)| match name {
+                    Name::Param(x) => Expr::Num(*x),
+                    Name::State(s) => Expr::Slot(FIXED_SLOTS + s),
+                    Name::Let(l) => Expr::Slot(FIXED_SLOTS + n_states + l),
+                })
+        }
+    };
+    let visible_at = |name: &str| {
+        names
+            .iter()
+            .position(|(n, _)| n == name)
+            .expect("declared above")
+    };
+
+    let mut resolved_lets = Vec::new();
+    for (idx, ast) in lets.iter().enumerate() {
+        let position = names
+            .iter()
+            .position(|(_, n)| *n == Name::Let(idx))
+            .expect("declared above");
+        resolved_lets.push(Expr::resolve(ast, &slot_of(position))?);
+    }
+
+    let mut inits = Vec::new();
+    let mut resolved_derivatives = Vec::new();
+    for (idx, (name, span, init)) in states.iter().enumerate() {
+        inits.push(match init {
+            Some(ast) => Some(Expr::resolve(ast, &slot_of(visible_at(name)))?),
+            None => None,
+        });
+        let Some((ast, visible)) = &derivatives[idx] else {
+            return Err(Error::new(
+                format!("missing equation for `{name}'`"),
+                span.clone(),
+            ));
+        };
+        resolved_derivatives.push(Expr::resolve(ast, &slot_of(*visible))?);
+    }
+
+    let mut axon_slot = vec![usize::MAX; states.len()];
+    for (idx, (name, _, _)) in states.iter().enumerate() {
+        if let Some(slot) = AXON_STATES.iter().position(|s| s == name) {
+            axon_slot[idx] = slot;
+        }
+    }
+    for idx in 0..states.len() {
+        if axon_slot[idx] == usize::MAX {
+            axon_slot[idx] = (0..AXON_STATES.len())
+                .find(|slot| !axon_slot.contains(slot))
+                .expect("at most as many states as slots");
+        }
+    }
+    debug_assert_eq!(axon_slot[v], 0);
+
+    Ok(Model {
+        states: states.into_iter().map(|(n, _, _)| n).collect(),
+        axon_slot,
+        inits,
+        lets: resolved_lets,
+        derivatives: resolved_derivatives,
+    })
+}
+
+impl Model {
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+
+    /// fill `slots` from time, current and an `hh::Axon` state, evaluating every `let`
+    fn fill(&self, slots: &mut [Float], state: &[Float; 4], t: Float, i: Float) {
+        slots[0] = t;
+        slots[1] = i;
+        for (idx, &slot) in self.axon_slot.iter().enumerate() {
+            slots[FIXED_SLOTS + idx] = state[slot];
+        }
+        let base = FIXED_SLOTS + self.states.len();
+        for (idx, expr) in self.lets.iter().enumerate() {
+            slots[base + idx] = expr.eval(slots);
+        }
+    }
+
+    /// same contract as `hh::Params::derivative`
+    pub fn derivative(&self, state: &[Float; 4], t: Float, i: Float, d_state: &mut [Float; 4]) {
+        let mut slots = [0.0; MAX_SLOTS];
+        self.fill(&mut slots, state, t, i);
+        *d_state = [0.0; 4];
+        for (expr, &slot) in self.derivatives.iter().zip(&self.axon_slot) {
+            d_state[slot] = expr.eval(&slots);
+        }
+    }
+
+    /// initial `hh::Axon` state, with `V` at `v0`; states without an initial value start at 0
+    pub fn initial(&self, v0: Float) -> [Float; 4] {
+        let mut state = [0.0; 4];
+        state[0] = v0;
+        let mut slots = [0.0; MAX_SLOTS];
+        for (init, &slot) in self.inits.iter().zip(&self.axon_slot) {
+            if let (Some(init), true) = (init, slot != 0) {
+                self.fill(&mut slots, &state, 0.0, 0.0);
+                state[slot] = init.eval(&slots);
+            }
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hh;
+
+    #[test]
+    fn hodgkin_huxley_reference() {
+        let model = compile(HODGKIN_HUXLEY).unwrap();
+        let builtin = hh::Params::default();
+        let custom = hh::Params {
+            custom: Some(model),
+            ..Default::default()
+        };
+        let setup = hh::Setup::default();
+
+        let expected = hh::simulate(&setup, &builtin);
+        let actual = hh::simulate(&setup, &custom);
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(&actual) {
+            assert!((e.v() - a.v()).abs() < 1e-9);
+            assert!((e.m() - a.m()).abs() < 1e-9);
+            assert!((e.h() - a.h()).abs() < 1e-9);
+            assert!((e.n() - a.n()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let mut parser = Parser::new(tokenize("-2^2 + 3 * 4 / 2 - pow(2, 3)").unwrap());
+        let expr = Expr::resolve(&parser.expr().unwrap(), &|_| None).unwrap();
+        assert_eq!(expr.eval(&[]), -4.0 + 6.0 - 8.0);
+    }
+
+    #[test]
+    fn errors_point_at_source() {
+        let source = "state V\nV' = -V + q\n";
+        let err = compile(source).unwrap_err();
+        assert_eq!(err.message, "unknown name `q`");
+        assert_eq!(&source[err.span.clone()], "q");
+        assert_eq!(
+            err.render(source),
+            "2:11: unknown name `q`\nV' = -V + q\n          ^"
+        );
+
+        let err = compile("state V\nstate m\nV' = 0\n").unwrap_err();
+        assert_eq!(err.message, "missing equation for `m'`");
+
+        let err = compile("state V\nV' = exp(V, 1)\n").unwrap_err();
+        assert_eq!(err.message, "`exp` takes 1 argument(s), got 2");
+
+        let err = compile("let a = b\nlet b = 1\nstate V\nV' = a\n").unwrap_err();
+        assert_eq!(err.message, "unknown name `b`");
+    }
+}
@@ -0,0 +1,169 @@
+use crate::Float;
+
+use std::ops::{Add, Div, Mul, Sub};
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Complex {
+    pub re: Float,
+    pub im: Float,
+}
+
+impl Complex {
+    pub const fn new(re: Float, im: Float) -> Self {
+        Self { re, im }
+    }
+
+    /// `e^{iθ}`
+    pub fn cis(theta: Float) -> Self {
+        Self::new(theta.cos(), theta.sin())
+    }
+
+    pub fn norm(&self) -> Float {
+        self.re.hypot(self.im)
+    }
+
+    pub fn norm_sqr(&self) -> Float {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// radians
+    pub fn arg(&self) -> Float {
+        self.im.atan2(self.re)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        let d = rhs.norm_sqr();
+        Self::new(
+            (self.re * rhs.re + self.im * rhs.im) / d,
+            (self.im * rhs.re - self.re * rhs.im) / d,
+        )
+    }
+}
+
+/// bit-reversal permutation, after checking the length is a power of two
+fn reorder(data: &mut [Complex]) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "FFT of {n} points");
+    if n == 1 {
+        return;
+    }
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+}
+
+/// the stage combining transforms of `len / 2` points into transforms of `len`
+fn butterflies(data: &mut [Complex], len: usize) {
+    let step = Complex::cis(-2.0 * std::f64::consts::PI / len as Float);
+    for chunk in data.chunks_exact_mut(len) {
+        let (low, high) = chunk.split_at_mut(len / 2);
+        let mut w = Complex::new(1.0, 0.0);
+        for (a, b) in low.iter_mut().zip(high) {
+            let t = w * *b;
+            (*a, *b) = (*a + t, *a - t);
+            w = w * step;
+        }
+    }
+}
+
+/// Discrete Fourier transform, `X_k = Σ x_n e^{-2πikn/N}`, of real values zero-padded to the
+/// next power of two, by iterative radix-2 Cooley–Tukey. Taken one stage at a time so that a
+/// long transform can be spread over many calls.
+pub struct Transform {
+    pub data: Vec<Complex>,
+    /// length of the transforms the next stage builds
+    len: usize,
+}
+
+impl Transform {
+    pub fn real(values: &[Float]) -> Self {
+        let mut data = vec![Complex::default(); values.len().next_power_of_two()];
+        for (d, &v) in data.iter_mut().zip(values) {
+            d.re = v;
+        }
+        reorder(&mut data);
+        Self { data, len: 2 }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.len > self.data.len()
+    }
+
+    pub fn stage(&mut self) {
+        if !self.finished() {
+            butterflies(&mut self.data, self.len);
+            self.len *= 2;
+        }
+    }
+}
+
+/// transform of real `values`, zero-padded to the next power of two
+pub fn real(values: &[Float]) -> Vec<Complex> {
+    let mut transform = Transform::real(values);
+    while !transform.finished() {
+        transform.stage();
+    }
+    transform.data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_direct_transform() {
+        let values: Vec<Float> = (0..16)
+            .map(|k| (k as Float * 0.7).sin() + 0.1 * k as Float)
+            .collect();
+        let transform = real(&values);
+        for (k, x) in transform.iter().enumerate() {
+            let direct = values
+                .iter()
+                .enumerate()
+                .map(|(n, &v)| {
+                    let theta = -2.0 * std::f64::consts::PI * (k * n) as Float / 16.0;
+                    Complex::cis(theta) * Complex::new(v, 0.0)
+                })
+                .fold(Complex::default(), |a, b| a + b);
+            assert!((*x - direct).norm() < 1e-9);
+        }
+
+        // Parseval
+        let energy: Float = values.iter().map(|v| v * v).sum();
+        let spectral: Float = transform.iter().map(Complex::norm_sqr).sum::<Float>() / 16.0;
+        assert!((energy - spectral).abs() < 1e-9 * energy);
+        let quotient = Complex::new(1.0, 2.0) / Complex::new(3.0, -1.0);
+        assert!((quotient - Complex::new(0.1, 0.7)).norm() < 1e-12);
+    }
+}
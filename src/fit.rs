@@ -0,0 +1,514 @@
+use crate::{
+    Float,
+    hh::{self, Axon, Params, Setup},
+    optim, rate, spikes,
+};
+
+/// A model parameter the fitter may adjust.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Param {
+    GNa,
+    GK,
+    GL,
+    ENa,
+    EK,
+    EL,
+    /// coefficient `coeff` of gate `gate`, in `rate::GATES` and `rate::Gate::coeffs` order
+    Kinetic {
+        gate: usize,
+        coeff: usize,
+        name: &'static str,
+        potential: bool,
+    },
+}
+
+impl Param {
+    /// every parameter of `p`; the kinetic ones depend on the families the gates use
+    pub fn all(p: &Params) -> Vec<Param> {
+        let mut all = vec![
+            Param::GNa,
+            Param::GK,
+            Param::GL,
+            Param::ENa,
+            Param::EK,
+            Param::EL,
+        ];
+        let mut kinetics = p.kinetics.clone();
+        for (gate, g) in kinetics.gates.iter_mut().enumerate() {
+            for (coeff, c) in g.coeffs().into_iter().enumerate() {
+                all.push(Param::Kinetic {
+                    gate,
+                    coeff,
+                    name: c.name,
+                    potential: c.potential,
+                });
+            }
+        }
+        all
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Param::GNa => "g_Na".to_string(),
+            Param::GK => "g_K".to_string(),
+            Param::GL => "g_L".to_string(),
+            Param::ENa => "E_Na".to_string(),
+            Param::EK => "E_K".to_string(),
+            Param::EL => "E_L".to_string(),
+            Param::Kinetic { gate, name, .. } => format!("{} {}", rate::GATES[*gate], name),
+        }
+    }
+
+    fn field<'a>(&self, p: &'a mut Params) -> &'a mut Float {
+        match self {
+            Param::GNa => &mut p.g_na,
+            Param::GK => &mut p.g_k,
+            Param::GL => &mut p.g_l,
+            Param::ENa => &mut p.e_na,
+            Param::EK => &mut p.e_k,
+            Param::EL => &mut p.e_l,
+            Param::Kinetic { gate, coeff, .. } => {
+                let mut coeffs = p.kinetics.gates[*gate].coeffs();
+                assert!(
+                    *coeff < coeffs.len(),
+                    "gate family changed since the parameter list was built"
+                );
+                coeffs.swap_remove(*coeff).value
+            }
+        }
+    }
+
+    pub fn get(&self, p: &Params) -> Float {
+        *self.field(&mut p.clone())
+    }
+
+    pub fn set(&self, p: &mut Params, x: Float) {
+        *self.field(p) = x;
+    }
+
+    /// Potentials are searched within ±30 mV of `x0`, everything else within a factor of 4
+    /// with its sign kept. `u = 0.5` maps to `x0`.
+    fn unscale(&self, x0: Float, u: Float) -> Float {
+        let is_potential = matches!(
+            self,
+            Param::ENa
+                | Param::EK
+                | Param::EL
+                | Param::Kinetic {
+                    potential: true,
+                    ..
+                }
+        );
+        if is_potential || x0 == 0.0 {
+            x0 + 60.0 * (u - 0.5)
+        } else {
+            x0 * Float::powf(4.0, 2.0 * u - 1.0)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Objective {
+    VoltageRmse,
+    SpikeFeatures,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Method {
+    NelderMead,
+    DifferentialEvolution,
+}
+
+/// Recorded voltage to fit against, already shifted into model time and voltage.
+#[derive(Clone, Default)]
+pub struct Target {
+    pub t: Vec<Float>,
+    pub v: Vec<Float>,
+}
+
+/// upper bound of target samples compared per evaluation
+const MAX_TARGET_POINTS: usize = 1000;
+
+/// residual assigned to each unmatched spike and to a diverged simulation
+const PENALTY: Float = 100.0;
+
+struct Problem {
+    setup: Setup,
+    base: Params,
+    params: Vec<Param>,
+    x0: Vec<Float>,
+    target: Target,
+    objective: Objective,
+}
+
+impl Problem {
+    fn params_at(&self, u: &[Float]) -> Params {
+        let mut p = self.base.clone();
+        for ((param, &x0), &u) in self.params.iter().zip(&self.x0).zip(u) {
+            param.set(&mut p, param.unscale(x0, u));
+        }
+        p
+    }
+
+    fn residuals(&self, p: &Params) -> Vec<Float> {
+        let history = hh::simulate(&self.setup, p);
+        let dt = self.setup.dt;
+        let sim_v = |t: Float| -> Option<Float> {
+            let idx = t / dt;
+            let left = idx.floor() as usize;
+            let (a, b) = (history.get(left)?, history.get(left + 1)?);
+            let frac = idx - left as Float;
+            Some(a.v() * (1.0 - frac) + b.v() * frac)
+        };
+
+        let mut residuals = match self.objective {
+            Objective::VoltageRmse => self
+                .target
+                .t
+                .iter()
+                .zip(&self.target.v)
+                .filter_map(|(&t, &v)| sim_v(t).map(|sim| sim - v))
+                .collect(),
+            Objective::SpikeFeatures => spike_residuals(
+                &history
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| (k as Float * dt, a.v()))
+                    .collect::<Vec<_>>(),
+                &self
+                    .target
+                    .t
+                    .iter()
+                    .copied()
+                    .zip(self.target.v.iter().copied())
+                    .collect::<Vec<_>>(),
+            ),
+        };
+        for r in &mut residuals {
+            if !r.is_finite() {
+                *r = PENALTY;
+            }
+        }
+        residuals
+    }
+
+    fn cost(&self, u: &[Float]) -> Float {
+        rms(&self.residuals(&self.params_at(u)))
+    }
+}
+
+fn rms(residuals: &[Float]) -> Float {
+    if residuals.is_empty() {
+        return PENALTY;
+    }
+    (residuals.iter().map(|r| r * r).sum::<Float>() / residuals.len() as Float).sqrt()
+}
+
+/// Spike count, times and peaks, plus the deepest after-hyperpolarization.
+fn spike_residuals(sim: &[(Float, Float)], target: &[(Float, Float)]) -> Vec<Float> {
+    let sim_spikes = spikes::detect(sim.iter().copied(), spikes::THRESHOLD);
+    let target_spikes = spikes::detect(target.iter().copied(), spikes::THRESHOLD);
+
+    let mut residuals: Vec<Float> = sim_spikes
+        .iter()
+        .zip(&target_spikes)
+        .flat_map(|(s, t)| [s.t - t.t, (s.peak - t.peak) / 10.0])
+        .collect();
+    let unmatched = sim_spikes.len().abs_diff(target_spikes.len());
+    residuals.extend(std::iter::repeat_n(PENALTY, unmatched));
+
+    let trough = |samples: &[(Float, Float)]| {
+        samples
+            .iter()
+            .map(|s| s.1)
+            .fold(Float::INFINITY, Float::min)
+    };
+    residuals.push((trough(sim) - trough(target)) / 5.0);
+    residuals
+}
+
+enum Optimizer {
+    NelderMead(optim::NelderMead),
+    DifferentialEvolution(optim::DifferentialEvolution),
+}
+
+/// A fitted value with the half width of its 95% confidence interval, when the problem is
+/// well conditioned enough to estimate one.
+pub struct Estimate {
+    pub param: Param,
+    pub initial: Float,
+    pub value: Float,
+    pub ci95: Option<Float>,
+}
+
+pub struct Fitter {
+    problem: Problem,
+    optimizer: Optimizer,
+    pub iterations: usize,
+    pub evaluations: usize,
+    /// best cost after each iteration
+    pub progress: Vec<Float>,
+    best_history: Vec<Axon>,
+}
+
+impl Fitter {
+    /// `setup` provides the stimulus; its end is stretched to cover the target.
+    pub fn new(
+        setup: &Setup,
+        base: &Params,
+        params: Vec<Param>,
+        target: Target,
+        objective: Objective,
+        method: Method,
+    ) -> Self {
+        let mut setup = setup.clone();
+        setup.end = target.t.last().copied().unwrap_or(0.0).max(setup.dt * 2.0);
+
+        let stride = (target.t.len() / MAX_TARGET_POINTS).max(1);
+        let target = match objective {
+            Objective::VoltageRmse => Target {
+                t: target.t.iter().step_by(stride).copied().collect(),
+                v: target.v.iter().step_by(stride).copied().collect(),
+            },
+            Objective::SpikeFeatures => target,
+        };
+
+        let problem = Problem {
+            setup,
+            base: base.clone(),
+            x0: params.iter().map(|p| p.get(base)).collect(),
+            params,
+            target,
+            objective,
+        };
+
+        let start = vec![0.5; problem.params.len()];
+        let mut evaluations = 0;
+        let mut f = |u: &[Float]| {
+            evaluations += 1;
+            problem.cost(u)
+        };
+        let optimizer = match method {
+            Method::NelderMead => {
+                Optimizer::NelderMead(optim::NelderMead::new(&start, 0.1, &mut f))
+            }
+            Method::DifferentialEvolution => Optimizer::DifferentialEvolution(
+                optim::DifferentialEvolution::new(&start, 0x5EED, &mut f),
+            ),
+        };
+
+        let mut fitter = Self {
+            problem,
+            optimizer,
+            iterations: 0,
+            evaluations,
+            progress: Vec::new(),
+            best_history: Vec::new(),
+        };
+        fitter.progress.push(fitter.best_cost());
+        fitter.best_history = hh::simulate(&fitter.problem.setup, &fitter.best_params());
+        fitter
+    }
+
+    fn best_unit(&self) -> (&[Float], Float) {
+        match &self.optimizer {
+            Optimizer::NelderMead(nm) => nm.best(),
+            Optimizer::DifferentialEvolution(de) => de.best(),
+        }
+    }
+
+    pub fn best_cost(&self) -> Float {
+        self.best_unit().1
+    }
+
+    pub fn best_params(&self) -> Params {
+        self.problem.params_at(self.best_unit().0)
+    }
+
+    /// simulated trace of the best parameters so far, at the setup's `dt`
+    pub fn best_history(&self) -> &[Axon] {
+        &self.best_history
+    }
+
+    pub fn dt(&self) -> Float {
+        self.problem.setup.dt
+    }
+
+    /// One simulation of the optimizer, plus one of the best parameters when an iteration
+    /// that improved on them completes.
+    pub fn step(&mut self) {
+        let problem = &self.problem;
+        let evaluations = &mut self.evaluations;
+        let mut f = |u: &[Float]| {
+            *evaluations += 1;
+            problem.cost(u)
+        };
+        let completed = match &mut self.optimizer {
+            Optimizer::NelderMead(nm) => nm.step(&mut f),
+            Optimizer::DifferentialEvolution(de) => de.step(&mut f),
+        };
+        if !completed {
+            return;
+        }
+
+        let before = self.progress.last().copied().unwrap_or(Float::INFINITY);
+        self.iterations += 1;
+        self.progress.push(self.best_cost());
+        if self.best_cost() < before {
+            self.best_history = hh::simulate(&self.problem.setup, &self.best_params());
+        }
+    }
+
+    /// Copy the fitted values onto `params`, leaving everything else as it is. Kinetic
+    /// parameters of gates whose family has changed since the fit started are skipped.
+    pub fn apply(&self, params: &mut Params) {
+        let best = self.best_params();
+        let current = Param::all(params);
+        for param in self.problem.params.iter().filter(|p| current.contains(p)) {
+            param.set(params, param.get(&best));
+        }
+    }
+
+    /// Confidence intervals from the Gauss–Newton covariance `s² (JᵀJ)⁻¹` of the residuals
+    /// around the best fit, with `J` taken by central differences.
+    pub fn estimates(&self) -> Vec<Estimate> {
+        let problem = &self.problem;
+        let best = self.best_params();
+        let values: Vec<Float> = problem.params.iter().map(|p| p.get(&best)).collect();
+        let residuals = problem.residuals(&best);
+        let (m, n) = (residuals.len(), values.len());
+
+        let jacobian: Vec<Vec<Float>> = problem
+            .params
+            .iter()
+            .zip(&values)
+            .map(|(param, &x)| {
+                let h = 1e-4 * x.abs().max(1e-2);
+                let mut p = best.clone();
+                param.set(&mut p, x + h);
+                let plus = problem.residuals(&p);
+                param.set(&mut p, x - h);
+                let minus = problem.residuals(&p);
+                (0..m)
+                    .map(|k| match (plus.get(k), minus.get(k)) {
+                        (Some(a), Some(b)) => (a - b) / (2.0 * h),
+                        _ => 0.0,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let covariance = (m > n)
+            .then(|| {
+                let jtj: Vec<Vec<Float>> = (0..n)
+                    .map(|i| {
+                        (0..n)
+                            .map(|j| (0..m).map(|k| jacobian[i][k] * jacobian[j][k]).sum())
+                            .collect()
+                    })
+                    .collect();
+                invert(jtj)
+            })
+            .flatten();
+        let s2 =
+            residuals.iter().map(|r| r * r).sum::<Float>() / (m.saturating_sub(n).max(1)) as Float;
+
+        problem
+            .params
+            .iter()
+            .zip(&problem.x0)
+            .zip(&values)
+            .enumerate()
+            .map(|(i, ((&param, &initial), &value))| Estimate {
+                param,
+                initial,
+                value,
+                ci95: covariance
+                    .as_ref()
+                    .map(|c| 1.96 * (s2 * c[i][i]).sqrt())
+                    .filter(|ci| ci.is_finite()),
+            })
+            .collect()
+    }
+}
+
+/// Gauss–Jordan elimination with partial pivoting. `None` when (numerically) singular.
+fn invert(mut a: Vec<Vec<Float>>) -> Option<Vec<Vec<Float>>> {
+    let n = a.len();
+    let mut inv: Vec<Vec<Float>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let scale = a
+        .iter()
+        .flatten()
+        .fold(0.0, |acc: Float, x| acc.max(x.abs()));
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= scale * 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+
+        let d = a[col][col];
+        for j in 0..n {
+            a[col][j] /= d;
+            inv[col][j] /= d;
+        }
+        for row in 0..n {
+            if row != col {
+                let factor = a[row][col];
+                for j in 0..n {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_conductances() {
+        let setup = Setup {
+            end: 8.0,
+            dt: 0.02,
+            ..Default::default()
+        };
+        let truth = Params::default();
+        let target = {
+            let history = hh::simulate(&setup, &truth);
+            Target {
+                t: (0..history.len()).map(|k| k as Float * setup.dt).collect(),
+                v: history.iter().map(|a| a.v()).collect(),
+            }
+        };
+
+        let mut start = truth.clone();
+        start.g_na *= 1.3;
+        start.g_k *= 0.8;
+        let mut fitter = Fitter::new(
+            &setup,
+            &start,
+            vec![Param::GNa, Param::GK],
+            target,
+            Objective::VoltageRmse,
+            Method::NelderMead,
+        );
+        // the rest of the simplex is left to `step`
+        assert_eq!(fitter.evaluations, 1);
+        while fitter.iterations < 80 {
+            fitter.step();
+        }
+
+        let fitted = fitter.best_params();
+        assert!((fitted.g_na - truth.g_na).abs() < 1.0);
+        assert!((fitted.g_k - truth.g_k).abs() < 0.5);
+        assert!(fitter.estimates().iter().all(|e| e.ci95.is_some()));
+    }
+}
@@ -1,4 +1,28 @@
-use crate::{Float, rate, rk4};
+use crate::{
+    Float, clamp, drug, expr, nmodl, rate,
+    record::{self, Recorder},
+    rk4,
+    rng::Rng,
+    runs,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use std::collections::VecDeque;
+
+#[cfg(target_arch = "wasm32")]
+use crate::FRAME_BUDGET;
+
+/// variables of `Axon`
+pub const STATES: usize = 7;
 
 pub mod consts {
     use super::Float;
@@ -12,49 +36,113 @@ pub mod consts {
     pub const G_L_MAX: Float = 0.3;
 
     pub const C_M: Float = 1.0;
+
+    /// °C, the temperature of Hodgkin and Huxley's recordings
+    pub const TEMPERATURE: Float = 6.3;
 }
 
-pub fn tau_m(v: Float) -> Float {
-    1.0 / (rate::alpha_m(v) + rate::beta_m(v))
+#[derive(Clone, PartialEq)]
+pub struct Pulse {
+    pub start: Float,
+    pub end: Float,
+    pub magnitude: Float,
 }
 
-pub fn m_inf(v: Float) -> Float {
-    rate::alpha_m(v) / (rate::alpha_m(v) + rate::beta_m(v))
+impl Pulse {
+    pub fn current(&self, t: Float) -> Float {
+        if t >= self.start && t < self.end {
+            self.magnitude
+        } else {
+            0.0
+        }
+    }
 }
 
-pub fn tau_h(v: Float) -> Float {
-    1.0 / (rate::alpha_h(v) + rate::beta_h(v))
+/// Piecewise linear current waveform, e.g. a recorded stimulus protocol. `t` must be ascending.
+#[derive(Clone, PartialEq, Default)]
+pub struct Waveform {
+    pub t: Vec<Float>,
+    pub i: Vec<Float>,
 }
 
-pub fn h_inf(v: Float) -> Float {
-    rate::alpha_h(v) / (rate::alpha_h(v) + rate::beta_h(v))
+impl Waveform {
+    /// zero outside the recorded range
+    pub fn current(&self, t: Float) -> Float {
+        let right = self.t.partition_point(|&x| x <= t);
+        if right == 0 || right == self.t.len() {
+            return 0.0;
+        }
+        let left = right - 1;
+        let frac = (t - self.t[left]) / (self.t[right] - self.t[left]);
+        self.i[left] * (1.0 - frac) + self.i[right] * frac
+    }
 }
 
-pub fn tau_n(v: Float) -> Float {
-    1.0 / (rate::alpha_n(v) + rate::beta_n(v))
+/// Gaussian white noise current around `mean`, drawing a new value every `interval` ms. The
+/// same seed gives the same noise.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Noise {
+    pub mean: Float,
+    pub sd: Float,
+    pub interval: Float,
+    pub seed: u64,
 }
 
-pub fn n_inf(v: Float) -> Float {
-    rate::alpha_n(v) / (rate::alpha_n(v) + rate::beta_n(v))
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            mean: 0.0,
+            sd: 2.0,
+            interval: 0.1,
+            seed: 1,
+        }
+    }
 }
 
-pub struct Pulse {
-    pub start: Float,
-    pub end: Float,
-    pub magnitude: Float,
+impl Noise {
+    pub fn current(&self, t: Float) -> Float {
+        let bin = (t / self.interval).floor().max(0.0) as u64;
+        self.mean + self.sd * Rng::stream(self.seed, bin).normal()
+    }
 }
+
+#[derive(Clone, PartialEq)]
 pub struct Setup {
     pub v0: Float,
     pub end: Float,
     pub dt: Float,
-    pub steps_per_frame: usize,
     pub pulse: Pulse,
+    /// when set, replaces `pulse` as the injected current
+    pub replay: Option<Waveform>,
+    /// added to the pulse or replayed current
+    pub noise: Option<Noise>,
+    /// virtual conductance injecting current on top of the stimulus, following the state
+    pub clamp: Option<clamp::Clamp>,
 }
 
 impl Setup {
     pub fn total_steps(&self) -> usize {
         (self.end / self.dt).floor() as usize
     }
+
+    /// prescribed current at time `t`
+    pub fn current(&self, t: Float) -> Float {
+        let noise = self.noise.as_ref().map_or(0.0, |n| n.current(t));
+        noise
+            + match &self.replay {
+                Some(waveform) => waveform.current(t),
+                None => self.pulse.current(t),
+            }
+    }
+
+    /// everything injected at time `t` into a cell in state `axon`, including the dynamic clamp
+    pub fn stimulus(&self, params: &Params, t: Float, axon: &Axon) -> Float {
+        let clamp = self
+            .clamp
+            .as_ref()
+            .map_or(0.0, |c| c.current(params, t, axon));
+        self.current(t) + clamp
+    }
 }
 
 impl Default for Setup {
@@ -63,23 +151,181 @@ impl Default for Setup {
             v0: 0.0,
             end: 10.0,
             dt: 0.01,
-            steps_per_frame: 1000,
             pulse: Pulse {
                 start: 0.0,
                 end: 1.0,
                 magnitude: 10.0,
             },
+            replay: None,
+            noise: None,
+            clamp: None,
+        }
+    }
+}
+
+/// Membrane constants and gating kinetics. `Default` is the original squid giant axon.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Params {
+    pub g_na: Float,
+    pub g_k: Float,
+    pub g_l: Float,
+    pub e_na: Float,
+    pub e_k: Float,
+    pub e_l: Float,
+    pub c_m: Float,
+    /// °C
+    pub temperature: Float,
+    pub kinetics: rate::Kinetics,
+    /// replaces the built-in equations when set; the membrane constants and kinetics above are
+    /// then unused by the integrator
+    pub custom: Option<expr::Model>,
+    /// channel mechanism compiled from NMODL, replacing the built-in ionic currents when set
+    pub mechanism: Option<nmodl::Mechanism>,
+    /// drugs blocking the built-in sodium and potassium conductances
+    pub drugs: Vec<drug::Application>,
+    /// mutant channels alongside the wild type of `kinetics`
+    pub mutation: rate::Mutation,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            g_na: consts::G_NA_MAX,
+            g_k: consts::G_K_MAX,
+            g_l: consts::G_L_MAX,
+            e_na: consts::E_NA,
+            e_k: consts::E_K,
+            e_l: consts::E_L,
+            c_m: consts::C_M,
+            temperature: consts::TEMPERATURE,
+            kinetics: rate::Kinetics::default(),
+            custom: None,
+            mechanism: None,
+            drugs: Vec::new(),
+            mutation: rate::Mutation::default(),
+        }
+    }
+}
+
+impl Params {
+    /// Q10 rate factor of gate `gate` at the current temperature
+    pub fn phi(&self, gate: usize) -> Float {
+        self.kinetics.q10[gate].phi(self.temperature)
+    }
+
+    pub fn tau_m(&self, v: Float) -> Float {
+        self.kinetics.m().tau(v) / self.phi(0)
+    }
+
+    pub fn m_inf(&self, v: Float) -> Float {
+        self.kinetics.m().inf(v)
+    }
+
+    pub fn tau_h(&self, v: Float) -> Float {
+        self.kinetics.h().tau(v) / self.phi(1)
+    }
+
+    pub fn h_inf(&self, v: Float) -> Float {
+        self.kinetics.h().inf(v)
+    }
+
+    pub fn tau_n(&self, v: Float) -> Float {
+        self.kinetics.n().tau(v) / self.phi(2)
+    }
+
+    pub fn n_inf(&self, v: Float) -> Float {
+        self.kinetics.n().inf(v)
+    }
+
+    /// τ of the mutant copy of gate `gate`
+    pub fn mutant_tau(&self, gate: usize, v: Float) -> Float {
+        let modifier = &self.mutation.modifiers[gate];
+        modifier.tau(&self.kinetics.gates[gate], v) / self.phi(gate)
+    }
+
+    /// x_∞ of the mutant copy of gate `gate`
+    pub fn mutant_inf(&self, gate: usize, v: Float) -> Float {
+        self.mutation.modifiers[gate].inf(&self.kinetics.gates[gate], v)
+    }
+
+    /// clamped at `v` long enough for every gate to settle, or the initial values of a custom
+    /// model or mechanism
+    pub fn steady_state(&self, v: Float) -> Axon {
+        if let Some(model) = &self.custom {
+            return Axon::padded(model.initial(v));
+        }
+        if let Some(mechanism) = &self.mechanism {
+            return Axon::padded(mechanism.initial(self, v));
+        }
+        Axon {
+            data: [
+                v,
+                self.m_inf(v),
+                self.h_inf(v),
+                self.n_inf(v),
+                self.mutant_inf(0, v),
+                self.mutant_inf(1, v),
+                self.mutant_inf(2, v),
+            ],
+        }
+    }
+
+    /// time derivative of `state` at time `t` under injected current `i`
+    pub fn derivative(
+        &self,
+        state: &[Float; STATES],
+        t: Float,
+        i: Float,
+        d_state: &mut [Float; STATES],
+    ) {
+        if self.custom.is_some() || self.mechanism.is_some() {
+            *d_state = [0.0; STATES];
+            let (state, d_state) = (
+                state.first_chunk().expect("longer"),
+                d_state.first_chunk_mut().expect("longer"),
+            );
+            match (&self.custom, &self.mechanism) {
+                (Some(model), _) => model.derivative(state, t, i, d_state),
+                (None, Some(mechanism)) => mechanism.derivative(self, state, t, i, d_state),
+                (None, None) => unreachable!(),
+            }
+            return;
+        }
+
+        let axon = Axon { data: *state };
+        let v = axon.v();
+        d_state[0] = (i - axon.i_ion(self, t)) / self.c_m;
+        d_state[1] = (-axon.m() + axon.m_inf(self)) / self.tau_m(v);
+        d_state[2] = (-axon.h() + axon.h_inf(self)) / self.tau_h(v);
+        d_state[3] = (-axon.n() + axon.n_inf(self)) / self.tau_n(v);
+        for gate in 0..3 {
+            d_state[4 + gate] = if self.mutation.fraction > 0.0 {
+                (-axon.data[4 + gate] + self.mutant_inf(gate, v)) / self.mutant_tau(gate, v)
+            } else {
+                0.0
+            };
         }
     }
 }
 
 #[derive(Clone, Copy, Default)]
 pub struct Axon {
-    /// V, m, h, n
-    data: [Float; 4],
+    /// V, m, h, n, then m, h and n of the mutant channels
+    data: [Float; STATES],
 }
 
 impl Axon {
+    /// the state of a custom model or mechanism, which leaves the mutant gates alone
+    fn padded(state: [Float; 4]) -> Self {
+        let mut data = [0.0; STATES];
+        data[..4].copy_from_slice(&state);
+        Self { data }
+    }
+
+    pub fn state(&self) -> [Float; STATES] {
+        self.data
+    }
+
     pub fn v(&self) -> Float {
         self.data[0]
     }
@@ -93,105 +339,816 @@ impl Axon {
         self.data[3]
     }
 
-    pub fn cond_na(&self) -> Float {
-        consts::G_NA_MAX * self.m().powi(3) * self.h()
+    /// at time `t`, which matters only while drugs are applied
+    pub fn cond_na(&self, p: &Params, t: Float) -> Float {
+        let [unblocked, _] = drug::unblocked(&p.drugs, t);
+        let [wild, mutant] = [1, 4].map(|k| self.data[k].powi(3) * self.data[k + 1]);
+        let f = p.mutation.fraction;
+        unblocked * p.g_na * ((1.0 - f) * wild + f * mutant)
+    }
+
+    pub fn cond_k(&self, p: &Params, t: Float) -> Float {
+        let [_, unblocked] = drug::unblocked(&p.drugs, t);
+        let [wild, mutant] = [3, 6].map(|k| self.data[k].powi(4));
+        let f = p.mutation.fraction;
+        unblocked * p.g_k * ((1.0 - f) * wild + f * mutant)
+    }
+
+    pub fn i_na(&self, p: &Params, t: Float) -> Float {
+        self.cond_na(p, t) * (self.v() - p.e_na)
+    }
+
+    pub fn i_k(&self, p: &Params, t: Float) -> Float {
+        self.cond_k(p, t) * (self.v() - p.e_k)
+    }
+
+    pub fn i_l(&self, p: &Params) -> Float {
+        p.g_l * (self.v() - p.e_l)
+    }
+
+    /// outward current through all the channels of the built-in equations
+    pub fn i_ion(&self, p: &Params, t: Float) -> Float {
+        self.i_na(p, t) + self.i_k(p, t) + self.i_l(p)
+    }
+
+    /// `C dV/dt` under the injected `stimulus`, from whichever equations are simulated
+    pub fn i_cap(&self, p: &Params, t: Float, stimulus: Float) -> Float {
+        let mut d_state = [0.0; STATES];
+        p.derivative(&self.data, t, stimulus, &mut d_state);
+        p.c_m * d_state[0]
+    }
+
+    pub fn m_inf(&self, p: &Params) -> Float {
+        p.m_inf(self.v())
+    }
+
+    pub fn h_inf(&self, p: &Params) -> Float {
+        p.h_inf(self.v())
+    }
+
+    pub fn n_inf(&self, p: &Params) -> Float {
+        p.n_inf(self.v())
     }
+}
+
+/// integrate one `dt` from `axon`, the state at time `t`, under the injected `current` at each
+/// time and state
+pub fn integrate(
+    params: &Params,
+    axon: Axon,
+    t: Float,
+    dt: Float,
+    current: impl Fn(Float, &Axon) -> Float,
+) -> Axon {
+    let system = |state: &[Float; STATES], t: Float, d_state: &mut [Float; STATES]| {
+        let i = current(t, &Axon { data: *state });
+        params.derivative(state, t, i, d_state);
+    };
 
-    pub fn cond_k(&self) -> Float {
-        consts::G_K_MAX * self.n().powi(4)
+    Axon {
+        data: rk4::step(system, axon.data, t, dt),
     }
+}
+
+/// integrate `axons`, the states at time `t`, one `dt` together, each under the injected
+/// current at its index of `currents` at each time and states, which may couple them
+pub fn integrate_coupled(
+    params: &Params,
+    axons: &[Axon],
+    t: Float,
+    dt: Float,
+    currents: impl Fn(Float, &[Axon]) -> Vec<Float>,
+) -> Vec<Axon> {
+    let unpack = |state: &[Float]| -> Vec<Axon> {
+        state
+            .chunks_exact(STATES)
+            .map(|data| Axon {
+                data: data.try_into().expect("STATES long"),
+            })
+            .collect()
+    };
+    let system = |state: &[Float], t: Float, d_state: &mut [Float]| {
+        let axons = unpack(state);
+        let currents = currents(t, &axons);
+        for ((axon, i), d_state) in axons
+            .iter()
+            .zip(currents)
+            .zip(d_state.chunks_exact_mut(STATES))
+        {
+            params.derivative(&axon.data, t, i, d_state.try_into().expect("STATES long"));
+        }
+    };
+
+    let state: Vec<Float> = axons.iter().flat_map(|axon| axon.data).collect();
+    unpack(&rk4::step_slice(system, &state, t, dt))
+}
+
+/// integrate one `setup.dt` from `axon`, the state at time `step * setup.dt`
+fn advance(setup: &Setup, params: &Params, axon: Axon, step: usize) -> Axon {
+    integrate(params, axon, setup.dt * step as Float, setup.dt, |t, a| {
+        setup.stimulus(params, t, a)
+    })
+}
+
+/// Run a whole simulation at once, without the per-frame budget of `State`.
+pub fn simulate(setup: &Setup, params: &Params) -> Vec<Axon> {
+    simulate_with(setup, params, |t, a| setup.stimulus(params, t, a))
+}
 
-    pub fn i_na(&self) -> Float {
-        self.cond_na() * (self.v() - consts::E_NA)
+/// `simulate` injecting `current` instead of the stimulus of `setup`
+pub fn simulate_with(
+    setup: &Setup,
+    params: &Params,
+    current: impl Fn(Float, &Axon) -> Float,
+) -> Vec<Axon> {
+    let total = setup.total_steps();
+    let mut history = Vec::with_capacity(total);
+    if total == 0 {
+        return history;
     }
 
-    pub fn i_k(&self) -> Float {
-        self.cond_k() * (self.v() - consts::E_K)
+    history.push(params.steady_state(setup.v0));
+    for step in 1..total {
+        let t = setup.dt * (step - 1) as Float;
+        history.push(integrate(params, history[step - 1], t, setup.dt, &current));
     }
+    history
+}
 
-    pub fn m_inf(&self) -> Float {
-        m_inf(self.v())
+/// Worst violation of the current balance over a run, a sign of a step too coarse for the
+/// dynamics or of a diverging integration.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Balance {
+    /// µA/cm²
+    pub worst: Float,
+    /// ms, the end of the step where it happened
+    pub at: Float,
+}
+
+impl Balance {
+    /// Account for the step of `setup.dt` from `before` to `after` at step `step - 1`, comparing
+    /// `C ΔV/Δt` to the currents averaged over the step: the prescribed current with the
+    /// weights of RK4 and the others by the trapezoidal rule, which resolves them to O(dt²).
+    fn check(&mut self, setup: &Setup, params: &Params, before: &Axon, after: &Axon, step: usize) {
+        if params.custom.is_some() || params.mechanism.is_some() {
+            return;
+        }
+        let (dt, t0) = (setup.dt, setup.dt * (step - 1) as Float);
+        let t1 = t0 + dt;
+        let prescribed =
+            (setup.current(t0) + 4.0 * setup.current(t0 + 0.5 * dt) + setup.current(t1)) / 6.0;
+        let other = |t: Float, axon: &Axon| {
+            setup.stimulus(params, t, axon) - setup.current(t) - axon.i_ion(params, t)
+        };
+        let net = prescribed + 0.5 * (other(t0, before) + other(t1, after));
+        let residual = (params.c_m * (after.v() - before.v()) / dt - net).abs();
+        // keep the first step that blew up
+        if !self.worst.is_nan() && (residual.is_nan() || residual > self.worst) {
+            *self = Self {
+                worst: residual,
+                at: t1,
+            };
+        }
     }
+}
 
-    pub fn h_inf(&self) -> Float {
-        h_inf(self.v())
+/// Steps a worker integrates before reporting back to the UI.
+#[cfg(not(target_arch = "wasm32"))]
+const CHUNK_STEPS: usize = 512;
+
+/// Progress of a worker: the samples due for recording since the previous chunk.
+#[cfg(not(target_arch = "wasm32"))]
+struct Chunk {
+    /// step and state
+    samples: Vec<(usize, Axon)>,
+    /// steps done, counting the initial state
+    done: usize,
+    last: Axon,
+    balance: Balance,
+}
+
+/// Integrates a run on its own thread, sending chunks of progress back. Dropping it stops the
+/// thread.
+#[cfg(not(target_arch = "wasm32"))]
+struct Worker {
+    chunks: mpsc::Receiver<Chunk>,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Worker {
+    /// continue from `axon`, the state at step `first - 1`, up to the end of `setup`, keeping
+    /// every `stride`th step
+    fn spawn(setup: Setup, params: Params, mut axon: Axon, first: usize, stride: usize) -> Self {
+        let (sender, chunks) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let shared = cancelled.clone();
+        thread::spawn(move || {
+            let mut samples = Vec::new();
+            let mut balance = Balance::default();
+            let total = setup.total_steps();
+            for step in first..total {
+                if shared.load(Ordering::Relaxed) {
+                    return;
+                }
+                let before = axon;
+                axon = advance(&setup, &params, axon, step - 1);
+                balance.check(&setup, &params, &before, &axon, step);
+                if step.is_multiple_of(stride) {
+                    samples.push((step, axon));
+                }
+                if (step + 1).is_multiple_of(CHUNK_STEPS) || step + 1 == total {
+                    let chunk = Chunk {
+                        samples: std::mem::take(&mut samples),
+                        done: step + 1,
+                        last: axon,
+                        balance: std::mem::take(&mut balance),
+                    };
+                    if sender.send(chunk).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Self { chunks, cancelled }
     }
+}
 
-    pub fn n_inf(&self) -> Float {
-        n_inf(self.v())
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Wall-clock seconds a live frame may catch up on, so a stalled window does not trigger a
+/// burst of integration.
+const LIVE_MAX_FRAME: f64 = 0.1;
+
+#[derive(Clone, Copy)]
+pub struct LiveSample {
+    pub t: Float,
+    pub axon: Axon,
+    /// injected current
+    pub i: Float,
+}
+
+/// Free-running integration at a fixed pace relative to wall-clock time, like an oscilloscope.
+/// Only the last `window` ms are kept.
+pub struct Live {
+    /// simulated ms per wall-clock second
+    pub speed: Float,
+    /// ms of history kept in `samples`
+    pub window: Float,
+    /// current injected all the time
+    pub baseline: Float,
+    /// added to `baseline` while `held`
+    pub pulse: Float,
+    pub held: bool,
+    t: Float,
+    /// simulated time the clock has reached
+    due: Float,
+    axon: Axon,
+    /// wall-clock seconds of the previous `run`
+    last_wall: Option<f64>,
+    pub samples: VecDeque<LiveSample>,
+}
+
+impl Live {
+    pub fn new(axon: Axon) -> Self {
+        Self {
+            speed: 10.0,
+            window: 50.0,
+            baseline: 0.0,
+            pulse: 10.0,
+            held: false,
+            t: 0.0,
+            due: 0.0,
+            axon,
+            last_wall: None,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn current(&self) -> Float {
+        self.baseline + if self.held { self.pulse } else { 0.0 }
+    }
+
+    /// simulated time of the newest sample
+    pub fn time(&self) -> Float {
+        self.t
+    }
+
+    /// Integrate in steps of `dt` up to where the clock says the trace should be at wall-clock
+    /// time `now` in seconds, using the parameters as they are now.
+    pub fn run(&mut self, params: &Params, dt: Float, now: f64) {
+        let elapsed = match self.last_wall {
+            Some(last) => (now - last).clamp(0.0, LIVE_MAX_FRAME),
+            None => 0.0,
+        };
+        self.last_wall = Some(now);
+
+        let i = self.current();
+        // the remainder short of a whole step carries over to the next frame
+        self.due += elapsed * self.speed;
+        while self.t + dt <= self.due + 1e-9 {
+            self.axon = integrate(params, self.axon, self.t, dt, |_, _| i);
+            self.t += dt;
+            self.samples.push_back(LiveSample {
+                t: self.t,
+                axon: self.axon,
+                i,
+            });
+        }
+        while self
+            .samples
+            .front()
+            .is_some_and(|s| s.t < self.t - self.window)
+        {
+            self.samples.pop_front();
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum RunState {
+    #[default]
+    Idle,
+    Running,
+    Paused,
+    Finished,
+    Cancelled,
+}
+
+impl RunState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RunState::Idle => "idle",
+            RunState::Running => "running",
+            RunState::Paused => "paused",
+            RunState::Finished => "finished",
+            RunState::Cancelled => "cancelled",
+        }
     }
 }
 
 #[derive(Default)]
 pub struct State {
     pub setup: Setup,
-    simulating: bool,
-    pub points_avail: usize,
-    pub history: Vec<Axon>,
+    pub params: Params,
+    /// what the next run records
+    pub record: record::Config,
+    pub recorder: Recorder,
+    /// earlier recordings, each joining when the next run starts
+    pub runs: runs::Runs,
+    /// what `recorder` was made with
+    run_setup: Setup,
+    run_params: Params,
+    run: RunState,
+    /// recording stride of the run
+    stride: usize,
+    /// integration steps done, counting the initial state
+    pub steps_done: usize,
+    /// state after the last step done
+    last: Axon,
+    /// of the steps done so far
+    pub balance: Balance,
+    #[cfg(not(target_arch = "wasm32"))]
+    worker: Option<Worker>,
+    /// free-running mode, exclusive with a run
+    pub live: Option<Live>,
 }
 
 impl State {
+    pub fn run_state(&self) -> RunState {
+        self.run
+    }
+
+    /// parameters `recorder` was simulated with, which may since have been edited
+    pub fn run_params(&self) -> &Params {
+        &self.run_params
+    }
+
+    /// running or paused, i.e. the setup and parameters are in use
     pub fn simulating(&self) -> bool {
-        self.simulating
+        matches!(self.run, RunState::Running | RunState::Paused)
     }
 
-    pub fn init(&mut self) {
+    /// Start a run recording as `record` says. Fails if the recording cannot be set up.
+    pub fn init(&mut self) -> Result<(), String> {
         if self.setup.total_steps() == 0 {
+            return Ok(());
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.worker = None;
+        }
+        self.live = None;
+        let recorder = Recorder::new(self.record.clone())?;
+        let previous = std::mem::replace(&mut self.recorder, recorder);
+        if previous.recorded > 0 {
+            let (setup, params) = (self.run_setup.clone(), self.run_params.clone());
+            self.runs.add(previous, setup, params);
+        }
+        self.run_setup = self.setup.clone();
+        self.run_params = self.params.clone();
+        self.stride = self.record.stride(self.setup.dt);
+        self.last = self.params.steady_state(self.setup.v0);
+        self.steps_done = 1;
+        self.balance = Balance::default();
+        self.recorder.push(
+            0.0,
+            &self.last,
+            &self.params,
+            self.setup.stimulus(&self.params, 0.0, &self.last),
+        );
+        self.resume();
+        Ok(())
+    }
+
+    /// continue a paused run, or start integrating after `init` or `extend`
+    fn resume(&mut self) {
+        self.run = RunState::Running;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.worker = Some(Worker::spawn(
+                self.setup.clone(),
+                self.params.clone(),
+                self.last,
+                self.steps_done,
+                self.stride,
+            ));
+        }
+        self.finish_if_done();
+    }
+
+    pub fn pause(&mut self) {
+        if self.run != RunState::Running {
             return;
         }
+        // whatever the worker computed beyond what it sent is recomputed on resume
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.step();
+            self.worker = None;
+        }
+        if self.run == RunState::Running {
+            self.run = RunState::Paused;
+            self.recorder.flush();
+        }
+    }
 
-        self.history
-            .resize(self.setup.total_steps(), Axon::default());
-        self.history[0] = Axon {
-            data: [
-                self.setup.v0,
-                m_inf(self.setup.v0),
-                h_inf(self.setup.v0),
-                n_inf(self.setup.v0),
-            ],
-        };
-        self.points_avail = 1;
-        self.simulating = true;
+    pub fn unpause(&mut self) {
+        if self.run == RunState::Paused {
+            self.resume();
+        }
+    }
+
+    /// integrate the step after `last`, recording it if due
+    fn advance_one(&mut self) {
+        let step = self.steps_done;
+        let before = self.last;
+        self.last = advance(&self.setup, &self.params, self.last, step - 1);
+        let (setup, params) = (&self.setup, &self.params);
+        self.balance.check(setup, params, &before, &self.last, step);
+        self.steps_done += 1;
+        if step.is_multiple_of(self.stride) {
+            self.record(step, self.last);
+        }
+    }
+
+    fn record(&mut self, step: usize, axon: Axon) {
+        let t = step as Float * self.setup.dt;
+        let stimulus = self.setup.stimulus(&self.params, t, &axon);
+        self.recorder.push(t, &axon, &self.params, stimulus);
+    }
+
+    /// advance a paused run by a single `setup.dt`
+    pub fn single_step(&mut self) {
+        if self.run != RunState::Paused {
+            return;
+        }
+        self.advance_one();
+        self.finish_if_done();
+    }
+
+    /// stop the run, keeping what has been recorded so far
+    pub fn cancel(&mut self) {
+        if !self.simulating() {
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.step();
+            self.worker = None;
+        }
+        if self.simulating() {
+            self.run = RunState::Cancelled;
+            self.recorder.flush();
+        }
+    }
+
+    /// switch to free-running mode from the steady state at `setup.v0`, ending any run
+    pub fn start_live(&mut self) {
+        self.cancel();
+        self.live = Some(Live::new(self.params.steady_state(self.setup.v0)));
+    }
+
+    /// the current recording or a kept one
+    pub fn recording(&self, pick: runs::Pick) -> Option<&Recorder> {
+        match pick {
+            runs::Pick::Current => Some(&self.recorder),
+            runs::Pick::Kept(idx) => self.runs.list.get(idx).map(|r| &r.recorder),
+        }
     }
 
-    /// do nothing if not already simulating
+    /// Whether `extend` can pick up from the last computed state: only when nothing but the
+    /// end has been edited since the run started, so that the whole run is simulated with the
+    /// setup and parameters it is kept under.
+    pub fn can_extend(&self) -> bool {
+        let mut setup = self.setup.clone();
+        setup.end = self.run_setup.end;
+        matches!(self.run, RunState::Finished | RunState::Cancelled)
+            && setup == self.run_setup
+            && self.params == self.run_params
+    }
+
+    /// Move `setup.end` by `by` and keep integrating from the last computed state, instead of
+    /// starting over.
+    pub fn extend(&mut self, by: Float) {
+        if !self.can_extend() {
+            return;
+        }
+        self.setup.end += by;
+        self.run_setup.end = self.setup.end;
+        self.resume();
+    }
+
+    fn finish_if_done(&mut self) {
+        if self.steps_done >= self.setup.total_steps() {
+            self.run = RunState::Finished;
+            self.recorder.flush();
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                self.worker = None;
+            }
+        }
+    }
+
+    /// do nothing if not running
     ///
-    /// continue simulating for `steps`. if upper limit met, break early and end the simulation
+    /// collect whatever the worker has finished since the last call, without waiting for it
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn step(&mut self) {
-        if !self.simulating {
+        loop {
+            let Some(worker) = &self.worker else {
+                return;
+            };
+            match worker.chunks.try_recv() {
+                Ok(chunk) => {
+                    for (step, axon) in chunk.samples {
+                        self.record(step, axon);
+                    }
+                    self.steps_done = chunk.done;
+                    self.last = chunk.last;
+                    let worst = chunk.balance.worst;
+                    if !self.balance.worst.is_nan()
+                        && (worst.is_nan() || worst > self.balance.worst)
+                    {
+                        self.balance = chunk.balance;
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.finish_if_done();
+                    // the worker is gone without finishing, e.g. it panicked
+                    if self.run == RunState::Running {
+                        self.run = RunState::Cancelled;
+                        self.worker = None;
+                    }
+                    return;
+                }
+            }
+        }
+        self.finish_if_done();
+    }
+
+    /// do nothing if not running
+    ///
+    /// simulate for `FRAME_BUDGET` seconds, as there is no worker thread on wasm. if upper limit
+    /// met, end the simulation
+    #[cfg(target_arch = "wasm32")]
+    pub fn step(&mut self) {
+        if self.run != RunState::Running {
             return;
         }
 
-        for _ in 0..self.setup.steps_per_frame {
-            if self.points_avail == self.setup.total_steps() {
-                self.simulating = false;
-                return;
+        let start = miniquad::date::now();
+        while miniquad::date::now() - start < FRAME_BUDGET {
+            // check the clock only every few steps
+            for _ in 0..64 {
+                if self.steps_done >= self.setup.total_steps() {
+                    self.finish_if_done();
+                    return;
+                }
+                self.advance_one();
             }
+        }
+    }
+}
 
-            let system = |state: &[Float; 4], t: Float, d_state: &mut [Float; 4]| {
-                let i = if t >= self.setup.pulse.start && t < self.setup.pulse.end {
-                    self.setup.pulse.magnitude
-                } else {
-                    0.0
-                };
-
-                let axon = Axon { data: *state };
-                d_state[0] =
-                    (-axon.i_na() - axon.i_k() + consts::G_L_MAX * (consts::E_L - axon.v()) + i)
-                        / consts::C_M;
-                d_state[1] = (-axon.m() + axon.m_inf()) / tau_m(axon.v());
-                d_state[2] = (-axon.h() + axon.h_inf()) / tau_h(axon.v());
-                d_state[3] = (-axon.n() + axon.n_inf()) / tau_n(axon.v());
-            };
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
 
-            let new_state = rk4::step(
-                system,
-                self.history[self.points_avail - 1].data,
-                self.setup.dt * self.points_avail as Float,
-                self.setup.dt,
-            );
-            self.history[self.points_avail] = Axon { data: new_state };
-            self.points_avail += 1;
+    /// poll like the UI does until the run is over
+    fn wait(state: &mut State) {
+        while state.simulating() {
+            state.step();
+            thread::sleep(Duration::from_millis(1));
         }
     }
+
+    fn assert_matches_simulate(state: &State) {
+        let expected = simulate(&state.setup, &state.params);
+        assert_eq!(state.steps_done, expected.len());
+        let recorded = state.recorder.column(record::Var::V).unwrap();
+        assert_eq!(recorded.len(), expected.len());
+        for (e, a) in expected.iter().zip(recorded) {
+            assert_eq!(e.v(), *a);
+        }
+    }
+
+    #[test]
+    fn worker_matches_simulate() {
+        let mut state = State::default();
+        state.init().unwrap();
+        wait(&mut state);
+        assert_eq!(state.run_state(), RunState::Finished);
+        assert_matches_simulate(&state);
+    }
+
+    #[test]
+    fn pause_step_resume() {
+        let mut state = State::default();
+        state.setup.end = 50.0;
+        state.init().unwrap();
+        state.pause();
+        assert_eq!(state.run_state(), RunState::Paused);
+        let reached = state.steps_done;
+        thread::sleep(Duration::from_millis(10));
+        state.step();
+        assert_eq!(state.steps_done, reached);
+
+        state.single_step();
+        assert_eq!(state.steps_done, reached + 1);
+        state.unpause();
+        wait(&mut state);
+        assert_matches_simulate(&state);
+    }
+
+    #[test]
+    fn records_every_interval() {
+        let mut state = State {
+            record: record::Config {
+                vars: vec![record::Var::M],
+                interval: 0.1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        state.init().unwrap();
+        wait(&mut state);
+        let expected = simulate(&state.setup, &state.params);
+        let recorded = state.recorder.column(record::Var::M).unwrap();
+        assert_eq!(recorded.len(), expected.len().div_ceil(10));
+        for (k, m) in recorded.iter().enumerate() {
+            assert_eq!(expected[k * 10].m(), *m);
+            assert!((state.recorder.t[k] - k as Float * 0.1).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn live_keeps_pace_and_window() {
+        let mut state = State::default();
+        state.start_live();
+        let live = state.live.as_mut().unwrap();
+        live.speed = 20.0;
+        live.window = 5.0;
+        live.held = true;
+
+        let mut peak: Float = 0.0;
+        for frame in 0..=100 {
+            live.run(&state.params, 0.01, frame as f64 * 0.02);
+            peak = live.samples.iter().fold(peak, |p, s| p.max(s.axon.v()));
+        }
+        // 2 wall-clock seconds at 20 ms/s
+        assert!((live.time() - 40.0).abs() < 0.011);
+        let oldest = live.samples.front().unwrap().t;
+        assert!(live.time() - oldest <= 5.0 && live.time() - oldest > 4.9);
+        assert!(live.samples.iter().all(|s| s.i == 10.0));
+        // the held current makes it fire
+        assert!(peak > 50.0);
+    }
+
+    #[test]
+    fn cancel_and_extend() {
+        let mut state = State::default();
+        state.setup.end = 200.0;
+        state.init().unwrap();
+        state.cancel();
+        assert_eq!(state.run_state(), RunState::Cancelled);
+        assert!(state.steps_done < state.setup.total_steps());
+
+        // picks up where the cancelled run stopped
+        state.extend(5.0);
+        wait(&mut state);
+        assert_eq!(state.setup.end, 205.0);
+        assert_matches_simulate(&state);
+
+        // but not under parameters the run was not simulated with
+        state.params.g_k *= 2.0;
+        assert!(!state.can_extend());
+        state.params.g_k /= 2.0;
+        assert!(state.can_extend());
+
+        // the next run keeps this one for comparison
+        let recorded = state.recorder.recorded;
+        state.init().unwrap();
+        assert_eq!(state.runs.list.len(), 1);
+        assert_eq!(state.runs.list[0].setup.end, 205.0);
+        assert_eq!(state.runs.list[0].recorder.recorded, recorded);
+        state.cancel();
+    }
+
+    #[test]
+    fn mutant_mixture() {
+        let wild = Params::default();
+        let v = |params: &Params| -> Vec<Float> {
+            simulate(&Setup::default(), params)
+                .iter()
+                .map(Axon::v)
+                .collect()
+        };
+        // unmodified mutants gate like the wild type
+        let all_mutant = Params {
+            mutation: rate::Mutation {
+                fraction: 1.0,
+                ..Default::default()
+            },
+            ..wild.clone()
+        };
+        for (a, b) in v(&wild).iter().zip(v(&all_mutant)) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        // half the sodium channels activating 10 mV earlier lower the threshold
+        let mut setup = Setup::default();
+        setup.pulse.magnitude = 4.0;
+        let mut gain = Params {
+            mutation: rate::Mutation {
+                fraction: 0.5,
+                ..Default::default()
+            },
+            ..wild.clone()
+        };
+        gain.mutation.modifiers[0].shift = -10.0;
+        let peak = |params: &Params| {
+            simulate(&setup, params)
+                .iter()
+                .map(Axon::v)
+                .fold(Float::NEG_INFINITY, Float::max)
+        };
+        assert!(peak(&wild) < 20.0);
+        assert!(peak(&gain) > 50.0);
+    }
+
+    #[test]
+    fn currents_balance() {
+        let setup = Setup {
+            end: 20.0,
+            ..Default::default()
+        };
+        // the diagnostic grows with the step
+        let worst = |dt: Float| {
+            let mut state = State {
+                setup: Setup {
+                    dt,
+                    ..setup.clone()
+                },
+                ..Default::default()
+            };
+            state.init().unwrap();
+            wait(&mut state);
+            state.balance
+        };
+        let (fine, coarse) = (worst(0.01), worst(0.05));
+        assert!(fine.worst > 0.0 && fine.worst < 1.0);
+        assert!(coarse.worst > 10.0 * fine.worst);
+        // diverges, the diagnostic pointing at where it started
+        let diverged = worst(0.1);
+        assert!(diverged.worst.is_nan() && diverged.at < 10.0);
+    }
 }
@@ -0,0 +1,304 @@
+use crate::{
+    Float,
+    fft::{self, Complex},
+    hh::{self, Axon, Params, Setup},
+};
+
+use std::f64::consts::PI;
+
+/// Impedance at one frequency, in kΩ·cm², i.e. mV per µA/cm².
+#[derive(Clone, Copy, Debug)]
+pub struct Point {
+    /// Hz
+    pub f: Float,
+    pub z: Complex,
+}
+
+/// Sinusoidal current sweeping linearly from `from` to `to` Hz over `duration` ms.
+#[derive(Clone, Copy, Debug)]
+pub struct Chirp {
+    /// µA/cm², small enough to stay below threshold
+    pub amplitude: Float,
+    pub from: Float,
+    pub to: Float,
+    pub duration: Float,
+}
+
+impl Default for Chirp {
+    fn default() -> Self {
+        Self {
+            amplitude: 0.5,
+            from: 1.0,
+            to: 200.0,
+            duration: 1000.0,
+        }
+    }
+}
+
+impl Chirp {
+    /// µA/cm² at `t` ms
+    pub fn current(&self, t: Float) -> Float {
+        let s = t * 1e-3;
+        let rate = (self.to - self.from) / (self.duration * 1e-3);
+        self.amplitude * (2.0 * PI * (self.from * s + 0.5 * rate * s * s)).sin()
+    }
+}
+
+/// Integration steps a `Zap` takes per call to `step`.
+const STEPS_PER_CALL: usize = 1000;
+
+/// Impedance from the cell's response to a chirp, starting at rest, as the ratio of the
+/// Fourier transforms of voltage and current within the swept band. Advanced a bounded amount
+/// of work per `step`: the simulation a thousand steps at a time, then the transforms a stage
+/// at a time.
+pub struct Zap {
+    pub chirp: Chirp,
+    params: Params,
+    setup: Setup,
+    axon: Axon,
+    /// voltage every `dt` so far
+    v: Vec<Float>,
+    /// of voltage and current, once the simulation is done
+    transforms: Option<[fft::Transform; 2]>,
+    points: Option<Vec<Point>>,
+}
+
+impl Zap {
+    pub fn new(params: &Params, chirp: &Chirp, dt: Float) -> Self {
+        let setup = Setup {
+            v0: rest(params).unwrap_or(0.0),
+            end: chirp.duration,
+            dt,
+            ..Default::default()
+        };
+        let axon = params.steady_state(setup.v0);
+        Self {
+            chirp: *chirp,
+            params: params.clone(),
+            v: Vec::with_capacity(setup.total_steps()),
+            setup,
+            axon,
+            transforms: None,
+            points: None,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.points.is_some()
+    }
+
+    /// fraction of the simulation done
+    pub fn progress(&self) -> Float {
+        self.v.len() as Float / self.setup.total_steps().max(1) as Float
+    }
+
+    pub fn step(&mut self) {
+        let (chirp, dt) = (self.chirp, self.setup.dt);
+        let total = self.setup.total_steps();
+        if self.v.len() < total {
+            for _ in 0..STEPS_PER_CALL.min(total - self.v.len()) {
+                if !self.v.is_empty() {
+                    let t = dt * (self.v.len() - 1) as Float;
+                    self.axon =
+                        hh::integrate(&self.params, self.axon, t, dt, |t, _| chirp.current(t));
+                }
+                self.v.push(self.axon.v());
+            }
+            return;
+        }
+
+        let Some([v, i]) = &mut self.transforms else {
+            let i: Vec<Float> = (0..self.v.len())
+                .map(|k| chirp.current(k as Float * dt))
+                .collect();
+            self.transforms =
+                Some([centred(&self.v), centred(&i)].map(|x| fft::Transform::real(&x)));
+            return;
+        };
+        if !v.finished() {
+            v.stage();
+            i.stage();
+            return;
+        }
+
+        let (v, i) = (&v.data, &i.data);
+        let resolution = 1e3 / (v.len() as Float * dt);
+        let points = (1..v.len() / 2)
+            .map(|k| Point {
+                f: k as Float * resolution,
+                z: v[k] / i[k],
+            })
+            .filter(|p| p.f >= chirp.from && p.f <= chirp.to)
+            .collect();
+        self.points = Some(points);
+    }
+
+    /// the model the chirp is applied to
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// empty until finished
+    pub fn points(&self) -> &[Point] {
+        self.points.as_deref().unwrap_or_default()
+    }
+}
+
+/// `x` less its mean
+fn centred(x: &[Float]) -> Vec<Float> {
+    let mean = x.iter().sum::<Float>() / x.len() as Float;
+    x.iter().map(|x| x - mean).collect()
+}
+
+/// Resting potential of the built-in equations, where the ionic currents with every gate at
+/// steady state cancel, or None if there is none within ±50 mV.
+pub fn rest(params: &Params) -> Option<Float> {
+    let current = |v: Float| params.steady_state(v).i_ion(params, 0.0);
+    let (mut lo, mut hi) = (-50.0, 50.0);
+    if current(lo).signum() == current(hi).signum() {
+        return None;
+    }
+    for _ in 0..60 {
+        let mid = 0.5 * (lo + hi);
+        if current(mid).signum() == current(lo).signum() {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Some(0.5 * (lo + hi))
+}
+
+/// Impedance of the built-in equations linearised about rest, at `frequencies` Hz:
+/// `1/Z = iωC + g + Σ_x (∂I/∂x) (dx_∞/dV) / (1 + iωτ_x)` over the gates `x`, wild-type and
+/// mutant, with `g` the conductance at fixed gates. None for custom models and mechanisms.
+pub fn linear(params: &Params, frequencies: &[Float]) -> Option<Vec<Point>> {
+    if params.custom.is_some() || params.mechanism.is_some() {
+        return None;
+    }
+    let v = rest(params)?;
+    let axon = params.steady_state(v);
+    let [m, h, n, mm, hm, nm] = [1, 2, 3, 4, 5, 6].map(|k| axon.state()[k]);
+    let [na, k] = crate::drug::unblocked(&params.drugs, 0.0);
+    let f = params.mutation.fraction;
+    let (g_na, g_k) = (na * params.g_na, k * params.g_k);
+    let (drive_na, drive_k) = (v - params.e_na, v - params.e_k);
+    let slope = |inf: &dyn Fn(Float) -> Float| (inf(v + 1e-4) - inf(v - 1e-4)) / 2e-4;
+
+    // ∂I/∂x, dx_∞/dV and τ_x of each gate
+    let gates = [
+        (
+            (1.0 - f) * g_na * 3.0 * m * m * h * drive_na,
+            slope(&|v| params.m_inf(v)),
+            params.tau_m(v),
+        ),
+        (
+            (1.0 - f) * g_na * m.powi(3) * drive_na,
+            slope(&|v| params.h_inf(v)),
+            params.tau_h(v),
+        ),
+        (
+            (1.0 - f) * g_k * 4.0 * n.powi(3) * drive_k,
+            slope(&|v| params.n_inf(v)),
+            params.tau_n(v),
+        ),
+        (
+            f * g_na * 3.0 * mm * mm * hm * drive_na,
+            slope(&|v| params.mutant_inf(0, v)),
+            params.mutant_tau(0, v),
+        ),
+        (
+            f * g_na * mm.powi(3) * drive_na,
+            slope(&|v| params.mutant_inf(1, v)),
+            params.mutant_tau(1, v),
+        ),
+        (
+            f * g_k * 4.0 * nm.powi(3) * drive_k,
+            slope(&|v| params.mutant_inf(2, v)),
+            params.mutant_tau(2, v),
+        ),
+    ];
+    let g = axon.cond_na(params, 0.0) + axon.cond_k(params, 0.0) + params.g_l;
+
+    let points = frequencies
+        .iter()
+        .map(|&freq| {
+            // per ms, as τ is in ms
+            let omega = 2.0 * PI * freq * 1e-3;
+            let mut admittance = Complex::new(g, omega * params.c_m);
+            for &(partial, inf_slope, tau) in &gates {
+                admittance = admittance
+                    + Complex::new(partial * inf_slope, 0.0) / Complex::new(1.0, omega * tau);
+            }
+            Point {
+                f: freq,
+                z: Complex::new(1.0, 0.0) / admittance,
+            }
+        })
+        .collect();
+    Some(points)
+}
+
+/// Peak of an impedance curve.
+#[derive(Clone, Copy, Debug)]
+pub struct Resonance {
+    /// Hz
+    pub frequency: Float,
+    /// |Z| at resonance over |Z| at the lowest frequency, 1 without resonance
+    pub q: Float,
+}
+
+impl Resonance {
+    pub fn of(points: &[Point]) -> Option<Self> {
+        let first = points.first()?;
+        let peak = points
+            .iter()
+            .max_by(|a, b| a.z.norm().total_cmp(&b.z.norm()))?;
+        Some(Self {
+            frequency: peak.f,
+            q: peak.z.norm() / first.z.norm(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squid_axon_resonates() {
+        let params = Params::default();
+        let v = rest(&params).unwrap();
+        assert!(v.abs() < 0.1);
+
+        let frequencies: Vec<Float> = (0..=200).map(Float::from).collect();
+        let linear = linear(&params, &frequencies).unwrap();
+        // the DC limit is the slope resistance of the steady-state I–V curve
+        let h = 1e-4;
+        let slope = (params.steady_state(v + h).i_ion(&params, 0.0)
+            - params.steady_state(v - h).i_ion(&params, 0.0))
+            / (2.0 * h);
+        assert!((linear[0].z.norm() - 1.0 / slope).abs() < 1e-3 / slope);
+
+        let resonance = Resonance::of(&linear).unwrap();
+        assert!(resonance.frequency > 20.0 && resonance.frequency < 150.0);
+        assert!(resonance.q > 1.1);
+
+        let chirp = Chirp {
+            duration: 500.0,
+            ..Default::default()
+        };
+        let mut zap = Zap::new(&params, &chirp, 0.02);
+        while !zap.finished() {
+            zap.step();
+        }
+        let zap = zap.points();
+        let measured = Resonance::of(zap).unwrap();
+        assert!((measured.frequency - resonance.frequency).abs() < 0.2 * resonance.frequency);
+        // the two agree over the band
+        for p in zap.iter().filter(|p| p.f > 10.0 && p.f < 150.0) {
+            let exact = linear[p.f.round() as usize].z.norm();
+            assert!((p.z.norm() - exact).abs() < 0.15 * exact, "{} Hz", p.f);
+        }
+    }
+}
@@ -1,17 +1,135 @@
+mod clamp;
+mod decimate;
+mod drug;
+mod energy;
+mod expr;
+mod fft;
+mod fit;
 mod hh;
+mod impedance;
+mod network;
+mod neuroml;
+mod nmodl;
+mod optim;
+mod prc;
 mod rate;
+mod record;
 mod rk4;
+mod rng;
+mod runs;
+mod spectrum;
+mod spikes;
+mod sweep;
+mod threshold;
+mod trace;
 
 mod ui;
 
-use egui::{DragValue, FontId, Grid, ProgressBar, RichText, Window, widgets};
-use egui_plot::{Legend, Line, Plot, PlotPoints};
+use egui::{DragValue, FontId, Grid, ProgressBar, RichText, TextEdit, Window, widgets};
+use egui_plot::{Legend, Line, Plot, PlotPoints, PlotUi};
 
 use std::{cell::RefCell, rc::Rc};
 
 type Float = f64;
 
-const PLOT_POINTS: usize = 200;
+/// seconds per frame that long computations stepped on the UI thread may take
+const FRAME_BUDGET: f64 = 0.015;
+
+/// Fraction of channels each drug blocks over the run, scaled to the largest stimulus so it
+/// shares the stimulus plot.
+fn drug_timeline(plot_ui: &mut PlotUi, hh: &hh::State) {
+    let largest = match &hh.setup.replay {
+        Some(waveform) => waveform.i.iter().fold(0.0, |m: Float, i| m.max(i.abs())),
+        None => hh.setup.pulse.magnitude.abs(),
+    };
+    let scale = if largest > 0.0 { largest } else { 1.0 };
+    for application in &hh.params.drugs {
+        let channels = [("Na", application.drug.na), ("K", application.drug.k)];
+        for (idx, (channel, block)) in channels.into_iter().enumerate() {
+            if block.is_none() {
+                continue;
+            }
+            let name = format!(
+                "{} block of {channel} ({scale} = 100%)",
+                application.drug.name
+            );
+            let application = application.clone();
+            let points = PlotPoints::from_explicit_callback(
+                move |t| {
+                    let unblocked = drug::unblocked(std::slice::from_ref(&application), t);
+                    scale * (1.0 - unblocked[idx])
+                },
+                0.0..=hh.setup.end,
+                512,
+            );
+            plot_ui.line(
+                Line::new(points)
+                    .style(egui_plot::LineStyle::dashed_loose())
+                    .name(name),
+            );
+        }
+    }
+}
+
+/// range of x the plot shows, or everything while it fits its bounds to the data
+fn visible_x(plot_ui: &PlotUi) -> std::ops::RangeInclusive<f64> {
+    if plot_ui.auto_bounds().x {
+        f64::NEG_INFINITY..=f64::INFINITY
+    } else {
+        let bounds = plot_ui.plot_bounds();
+        bounds.min()[0]..=bounds.max()[0]
+    }
+}
+
+/// Line through the `len` points of `sample`, decimated to about two per pixel of the part of
+/// `x` the plot shows, so that no peak is lost.
+fn decimated_line(
+    plot_ui: &PlotUi,
+    x: std::ops::RangeInclusive<f64>,
+    len: usize,
+    sample: impl Fn(usize) -> [f64; 2],
+) -> Line {
+    let pixels = plot_ui.response().rect.width().max(1.0) as usize;
+    Line::new(PlotPoints::from(decimate::min_max(len, sample, x, pixels)))
+}
+
+/// helper function to decimate a recorded variable, mapped through `map`, for plotting;
+/// only what has been simulated so far is recorded, so the rest is left blank
+fn line_from_record(
+    plot_ui: &PlotUi,
+    recorder: &record::Recorder,
+    var: record::Var,
+    map: impl Fn(Float) -> Float,
+) -> Line {
+    let Some(column) = recorder.column(var) else {
+        return Line::new(PlotPoints::default());
+    };
+    decimated_line(plot_ui, visible_x(plot_ui), column.len(), |k| {
+        [recorder.t[k], map(column[k])]
+    })
+}
+
+/// `var` of the current run and every visible kept run, or of run A minus run B when comparing
+fn run_lines(plot_ui: &mut PlotUi, hh: &hh::State, var: record::Var, label: &str) {
+    let runs = &hh.runs;
+    if runs.diff {
+        if let (Some(a), Some(b)) = (hh.recording(runs.a), hh.recording(runs.b)) {
+            let points = runs::difference(a, b, var);
+            let line = decimated_line(plot_ui, visible_x(plot_ui), points.len(), |k| points[k]);
+            plot_ui.line(line.name(format!(
+                "{label}: {} − {}",
+                runs.name(runs.a),
+                runs.name(runs.b)
+            )));
+        }
+        return;
+    }
+    plot_ui.line(line_from_record(plot_ui, &hh.recorder, var, |x| x).name(label));
+    for run in runs.list.iter().filter(|r| r.visible) {
+        let line = line_from_record(plot_ui, &run.recorder, var, |x| x);
+        plot_ui.line(line.color(run.color).name(format!("{}: {label}", run.name)));
+    }
+}
 
 #[derive(Default, PartialEq)]
 enum ExtraPlot {
@@ -21,169 +139,2838 @@ enum ExtraPlot {
     Conductance,
 }
 
-#[derive(Default)]
-struct UiState {
-    sim_prog_bar_animate: bool,
-    extra_plot: ExtraPlot,
-}
+/// An imported trace drawn over the simulated voltage.
+struct Overlay {
+    trace: trace::Trace,
+    visible: bool,
+    /// added to recorded time, in ms
+    time_shift: Float,
+    /// added to recorded voltage, in mV. The model puts rest at 0 mV, hence the default of 65.
+    v_offset: Float,
+    /// sweep whose current is replayed as stimulus
+    replay_sweep: usize,
+    /// μA/cm² per recorded current unit
+    i_scale: Float,
+}
+
+impl Overlay {
+    fn new(trace: trace::Trace) -> Self {
+        Self {
+            trace,
+            visible: true,
+            time_shift: 0.0,
+            v_offset: 65.0,
+            replay_sweep: 0,
+            i_scale: 1.0,
+        }
+    }
+}
+
+struct FitUi {
+    overlay: usize,
+    sweep: usize,
+    /// every fittable parameter and whether it is free
+    selected: Vec<(fit::Param, bool)>,
+    objective: fit::Objective,
+    method: fit::Method,
+    fitter: Option<fit::Fitter>,
+    running: bool,
+    estimates: Vec<fit::Estimate>,
+}
+
+impl Default for FitUi {
+    fn default() -> Self {
+        Self {
+            overlay: 0,
+            sweep: 0,
+            selected: fit::Param::all(&hh::Params::default())
+                .into_iter()
+                .map(|p| {
+                    (
+                        p,
+                        matches!(p, fit::Param::GNa | fit::Param::GK | fit::Param::GL),
+                    )
+                })
+                .collect(),
+            objective: fit::Objective::VoltageRmse,
+            method: fit::Method::NelderMead,
+            fitter: None,
+            running: false,
+            estimates: Vec::new(),
+        }
+    }
+}
+
+struct ModelUi {
+    source: String,
+    error: Option<String>,
+}
+
+impl Default for ModelUi {
+    fn default() -> Self {
+        Self {
+            source: expr::HODGKIN_HUXLEY.to_string(),
+            error: None,
+        }
+    }
+}
+
+struct FilesUi {
+    neuroml_path: String,
+    nmodl_path: String,
+    v_rest: Float,
+    message: Option<Result<String, String>>,
+}
+
+impl Default for FilesUi {
+    fn default() -> Self {
+        Self {
+            neuroml_path: String::new(),
+            nmodl_path: String::new(),
+            v_rest: neuroml::V_REST,
+            message: None,
+        }
+    }
+}
+
+struct RunUi {
+    /// how far "Continue" extends a finished run
+    continue_by: Float,
+    error: Option<String>,
+}
+
+impl Default for RunUi {
+    fn default() -> Self {
+        Self {
+            continue_by: 10.0,
+            error: None,
+        }
+    }
+}
+
+struct SweepUi {
+    x: sweep::Axis,
+    y: sweep::Axis,
+    two_d: bool,
+    metric: sweep::Metric,
+    sweep: Option<sweep::Sweep>,
+}
+
+impl Default for SweepUi {
+    fn default() -> Self {
+        Self {
+            x: sweep::Axis::new(sweep::Knob::Amplitude),
+            y: sweep::Axis::new(sweep::Knob::GK),
+            two_d: false,
+            metric: sweep::Metric::SpikeCount,
+            sweep: None,
+        }
+    }
+}
+
+fn axis_editor(ui: &mut egui::Ui, label: &str, axis: &mut sweep::Axis) {
+    ui.label(label);
+    egui::ComboBox::from_id_source(label)
+        .selected_text(axis.knob.name())
+        .show_ui(ui, |ui| {
+            for knob in sweep::Knob::ALL {
+                if ui
+                    .selectable_label(axis.knob == knob, knob.name())
+                    .clicked()
+                    && axis.knob != knob
+                {
+                    *axis = sweep::Axis::new(knob);
+                }
+            }
+        });
+    let (lo, hi) = axis.knob.bounds();
+    ui.label("from");
+    ui.add(DragValue::new(&mut axis.from).range(lo..=hi).speed(0.1));
+    ui.label("to");
+    ui.add(DragValue::new(&mut axis.to).range(lo..=hi).speed(0.1));
+    ui.add(
+        DragValue::new(&mut axis.points)
+            .range(1..=200)
+            .suffix(" points"),
+    );
+    ui.end_row();
+}
+
+/// maps `u` in 0..=1 onto a dark blue to yellow scale
+fn colormap(u: Float) -> egui::Color32 {
+    const STOPS: [[f32; 3]; 5] = [
+        [68.0, 1.0, 84.0],
+        [59.0, 82.0, 139.0],
+        [33.0, 145.0, 140.0],
+        [94.0, 201.0, 98.0],
+        [253.0, 231.0, 37.0],
+    ];
+    if !u.is_finite() {
+        return egui::Color32::GRAY;
+    }
+    let x = u.clamp(0.0, 1.0) as f32 * (STOPS.len() - 1) as f32;
+    let (k, w) = ((x.floor() as usize).min(STOPS.len() - 2), x.fract());
+    let w = if x >= (STOPS.len() - 1) as f32 {
+        1.0
+    } else {
+        w
+    };
+    let c = |i: usize| (STOPS[k][i] * (1.0 - w) + STOPS[k + 1][i] * w) as u8;
+    egui::Color32::from_rgb(c(0), c(1), c(2))
+}
+
+struct StrengthUi {
+    /// shortest and longest pulse, in ms
+    from: Float,
+    to: Float,
+    points: usize,
+    polarity: threshold::Polarity,
+    max: Float,
+    search: Option<threshold::StrengthDuration>,
+}
+
+impl Default for StrengthUi {
+    fn default() -> Self {
+        Self {
+            from: 0.05,
+            to: 20.0,
+            points: 12,
+            polarity: threshold::Polarity::Depolarizing,
+            max: 200.0,
+            search: None,
+        }
+    }
+}
+
+struct RefractoryUi {
+    /// shortest and longest interval between pulse onsets, in ms
+    from: Float,
+    to: Float,
+    points: usize,
+    test_duration: Float,
+    max: Float,
+    protocol: Option<threshold::PairedPulse>,
+}
+
+impl Default for RefractoryUi {
+    fn default() -> Self {
+        Self {
+            from: 1.0,
+            to: 20.0,
+            points: 20,
+            test_duration: 0.5,
+            max: 200.0,
+            protocol: None,
+        }
+    }
+}
+
+/// Coupling coefficient of the first gap junction, with what it was computed for.
+struct Coupling {
+    conductance: Float,
+    dt: Float,
+    params: hh::Params,
+    coefficient: Float,
+}
+
+struct NetworkUi {
+    network: network::Network,
+    end: Float,
+    sim: Option<network::Simulation>,
+    coupling: Option<Coupling>,
+}
+
+impl Default for NetworkUi {
+    fn default() -> Self {
+        Self {
+            network: network::Network::preset(network::Preset::Excitation),
+            end: 100.0,
+            sim: None,
+            coupling: None,
+        }
+    }
+}
+
+fn network_editor(ui: &mut egui::Ui, net: &mut network::Network) {
+    let count = net.neurons.len();
+    ui.label("Neurons");
+    let mut remove = None;
+    Grid::new("neuron grid")
+        .num_columns(6)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Name");
+            ui.label("Bias");
+            ui.label("Pulse start");
+            ui.label("end");
+            ui.label("magnitude");
+            ui.end_row();
+            for (idx, neuron) in net.neurons.iter_mut().enumerate() {
+                ui.add(TextEdit::singleline(&mut neuron.name).desired_width(80.0));
+                ui.add(
+                    DragValue::new(&mut neuron.bias)
+                        .speed(0.1)
+                        .suffix(" μA/cm²"),
+                );
+                let pulse = &mut neuron.pulse;
+                ui.add(
+                    DragValue::new(&mut pulse.start)
+                        .range(0.0..=pulse.end)
+                        .speed(0.1),
+                );
+                ui.add(
+                    DragValue::new(&mut pulse.end)
+                        .range(pulse.start..=1e4)
+                        .speed(0.1),
+                );
+                ui.add(DragValue::new(&mut pulse.magnitude).speed(0.5));
+                if ui.button("Remove").clicked() {
+                    remove = Some(idx);
+                }
+                ui.end_row();
+            }
+        });
+    if let Some(idx) = remove {
+        net.neurons.remove(idx);
+        net.synapses.retain(|s| s.from != idx && s.to != idx);
+        net.gaps.retain(|g| g.a != idx && g.b != idx);
+        let ends = net
+            .synapses
+            .iter_mut()
+            .flat_map(|s| [&mut s.from, &mut s.to]);
+        for end in ends.chain(net.gaps.iter_mut().flat_map(|g| [&mut g.a, &mut g.b])) {
+            if *end > idx {
+                *end -= 1;
+            }
+        }
+    }
+    if ui.button("Add neuron").clicked() {
+        net.neurons
+            .push(network::Neuron::new(format!("N{}", count + 1)));
+    }
+
+    ui.separator();
+    ui.label("Synapses");
+    let names: Vec<String> = net.neurons.iter().map(|n| n.name.clone()).collect();
+    let mut remove = None;
+    Grid::new("synapse grid")
+        .num_columns(9)
+        .striped(true)
+        .show(ui, |ui| {
+            for label in [
+                "From", "To", "Type", "Weight", "Delay", "τ rise", "τ decay", "E_rev",
+            ] {
+                ui.label(label);
+            }
+            ui.end_row();
+            for (idx, synapse) in net.synapses.iter_mut().enumerate() {
+                for (end, which) in [(&mut synapse.from, "from"), (&mut synapse.to, "to")] {
+                    egui::ComboBox::from_id_source(("synapse", idx, which))
+                        .width(80.0)
+                        .selected_text(names[*end].as_str())
+                        .show_ui(ui, |ui| {
+                            for (n, name) in names.iter().enumerate() {
+                                ui.selectable_value(end, n, name);
+                            }
+                        });
+                }
+                egui::ComboBox::from_id_source(("synapse kind", idx))
+                    .width(90.0)
+                    .selected_text(synapse.kind.name())
+                    .show_ui(ui, |ui| {
+                        for kind in network::Kind::ALL {
+                            if ui
+                                .selectable_label(synapse.kind == kind, kind.name())
+                                .clicked()
+                            {
+                                let (from, to) = (synapse.from, synapse.to);
+                                *synapse = network::Synapse {
+                                    weight: synapse.weight,
+                                    delay: synapse.delay,
+                                    ..network::Synapse::new(from, to, kind)
+                                };
+                            }
+                        }
+                    });
+                ui.add(
+                    DragValue::new(&mut synapse.weight)
+                        .range(0.0..=100.0)
+                        .speed(0.01)
+                        .suffix(" mS/cm²"),
+                );
+                ui.add(
+                    DragValue::new(&mut synapse.delay)
+                        .range(0.0..=100.0)
+                        .speed(0.1)
+                        .suffix(" ms"),
+                );
+                let rises = !matches!(
+                    synapse.kind,
+                    network::Kind::Exponential | network::Kind::Alpha
+                );
+                ui.add_enabled(
+                    rises,
+                    DragValue::new(&mut synapse.tau_rise)
+                        .range(0.01..=synapse.tau_decay * 0.99)
+                        .speed(0.01),
+                );
+                ui.add(
+                    DragValue::new(&mut synapse.tau_decay)
+                        .range(0.1..=1000.0)
+                        .speed(0.1),
+                );
+                ui.add(DragValue::new(&mut synapse.reversal).speed(0.5));
+                if ui.button("Remove").clicked() {
+                    remove = Some(idx);
+                }
+                ui.end_row();
+            }
+        });
+    if let Some(idx) = remove {
+        net.synapses.remove(idx);
+    }
+    if ui
+        .add_enabled(count > 0, egui::Button::new("Add synapse"))
+        .clicked()
+    {
+        net.synapses
+            .push(network::Synapse::new(0, count - 1, network::Kind::Ampa));
+    }
+
+    ui.separator();
+    ui.label("Gap junctions");
+    let mut remove = None;
+    Grid::new("gap grid")
+        .num_columns(4)
+        .striped(true)
+        .show(ui, |ui| {
+            for (idx, gap) in net.gaps.iter_mut().enumerate() {
+                for (end, which) in [(&mut gap.a, "a"), (&mut gap.b, "b")] {
+                    egui::ComboBox::from_id_source(("gap", idx, which))
+                        .width(80.0)
+                        .selected_text(names[*end].as_str())
+                        .show_ui(ui, |ui| {
+                            for (n, name) in names.iter().enumerate() {
+                                ui.selectable_value(end, n, name);
+                            }
+                        });
+                }
+                ui.add(
+                    DragValue::new(&mut gap.conductance)
+                        .range(0.0..=10.0)
+                        .speed(0.01)
+                        .suffix(" mS/cm²"),
+                );
+                if ui.button("Remove").clicked() {
+                    remove = Some(idx);
+                }
+                ui.end_row();
+            }
+        });
+    if let Some(idx) = remove {
+        net.gaps.remove(idx);
+    }
+    if ui
+        .add_enabled(count > 1, egui::Button::new("Add gap junction"))
+        .clicked()
+    {
+        net.gaps.push(network::Gap {
+            a: 0,
+            b: 1,
+            conductance: 0.1,
+        });
+    }
+}
+
+/// A row of the energy comparison: what was compared, its value along the axis if it came
+/// from one, and the average cost per spike once the run has been analysed.
+struct EnergyRow {
+    name: String,
+    x: Option<Float>,
+    run: (hh::Setup, hh::Params),
+    summary: Option<energy::Summary>,
+}
+
+struct EnergyUi {
+    running: Option<energy::Analysis>,
+    costs: Vec<energy::SpikeCost>,
+    axis: sweep::Axis,
+    rows: Vec<EnergyRow>,
+}
+
+impl Default for EnergyUi {
+    fn default() -> Self {
+        Self {
+            running: None,
+            costs: Vec::new(),
+            axis: sweep::Axis {
+                points: 7,
+                ..sweep::Axis::new(sweep::Knob::Temperature)
+            },
+            rows: Vec::new(),
+        }
+    }
+}
+
+struct PrcUi {
+    /// µA/cm² driving tonic firing
+    drive: Float,
+    perturbation: prc::Perturbation,
+    phases: usize,
+    search: Option<prc::Search>,
+    direct: Option<prc::Direct>,
+    /// phase and the advance the adjoint predicts for the perturbation
+    adjoint: Vec<[Float; 2]>,
+    error: Option<String>,
+}
+
+impl Default for PrcUi {
+    fn default() -> Self {
+        Self {
+            drive: 10.0,
+            perturbation: prc::Perturbation {
+                amplitude: 2.0,
+                duration: 0.1,
+            },
+            phases: 50,
+            search: None,
+            direct: None,
+            adjoint: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ImpedanceUi {
+    chirp: impedance::Chirp,
+    running: Option<impedance::Zap>,
+    zap: Vec<impedance::Point>,
+    /// at the frequencies of `zap`, None for models it cannot linearise
+    linear: Option<Vec<impedance::Point>>,
+}
+
+/// magnitude and phase in degrees of each point, as plot lines
+fn impedance_lines(points: &[impedance::Point]) -> (Vec<[f64; 2]>, Vec<[f64; 2]>) {
+    points
+        .iter()
+        .map(|p| ([p.f, p.z.norm()], [p.f, p.z.arg().to_degrees()]))
+        .unzip()
+}
+
+struct SpectrumUi {
+    var: record::Var,
+    /// samples per Welch segment, a power of two
+    segment: usize,
+    window: spectrum::Window,
+    log: bool,
+    /// ms at the start of the recording left out, to skip the transient
+    skip: Float,
+    psd: Vec<[Float; 2]>,
+    error: Option<String>,
+}
+
+impl Default for SpectrumUi {
+    fn default() -> Self {
+        Self {
+            var: record::Var::V,
+            segment: 4096,
+            window: spectrum::Window::Hann,
+            log: true,
+            skip: 50.0,
+            psd: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct UiState {
+    sim_prog_bar_animate: bool,
+    run: RunUi,
+    extra_plot: ExtraPlot,
+    trace_path: String,
+    trace_error: Option<String>,
+    overlays: Vec<Overlay>,
+    fit: FitUi,
+    model: ModelUi,
+    files: FilesUi,
+    sweep: SweepUi,
+    strength: StrengthUi,
+    refractory: RefractoryUi,
+    network: NetworkUi,
+    energy: EnergyUi,
+    prc: PrcUi,
+    impedance: ImpedanceUi,
+    spectrum: SpectrumUi,
+}
+
+fn rate_editor(ui: &mut egui::Ui, label: &str, rate: &mut rate::Rate) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        egui::ComboBox::from_id_source(label)
+            .selected_text(rate.family.name())
+            .show_ui(ui, |ui| {
+                for family in rate::Family::ALL {
+                    ui.selectable_value(&mut rate.family, family, family.name());
+                }
+            });
+        ui.label("rate");
+        ui.add(DragValue::new(&mut rate.rate).speed(0.01));
+        ui.label("midpoint");
+        ui.add(DragValue::new(&mut rate.midpoint).speed(0.5));
+        ui.label("scale");
+        ui.add(DragValue::new(&mut rate.scale).speed(0.1));
+    });
+    // the scale divides, keep it away from zero
+    if rate.scale.abs() < 1e-3 {
+        rate.scale = 1e-3_f64.copysign(rate.scale);
+    }
+}
+
+/// `idx` in `rate::GATES` order
+fn gate_editor(ui: &mut egui::Ui, idx: usize, gate: &mut rate::Gate) {
+    let name = rate::GATES[idx];
+    ui.horizontal(|ui| {
+        let is_rates = matches!(gate, rate::Gate::Rates { .. });
+        if ui.selectable_label(is_rates, "α/β rates").clicked() && !is_rates {
+            *gate = rate::Kinetics::default().gates[idx].clone();
+        }
+        if ui
+            .selectable_label(!is_rates, "Boltzmann + τ table")
+            .clicked()
+            && is_rates
+        {
+            *gate = gate.to_boltzmann();
+        }
+    });
+
+    match gate {
+        rate::Gate::Rates { alpha, beta } => {
+            rate_editor(ui, &format!("α_{name}"), alpha);
+            rate_editor(ui, &format!("β_{name}"), beta);
+        }
+        rate::Gate::Boltzmann { v_half, slope, tau } => {
+            ui.horizontal(|ui| {
+                ui.label("V½");
+                ui.add(DragValue::new(v_half).speed(0.5));
+                ui.label("slope");
+                ui.add(DragValue::new(slope).speed(0.1));
+            });
+            if slope.abs() < 1e-3 {
+                *slope = 1e-3_f64.copysign(*slope);
+            }
+
+            ui.collapsing("τ table", |ui| {
+                let mut remove = None;
+                let removable = tau.v.len() > 1;
+                Grid::new("tau table grid").num_columns(3).show(ui, |ui| {
+                    for (idx, (v, t)) in tau.v.iter_mut().zip(&mut tau.tau).enumerate() {
+                        ui.add(DragValue::new(v).speed(0.5).suffix(" mV"));
+                        ui.add(
+                            DragValue::new(t)
+                                .range(1e-3..=1e3)
+                                .speed(0.01)
+                                .suffix(" ms"),
+                        );
+                        if ui
+                            .add_enabled(removable, egui::Button::new("✖").small())
+                            .clicked()
+                        {
+                            remove = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if let Some(idx) = remove {
+                    tau.v.remove(idx);
+                    tau.tau.remove(idx);
+                }
+                if ui.button("Add row").clicked() {
+                    let (v, t) = (tau.v[tau.v.len() - 1] + 10.0, tau.tau[tau.tau.len() - 1]);
+                    tau.v.push(v);
+                    tau.tau.push(t);
+                }
+            });
+            tau.sort();
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    ui: UiState,
+    hh: hh::State,
+}
+
+fn clamp_editor(ui: &mut egui::Ui, hh: &mut hh::State) {
+    ui.add_enabled_ui(!hh.simulating(), |ui| {
+        let setup = &mut hh.setup;
+        ui.horizontal(|ui| {
+            let name = setup.clamp.as_ref().map_or("none", |c| c.name());
+            egui::ComboBox::from_id_source("clamp")
+                .selected_text(name)
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(setup.clamp.is_none(), "none").clicked() {
+                        setup.clamp = None;
+                    }
+                    for clamp in clamp::Clamp::all() {
+                        let selected = clamp.name() == name;
+                        if ui.selectable_label(selected, clamp.name()).clicked() && !selected {
+                            setup.clamp = Some(clamp);
+                        }
+                    }
+                });
+            match &mut setup.clamp {
+                None => {}
+                Some(clamp::Clamp::Sodium { conductance }) => {
+                    ui.label("g");
+                    ui.add(DragValue::new(conductance).range(0.0..=500.0).speed(1.0));
+                }
+                Some(clamp::Clamp::Synapse {
+                    conductance,
+                    reversal,
+                    tau,
+                    start,
+                    interval,
+                    count,
+                }) => {
+                    ui.label("g");
+                    ui.add(DragValue::new(conductance).range(0.0..=10.0).speed(0.01));
+                    ui.label("Reversal");
+                    ui.add(DragValue::new(reversal).range(-50.0..=150.0).speed(0.5));
+                    ui.label("τ");
+                    ui.add(
+                        DragValue::new(tau)
+                            .range(0.1..=50.0)
+                            .speed(0.1)
+                            .suffix(" ms"),
+                    );
+                    ui.label("Start");
+                    ui.add(DragValue::new(start).range(0.0..=200.0).speed(0.5));
+                    ui.label("Interval");
+                    ui.add(DragValue::new(interval).range(0.1..=200.0).speed(0.5));
+                    ui.label("Events");
+                    ui.add(DragValue::new(count).range(0..=100));
+                }
+                Some(clamp::Clamp::Cell {
+                    conductance,
+                    pulse,
+                    v,
+                    ..
+                }) => {
+                    ui.label("g");
+                    ui.add(DragValue::new(conductance).range(0.0..=10.0).speed(0.01));
+                    ui.label("Cell pulse");
+                    let (before, limit) = (pulse.clone(), pulse.end);
+                    ui.add(
+                        DragValue::new(&mut pulse.start)
+                            .range(0.0..=limit)
+                            .speed(0.1),
+                    );
+                    let start = pulse.start;
+                    ui.add(
+                        DragValue::new(&mut pulse.end)
+                            .range(start..=200.0)
+                            .speed(0.1),
+                    );
+                    ui.add(
+                        DragValue::new(&mut pulse.magnitude)
+                            .range(-20.0..=20.0)
+                            .speed(1.0),
+                    );
+                    if pulse.start != before.start
+                        || pulse.end != before.end
+                        || pulse.magnitude != before.magnitude
+                    {
+                        v.clear();
+                    }
+                }
+            }
+        });
+        if let Some(clamp::Clamp::Cell { v, .. }) = &setup.clamp {
+            let recorded = !v.is_empty();
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Record model cell")
+                    .on_hover_text("Simulate the model cell with the current setup and parameters")
+                    .clicked()
+                {
+                    let mut clamp = setup.clamp.take().expect("matched");
+                    clamp.record_cell(setup, &hh.params);
+                    setup.clamp = Some(clamp);
+                }
+                ui.label(if recorded {
+                    "recorded"
+                } else {
+                    "not recorded: the clamp injects no current"
+                });
+            });
+        }
+    });
+}
+
+fn drug_editor(ui: &mut egui::Ui, hh: &mut hh::State) {
+    let built_in = hh.params.custom.is_none() && hh.params.mechanism.is_none();
+    if !built_in {
+        ui.label(
+            "Drugs block the built-in sodium and potassium channels, \
+             so have no effect on a custom model or mechanism.",
+        );
+    }
+    ui.add_enabled_ui(built_in && !hh.simulating(), |ui| {
+        let drugs = &mut hh.params.drugs;
+        let mut remove = None;
+        for (idx, application) in drugs.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(&application.drug.name).strong());
+                let speed = application.concentration.max(0.01) * 0.01;
+                ui.add(
+                    DragValue::new(&mut application.concentration)
+                        .range(0.0..=1e6)
+                        .speed(speed)
+                        .suffix(" µM"),
+                );
+                ui.label("from");
+                let off = application.off;
+                ui.add(
+                    DragValue::new(&mut application.on)
+                        .range(0.0..=off)
+                        .speed(0.5)
+                        .suffix(" ms"),
+                );
+                let mut washed_out = application.off.is_finite();
+                if ui.checkbox(&mut washed_out, "to").changed() {
+                    application.off = if washed_out {
+                        hh.setup.end.max(application.on)
+                    } else {
+                        Float::INFINITY
+                    };
+                }
+                if washed_out {
+                    let on = application.on;
+                    ui.add(
+                        DragValue::new(&mut application.off)
+                            .range(on..=1e4)
+                            .speed(0.5)
+                            .suffix(" ms"),
+                    );
+                }
+                ui.label("τ");
+                ui.add(
+                    DragValue::new(&mut application.tau)
+                        .range(0.0..=1e3)
+                        .speed(0.1)
+                        .suffix(" ms"),
+                )
+                .on_hover_text("Time constant of wash-in and wash-out");
+                if ui.button("Remove").clicked() {
+                    remove = Some(idx);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add_space(20.0);
+                for (channel, block) in [
+                    ("Na", &mut application.drug.na),
+                    ("K", &mut application.drug.k),
+                ] {
+                    let mut blocks = block.is_some();
+                    if ui
+                        .checkbox(&mut blocks, format!("blocks {channel}"))
+                        .changed()
+                    {
+                        *block = blocks.then_some(drug::Block {
+                            ic50: 1.0,
+                            hill: 1.0,
+                        });
+                    }
+                    if let Some(block) = block {
+                        ui.label("IC50");
+                        let speed = block.ic50 * 0.01;
+                        ui.add(
+                            DragValue::new(&mut block.ic50)
+                                .range(1e-6..=1e6)
+                                .speed(speed)
+                                .suffix(" µM"),
+                        );
+                        ui.label("Hill");
+                        ui.add(DragValue::new(&mut block.hill).range(0.1..=5.0).speed(0.01));
+                    }
+                }
+            });
+        }
+        if let Some(idx) = remove {
+            drugs.remove(idx);
+        }
+        ui.horizontal(|ui| {
+            ui.label("Apply");
+            for preset in drug::Drug::presets() {
+                if ui.button(&preset.name).clicked() {
+                    drugs.push(drug::Application::new(preset));
+                }
+            }
+        });
+    });
+}
+
+fn mutation_editor(ui: &mut egui::Ui, mutation: &mut rate::Mutation) {
+    Grid::new("mutation grid").num_columns(4).show(ui, |ui| {
+        ui.label("Gate");
+        ui.label("V shift");
+        ui.label("Slope factor");
+        ui.label("τ scale");
+        ui.end_row();
+        for (name, modifier) in rate::GATES.iter().zip(&mut mutation.modifiers) {
+            ui.label(*name);
+            ui.add(
+                DragValue::new(&mut modifier.shift)
+                    .range(-50.0..=50.0)
+                    .speed(0.5)
+                    .suffix(" mV"),
+            );
+            ui.add(
+                DragValue::new(&mut modifier.slope)
+                    .range(0.1..=10.0)
+                    .speed(0.01),
+            );
+            ui.add(
+                DragValue::new(&mut modifier.tau_scale)
+                    .range(0.01..=100.0)
+                    .speed(0.01),
+            );
+            ui.end_row();
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Mutant channels");
+        ui.add(egui::Slider::new(&mut mutation.fraction, 0.0..=1.0))
+            .on_hover_text("Fraction of the Na and K channels gating like the mutant");
+        if ui.button("Wild type").clicked() {
+            *mutation = rate::Mutation::default();
+        }
+    });
+}
+
+fn noise_editor(ui: &mut egui::Ui, hh: &mut hh::State) {
+    ui.add_enabled_ui(!hh.simulating(), |ui| {
+        let noise = &mut hh.setup.noise;
+        ui.horizontal(|ui| {
+            let mut on = noise.is_some();
+            if ui.checkbox(&mut on, "Inject noise").changed() {
+                *noise = on.then(hh::Noise::default);
+            }
+            let Some(noise) = noise else {
+                return;
+            };
+            ui.label("Mean");
+            ui.add(
+                DragValue::new(&mut noise.mean)
+                    .range(-50.0..=50.0)
+                    .speed(0.1)
+                    .suffix(" μA/cm²"),
+            );
+            ui.label("SD");
+            ui.add(
+                DragValue::new(&mut noise.sd)
+                    .range(0.0..=100.0)
+                    .speed(0.1)
+                    .suffix(" μA/cm²"),
+            );
+            ui.label("New value every");
+            ui.add(
+                DragValue::new(&mut noise.interval)
+                    .range(0.001..=100.0)
+                    .speed(0.01)
+                    .suffix(" ms"),
+            );
+            ui.label("Seed");
+            ui.add(DragValue::new(&mut noise.seed));
+        });
+    });
+}
+
+fn recording_editor(ui: &mut egui::Ui, hh: &mut hh::State) {
+    ui.add_enabled_ui(!hh.simulating(), |ui| {
+        let config = &mut hh.record;
+        ui.horizontal_wrapped(|ui| {
+            for var in record::Var::ALL {
+                let mut on = config.vars.contains(&var);
+                if ui.checkbox(&mut on, var.name()).changed() {
+                    if on {
+                        config.vars.push(var);
+                    } else {
+                        config.vars.retain(|v| *v != var);
+                    }
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Interval");
+            ui.add(
+                DragValue::new(&mut config.interval)
+                    .range(0.001..=10.0)
+                    .speed(0.01)
+                    .suffix(" ms"),
+            );
+            egui::ComboBox::from_id_source("storage")
+                .selected_text(config.storage.name())
+                .show_ui(ui, |ui| {
+                    let disk = record::Storage::Disk {
+                        path: "recording.csv".to_string(),
+                    };
+                    for storage in [record::Storage::Memory, record::Storage::Ring, disk] {
+                        let selected = std::mem::discriminant(&storage)
+                            == std::mem::discriminant(&config.storage);
+                        if ui.selectable_label(selected, storage.name()).clicked() && !selected {
+                            config.storage = storage;
+                        }
+                    }
+                });
+            if config.storage != record::Storage::Memory {
+                ui.label("Keep");
+                ui.add(
+                    DragValue::new(&mut config.capacity)
+                        .range(1..=100_000_000)
+                        .speed(100)
+                        .suffix(" samples"),
+                );
+            }
+            if let record::Storage::Disk { path } = &mut config.storage {
+                ui.add(TextEdit::singleline(path).desired_width(150.0));
+            }
+        });
+    });
+
+    let (steps, dt) = (hh.setup.total_steps(), hh.setup.dt);
+    ui.label(format!(
+        "A run records {} samples every {} step(s) and needs {:.1} MB.",
+        hh.record.samples(steps, dt),
+        hh.record.stride(dt),
+        hh.record.memory(steps, dt) as f64 / 1e6,
+    ));
+    let recorder = &hh.recorder;
+    ui.label(format!(
+        "Last run: {} samples recorded, {} in memory ({:.1} MB).",
+        recorder.recorded,
+        recorder.len(),
+        recorder.memory() as f64 / 1e6,
+    ));
+    if let Some(e) = &recorder.error {
+        ui.colored_label(ui.visuals().error_fg_color, format!("Writing failed: {e}"));
+    }
+}
+
+fn main() {
+    let state = Rc::<RefCell<State>>::new(RefCell::new(State::default()));
+
+    let app = move |_mq_ctx: &mut dyn miniquad::RenderingBackend, egui_ctx: &egui::Context| {
+        Window::new("Console").title_bar(true).show(egui_ctx, |ui| {
+            Grid::new("console grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Theme");
+                    widgets::global_dark_light_mode_buttons(ui);
+                    ui.end_row();
+
+                    ui.label("Source code");
+                    ui.hyperlink_to(
+                        format!("{0} Open on GitHub", egui::special_emojis::GITHUB),
+                        "https://github.com/Richardn2002/hodgkin-huxley-playground",
+                    );
+                    ui.end_row();
+
+                    ui.label("Hint");
+                    ui.label("Double click on plot to reset axes.");
+                    ui.end_row();
+
+                    ui.label("Powered by");
+                    ui.hyperlink_to("Rust", "https://www.rust-lang.org/");
+                    ui.end_row();
+                    ui.label("");
+                    ui.hyperlink_to("egui", "https://github.com/emilk/egui");
+                    ui.end_row();
+                    ui.label("");
+                    ui.hyperlink_to("miniquad", "https://github.com/not-fl3/miniquad");
+                    ui.end_row();
+                })
+        });
+
+        Window::new("Rate Functions").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            ui.add_enabled_ui(!state.hh.simulating(), |ui| {
+                ui.collapsing("Edit kinetics", |ui| {
+                    for (idx, gate) in state.hh.params.kinetics.gates.iter_mut().enumerate() {
+                        ui.push_id(idx, |ui| {
+                            ui.label(RichText::new(format!("Gate {}", rate::GATES[idx])).strong());
+                            gate_editor(ui, idx, gate);
+                        });
+                        ui.separator();
+                    }
+                    if ui.button("Reset to Hodgkin–Huxley").clicked() {
+                        state.hh.params.kinetics = rate::Kinetics::default();
+                    }
+                });
+            });
+
+            let plot = Plot::new("rate function plot").legend(Legend::default());
+            plot.show(ui, |plot_ui| {
+                for (name, gate) in rate::GATES.iter().zip(&state.hh.params.kinetics.gates) {
+                    let g = gate.clone();
+                    plot_ui.line(
+                        Line::new(PlotPoints::from_explicit_callback(
+                            move |v| g.alpha(v),
+                            -50.0..150.0,
+                            200,
+                        ))
+                        .name(format!("α_{name}")),
+                    );
+                    let g = gate.clone();
+                    plot_ui.line(
+                        Line::new(PlotPoints::from_explicit_callback(
+                            move |v| g.beta(v),
+                            -50.0..150.0,
+                            200,
+                        ))
+                        .name(format!("β_{name}")),
+                    );
+                }
+            });
+        });
+
+        Window::new("First-order Kinetics").show(egui_ctx, |ui| {
+            ui.label("For a large collection of identical gates, define");
+            ui.label(RichText::new("τ_x = 1/(α_x + β_x)").font(FontId::proportional(20.0)));
+            ui.label(RichText::new("x_∞ = α_x/(α_x + β_x)").font(FontId::proportional(20.0)));
+            ui.label("Then gate dynamics follow,");
+            ui.label(RichText::new("τ_x dx/dt = -x + x_∞").font(FontId::proportional(20.0)));
+
+            {
+                let mut state = state.borrow_mut();
+                let state = &mut *state;
+                let params = &state.hh.params;
+                let built_in = params.custom.is_none() && params.mechanism.is_none();
+                if !built_in {
+                    ui.label(
+                        "Mutations modify the built-in gates, \
+                         so have no effect on a custom model or mechanism.",
+                    );
+                }
+                ui.add_enabled_ui(built_in && !state.hh.simulating(), |ui| {
+                    ui.collapsing("Mutation", |ui| {
+                        mutation_editor(ui, &mut state.hh.params.mutation);
+                    });
+                });
+            }
+
+            let (kinetics, mutation) = {
+                let params = &state.borrow().hh.params;
+                (params.kinetics.clone(), params.mutation.clone())
+            };
+            let plot = Plot::new("first-order kinetics plot")
+                .height(ui.available_height())
+                .legend(Legend::default());
+            plot.show(ui, |plot_ui| {
+                let gates = kinetics.gates.iter().zip(mutation.modifiers);
+                for (name, (gate, modifier)) in rate::GATES.iter().zip(gates) {
+                    let g = gate.clone();
+                    plot_ui.line(
+                        Line::new(PlotPoints::from_explicit_callback(
+                            move |v| g.tau(v),
+                            -150.0..100.0,
+                            250,
+                        ))
+                        .name(format!("τ_{name}")),
+                    );
+                    let g = gate.clone();
+                    plot_ui.line(
+                        Line::new(PlotPoints::from_explicit_callback(
+                            move |v| g.inf(v),
+                            -150.0..100.0,
+                            250,
+                        ))
+                        .name(format!("{name}_∞")),
+                    );
+                    if modifier == rate::Modifier::default() {
+                        continue;
+                    }
+                    let g = gate.clone();
+                    plot_ui.line(
+                        Line::new(PlotPoints::from_explicit_callback(
+                            move |v| modifier.tau(&g, v),
+                            -150.0..100.0,
+                            250,
+                        ))
+                        .style(egui_plot::LineStyle::dashed_loose())
+                        .name(format!("mutant τ_{name}")),
+                    );
+                    let g = gate.clone();
+                    plot_ui.line(
+                        Line::new(PlotPoints::from_explicit_callback(
+                            move |v| modifier.inf(&g, v),
+                            -150.0..100.0,
+                            250,
+                        ))
+                        .style(egui_plot::LineStyle::dashed_loose())
+                        .name(format!("mutant {name}_∞")),
+                    );
+                }
+            });
+        });
+
+        Window::new("Model Equations").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            ui.label(match &state.hh.params.custom {
+                Some(model) => format!(
+                    "Simulating custom model with states {}.",
+                    model.states().join(", ")
+                ),
+                None if state.hh.params.mechanism.is_some() => {
+                    "Simulating an NMODL mechanism, see Model Files.".to_string()
+                }
+                None => "Simulating built-in Hodgkin–Huxley equations.".to_string(),
+            });
+            ui.add_enabled_ui(!state.hh.simulating(), |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Compile and use").clicked() {
+                        match expr::compile(&state.ui.model.source) {
+                            Ok(model) => {
+                                state.hh.params.custom = Some(model);
+                                state.hh.params.mechanism = None;
+                                state.ui.model.error = None;
+                            }
+                            Err(e) => state.ui.model.error = Some(e.render(&state.ui.model.source)),
+                        }
+                    }
+                    if ui.button("Use built-in").clicked() {
+                        state.hh.params.custom = None;
+                    }
+                    if ui.button("Reset text").clicked() {
+                        state.ui.model.source = expr::HODGKIN_HUXLEY.to_string();
+                    }
+                });
+            });
+            if let Some(e) = &state.ui.model.error {
+                ui.label(
+                    RichText::new(e)
+                        .monospace()
+                        .color(ui.visuals().error_fg_color),
+                );
+            }
+            ui.label("Current, conductance and gate plots assume the built-in equations.");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.add(
+                    TextEdit::multiline(&mut state.ui.model.source)
+                        .code_editor()
+                        .desired_width(f32::INFINITY),
+                );
+            });
+        });
+
+        Window::new("Model Files").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let files = &mut state.ui.files;
+
+            ui.heading("NeuroML");
+            ui.horizontal(|ui| {
+                ui.label("Path");
+                ui.add(TextEdit::singleline(&mut files.neuroml_path).hint_text("channels.nml"));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Resting potential");
+                ui.add(
+                    DragValue::new(&mut files.v_rest)
+                        .range(-100.0..=0.0)
+                        .speed(0.5)
+                        .suffix(" mV"),
+                );
+            });
+            ui.horizontal(|ui| {
+                let path = files.neuroml_path.trim();
+                let import = ui.add_enabled(!state.hh.simulating(), egui::Button::new("Import"));
+                if import.clicked() {
+                    files.message = Some(
+                        std::fs::read_to_string(path)
+                            .map_err(|e| format!("{path}: {e}"))
+                            .and_then(|text| neuroml::import(&text, &state.hh.params, files.v_rest))
+                            .map(|(params, notes)| {
+                                state.hh.params = params;
+                                if notes.is_empty() {
+                                    format!("Imported {path}.")
+                                } else {
+                                    format!("Imported {path}:\n{}", notes.join("\n"))
+                                }
+                            }),
+                    );
+                }
+                if ui.button("Export").clicked() {
+                    files.message = Some(
+                        neuroml::export(&state.hh.params, files.v_rest)
+                            .and_then(|text| {
+                                std::fs::write(path, text).map_err(|e| format!("{path}: {e}"))
+                            })
+                            .map(|()| format!("Exported to {path}.")),
+                    );
+                }
+            });
+
+            ui.separator();
+            ui.heading("NMODL");
+            ui.label(match &state.hh.params.mechanism {
+                Some(mechanism) => format!(
+                    "Simulating mechanism `{}` with states {}.",
+                    mechanism.name,
+                    mechanism.states().join(", ")
+                ),
+                None => "No mechanism in use.".to_string(),
+            });
+            ui.horizontal(|ui| {
+                ui.label("Path");
+                ui.add(TextEdit::singleline(&mut files.nmodl_path).hint_text("hh.mod"));
+            });
+            ui.add_enabled_ui(!state.hh.simulating(), |ui| {
+                ui.horizontal(|ui| {
+                    let path = files.nmodl_path.trim();
+                    if ui.button("Compile and use").clicked() {
+                        files.message = Some(
+                            std::fs::read_to_string(path)
+                                .map_err(|e| format!("{path}: {e}"))
+                                .and_then(|text| {
+                                    nmodl::compile(&text, files.v_rest).map_err(|e| e.render(&text))
+                                })
+                                .map(|mechanism| {
+                                    state.hh.params.custom = None;
+                                    state.hh.params.mechanism = Some(mechanism);
+                                    format!("Compiled {path}.")
+                                }),
+                        );
+                    }
+                    if ui.button("Use built-in").clicked() {
+                        state.hh.params.mechanism = None;
+                    }
+                });
+            });
+
+            match &files.message {
+                Some(Ok(message)) => {
+                    ui.label(message);
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                }
+                None => {}
+            }
+        });
+
+        Window::new("Imported Traces").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            ui.horizontal(|ui| {
+                ui.label("Path");
+                ui.add(
+                    TextEdit::singleline(&mut state.ui.trace_path)
+                        .hint_text("recording.abf / .atf / .csv"),
+                );
+                if ui.button("Load").clicked() {
+                    match trace::load(state.ui.trace_path.trim()) {
+                        Ok(trace) => {
+                            state.ui.overlays.push(Overlay::new(trace));
+                            state.ui.trace_error = None;
+                        }
+                        Err(e) => state.ui.trace_error = Some(e),
+                    }
+                }
+            });
+            if let Some(e) = &state.ui.trace_error {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
+
+            let mut remove = None;
+            for (idx, overlay) in state.ui.overlays.iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut overlay.visible, &overlay.trace.name);
+                    ui.label(format!("{} sweep(s)", overlay.trace.sweeps.len()));
+                    if ui.button("Remove").clicked() {
+                        remove = Some(idx);
+                    }
+                });
+                Grid::new(("trace grid", idx))
+                    .num_columns(4)
+                    .spacing([20.0, 4.0])
+                    .show(ui, |ui| {
+                        ui.label("Time shift");
+                        ui.add(DragValue::new(&mut overlay.time_shift).speed(0.1));
+                        ui.label("Voltage offset");
+                        ui.add(DragValue::new(&mut overlay.v_offset).speed(0.5));
+                        ui.end_row();
+
+                        ui.label("Replay sweep");
+                        ui.add(
+                            DragValue::new(&mut overlay.replay_sweep)
+                                .range(0..=overlay.trace.sweeps.len().saturating_sub(1)),
+                        );
+                        ui.label(format!("μA/cm² per {}", overlay.trace.current_unit));
+                        ui.add(DragValue::new(&mut overlay.i_scale).speed(0.01));
+                        ui.end_row();
+                    });
+
+                let sweep = &overlay.trace.sweeps[overlay.replay_sweep];
+                let replay = ui.add_enabled(
+                    sweep.i.is_some() && !state.hh.simulating(),
+                    egui::Button::new("Use sweep current as stimulus"),
+                );
+                if let (true, Some(i)) = (replay.clicked(), &sweep.i) {
+                    state.hh.setup.replay = Some(hh::Waveform {
+                        t: sweep.t.iter().map(|t| t + overlay.time_shift).collect(),
+                        i: i.iter().map(|i| i * overlay.i_scale).collect(),
+                    });
+                    state.hh.setup.end = (sweep.duration() + overlay.time_shift).clamp(0.0, 200.0);
+                }
+            }
+            if let Some(idx) = remove {
+                state.ui.overlays.remove(idx);
+            }
+        });
+
+        Window::new("Parameter Fitting").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let fit = &mut state.ui.fit;
+
+            if state.ui.overlays.is_empty() {
+                ui.label("Import a trace to fit against.");
+                return;
+            }
+            let params = fit::Param::all(&state.hh.params);
+            if !params.iter().eq(fit.selected.iter().map(|(p, _)| p)) {
+                // gate families changed, keep what can be matched by name
+                fit.selected = params
+                    .into_iter()
+                    .map(|p| {
+                        let free = fit
+                            .selected
+                            .iter()
+                            .any(|(q, free)| *free && q.name() == p.name());
+                        (p, free)
+                    })
+                    .collect();
+            }
+            fit.overlay = fit.overlay.min(state.ui.overlays.len() - 1);
+            let overlay = &state.ui.overlays[fit.overlay];
+            fit.sweep = fit.sweep.min(overlay.trace.sweeps.len() - 1);
+
+            Grid::new("fit settings grid")
+                .num_columns(4)
+                .spacing([20.0, 4.0])
+                .show(ui, |ui| {
+                    ui.label("Target trace");
+                    ui.add(DragValue::new(&mut fit.overlay).range(0..=state.ui.overlays.len() - 1));
+                    ui.label("Sweep");
+                    ui.add(
+                        DragValue::new(&mut fit.sweep).range(0..=overlay.trace.sweeps.len() - 1),
+                    );
+                    ui.end_row();
+
+                    ui.label("Error");
+                    ui.selectable_value(
+                        &mut fit.objective,
+                        fit::Objective::VoltageRmse,
+                        "Voltage RMSE",
+                    );
+                    ui.selectable_value(
+                        &mut fit.objective,
+                        fit::Objective::SpikeFeatures,
+                        "Spike features",
+                    );
+                    ui.end_row();
+
+                    ui.label("Method");
+                    ui.selectable_value(&mut fit.method, fit::Method::NelderMead, "Nelder–Mead");
+                    ui.selectable_value(
+                        &mut fit.method,
+                        fit::Method::DifferentialEvolution,
+                        "Differential evolution",
+                    );
+                    ui.end_row();
+                });
+
+            ui.collapsing("Free parameters", |ui| {
+                Grid::new("fit parameter grid")
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        for (idx, (param, free)) in fit.selected.iter_mut().enumerate() {
+                            ui.checkbox(free, param.name());
+                            if idx % 3 == 2 {
+                                ui.end_row();
+                            }
+                        }
+                    });
+            });
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                let free: Vec<fit::Param> = fit
+                    .selected
+                    .iter()
+                    .filter(|(_, free)| *free)
+                    .map(|(p, _)| *p)
+                    .collect();
+                if ui
+                    .add_enabled(!free.is_empty(), egui::Button::new("Start"))
+                    .clicked()
+                {
+                    let sweep = &overlay.trace.sweeps[fit.sweep];
+                    let target = fit::Target {
+                        t: sweep.t.iter().map(|t| t + overlay.time_shift).collect(),
+                        v: sweep.v.iter().map(|v| v + overlay.v_offset).collect(),
+                    };
+                    fit.fitter = Some(fit::Fitter::new(
+                        &state.hh.setup,
+                        &state.hh.params,
+                        free,
+                        target,
+                        fit.objective,
+                        fit.method,
+                    ));
+                    fit.estimates.clear();
+                    fit.running = true;
+                }
+                let Some(fitter) = &fit.fitter else {
+                    return;
+                };
+                if ui
+                    .button(if fit.running { "Stop" } else { "Resume" })
+                    .clicked()
+                {
+                    fit.running = !fit.running;
+                }
+                if ui.button("Estimate confidence").clicked() {
+                    fit.running = false;
+                    fit.estimates = fitter.estimates();
+                }
+                if ui.button("Apply to model").clicked() {
+                    fitter.apply(&mut state.hh.params);
+                }
+            });
+
+            let Some(fitter) = &mut fit.fitter else {
+                return;
+            };
+            if fit.running {
+                let start = miniquad::date::now();
+                while miniquad::date::now() - start < FRAME_BUDGET {
+                    fitter.step();
+                }
+                ui.ctx().request_repaint();
+            }
+            ui.label(format!(
+                "{} iterations, {} simulations, best error {:.4}",
+                fitter.iterations,
+                fitter.evaluations,
+                fitter.best_cost()
+            ));
+
+            let plot_height = ui.available_height().min(600.0) * 0.4;
+            Plot::new("fit trace plot")
+                .height(plot_height)
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    let sweep = &overlay.trace.sweeps[fit.sweep];
+                    let x = visible_x(plot_ui);
+                    let target = decimated_line(plot_ui, x.clone(), sweep.t.len(), |k| {
+                        [
+                            sweep.t[k] + overlay.time_shift,
+                            sweep.v[k] + overlay.v_offset,
+                        ]
+                    });
+                    plot_ui.line(target.name("target"));
+
+                    let history = fitter.best_history();
+                    let best = decimated_line(plot_ui, x, history.len(), |k| {
+                        [k as f64 * fitter.dt(), history[k].v()]
+                    });
+                    plot_ui.line(best.name("best fit"));
+                });
+            Plot::new("fit progress plot")
+                .height(plot_height * 0.5)
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    let progress: Vec<[f64; 2]> = fitter
+                        .progress
+                        .iter()
+                        .enumerate()
+                        .map(|(k, cost)| [k as f64, cost.log10()])
+                        .collect();
+                    plot_ui.line(Line::new(PlotPoints::from(progress)).name("log₁₀ error"));
+                });
+
+            let best = fitter.best_params();
+            Grid::new("fit result grid")
+                .num_columns(4)
+                .spacing([20.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Parameter");
+                    ui.label("Initial");
+                    ui.label("Fitted");
+                    ui.label("95% CI");
+                    ui.end_row();
+
+                    for (param, free) in &fit.selected {
+                        if !free {
+                            continue;
+                        }
+                        let estimate = fit.estimates.iter().find(|e| e.param == *param);
+                        ui.label(param.name());
+                        ui.label(format!(
+                            "{:.4}",
+                            estimate.map_or(param.get(&state.hh.params), |e| e.initial)
+                        ));
+                        ui.label(format!(
+                            "{:.4}",
+                            estimate.map_or(param.get(&best), |e| e.value)
+                        ));
+                        ui.label(match estimate.map(|e| e.ci95) {
+                            Some(Some(ci)) => format!("± {ci:.4}"),
+                            Some(None) => "ill-conditioned".to_string(),
+                            None => String::new(),
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+
+        Window::new("Oscilloscope").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            ui.horizontal(|ui| {
+                if state.hh.live.is_none() {
+                    if ui.button("Start").clicked() {
+                        state.hh.start_live();
+                    }
+                } else if ui.button("Stop").clicked() {
+                    state.hh.live = None;
+                }
+                ui.label("Parameters can be changed while it runs.");
+            });
+            let Some(live) = &mut state.hh.live else {
+                return;
+            };
+
+            Grid::new("oscilloscope grid")
+                .num_columns(4)
+                .spacing([20.0, 4.0])
+                .show(ui, |ui| {
+                    ui.label("Speed");
+                    ui.add(
+                        DragValue::new(&mut live.speed)
+                            .range(0.1..=200.0)
+                            .speed(0.1)
+                            .suffix(" ms/s"),
+                    );
+                    ui.label("Window");
+                    ui.add(
+                        DragValue::new(&mut live.window)
+                            .range(1.0..=500.0)
+                            .speed(0.5)
+                            .suffix(" ms"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Baseline current");
+                    ui.add(egui::Slider::new(&mut live.baseline, -20.0..=20.0));
+                    ui.label("Pulse");
+                    ui.add(DragValue::new(&mut live.pulse).range(-50.0..=50.0));
+                    ui.end_row();
+                });
+            let inject = ui
+                .button("Hold to inject (or hold Space)")
+                .is_pointer_button_down_on();
+            live.held = inject || ui.input(|i| i.key_down(egui::Key::Space));
+
+            live.run(&state.hh.params, state.hh.setup.dt, miniquad::date::now());
+
+            let (end, window) = (live.time(), live.window);
+            // the bounds are set below, so the previous frame's are already out of date
+            let line = |plot_ui: &PlotUi, extract: &dyn Fn(&hh::LiveSample) -> Float| {
+                decimated_line(plot_ui, end - window..=end, live.samples.len(), |k| {
+                    let s = &live.samples[k];
+                    [s.t, extract(s)]
+                })
+            };
+            let scope = |id: &str, height: f32| {
+                Plot::new(id)
+                    .link_axis("oscilloscope", true, false)
+                    .link_cursor("oscilloscope", true, false)
+                    .height(height)
+                    .legend(Legend::default())
+                    .allow_drag(false)
+                    .allow_zoom(false)
+            };
+            let height = ui.available_height();
+            scope("oscilloscope current", height * 0.15).show(ui, |plot_ui| {
+                plot_ui.set_plot_bounds(egui_plot::PlotBounds::from_min_max(
+                    [end - window, -20.0],
+                    [end, 20.0],
+                ));
+                plot_ui.line(line(plot_ui, &|s| s.i).name("injected current"));
+            });
+            scope("oscilloscope voltage", height * 0.45).show(ui, |plot_ui| {
+                plot_ui.set_plot_bounds(egui_plot::PlotBounds::from_min_max(
+                    [end - window, -20.0],
+                    [end, 120.0],
+                ));
+                plot_ui.line(line(plot_ui, &|s| s.axon.v()).name("voltage"));
+            });
+            scope("oscilloscope gates", ui.available_height()).show(ui, |plot_ui| {
+                plot_ui.set_plot_bounds(egui_plot::PlotBounds::from_min_max(
+                    [end - window, 0.0],
+                    [end, 1.0],
+                ));
+                plot_ui.line(line(plot_ui, &|s| s.axon.m()).name("m"));
+                plot_ui.line(line(plot_ui, &|s| s.axon.h()).name("h"));
+                plot_ui.line(line(plot_ui, &|s| s.axon.n()).name("n"));
+            });
+        });
+
+        Window::new("Parameter Sweep").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let sweep_ui = &mut state.ui.sweep;
+
+            Grid::new("sweep settings grid")
+                .num_columns(8)
+                .spacing([10.0, 4.0])
+                .show(ui, |ui| {
+                    axis_editor(ui, "X", &mut sweep_ui.x);
+                    ui.add_enabled_ui(sweep_ui.two_d, |ui| {
+                        axis_editor(ui, "Y", &mut sweep_ui.y);
+                    });
+                });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut sweep_ui.two_d, "Sweep a second parameter");
+                ui.label("Metric");
+                egui::ComboBox::from_id_source("sweep metric")
+                    .selected_text(sweep_ui.metric.name())
+                    .show_ui(ui, |ui| {
+                        for metric in sweep::Metric::ALL {
+                            ui.selectable_value(&mut sweep_ui.metric, metric, metric.name());
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Start").clicked() {
+                    sweep_ui.sweep = Some(sweep::Sweep::start(sweep::Plan {
+                        x: sweep_ui.x.clone(),
+                        y: sweep_ui.two_d.then(|| sweep_ui.y.clone()),
+                        metric: sweep_ui.metric,
+                        setup: state.hh.setup.clone(),
+                        params: state.hh.params.clone(),
+                    }));
+                }
+                let running = sweep_ui.sweep.as_ref().is_some_and(|s| !s.finished());
+                if ui
+                    .add_enabled(running, egui::Button::new("Cancel"))
+                    .clicked()
+                {
+                    sweep_ui.sweep = None;
+                }
+                if let Some(sweep) = &sweep_ui.sweep {
+                    let progress = sweep.done as f32 / sweep.plan.len() as f32;
+                    ui.add(ProgressBar::new(progress).show_percentage());
+                }
+            });
+
+            let Some(sweep) = &mut sweep_ui.sweep else {
+                return;
+            };
+            if !sweep.finished() {
+                sweep.step();
+                ui.ctx().request_repaint();
+            }
+            let plan = &sweep.plan;
+            let range = sweep.range();
+            if let Some((lo, hi)) = range {
+                ui.label(format!(
+                    "{} from {lo:.3} to {hi:.3}. Click a point to open its run.",
+                    plan.metric.name()
+                ));
+            }
+
+            let response = Plot::new("sweep plot")
+                .height(ui.available_height().max(200.0))
+                .allow_drag(false)
+                .show(ui, |plot_ui| {
+                    match &plan.y {
+                        None => {
+                            let points: Vec<[f64; 2]> = (0..plan.len())
+                                .filter_map(|ix| Some([plan.x.value(ix), sweep.values[ix]?]))
+                                .collect();
+                            plot_ui.line(Line::new(PlotPoints::from(points.clone())));
+                            plot_ui.points(
+                                egui_plot::Points::new(PlotPoints::from(points)).radius(3.0),
+                            );
+                        }
+                        Some(y) => {
+                            let (lo, hi) = range.unwrap_or((0.0, 1.0));
+                            let (hx, hy) = (plan.x.step() / 2.0, y.step() / 2.0);
+                            for (idx, value) in sweep.values.iter().enumerate() {
+                                let Some(value) = value else {
+                                    continue;
+                                };
+                                let (cx, cy) = (
+                                    plan.x.value(idx % plan.x.points),
+                                    y.value(idx / plan.x.points),
+                                );
+                                let u = if hi > lo {
+                                    (value - lo) / (hi - lo)
+                                } else {
+                                    0.5
+                                };
+                                let cell = vec![
+                                    [cx - hx, cy - hy],
+                                    [cx + hx, cy - hy],
+                                    [cx + hx, cy + hy],
+                                    [cx - hx, cy + hy],
+                                ];
+                                plot_ui.polygon(
+                                    egui_plot::Polygon::new(PlotPoints::from(cell))
+                                        .fill_color(colormap(u))
+                                        .stroke(egui::Stroke::NONE)
+                                        .allow_hover(false),
+                                );
+                            }
+                        }
+                    }
+                    if plot_ui.response().clicked() {
+                        plot_ui.pointer_coordinate().and_then(|p| plan.at(p.x, p.y))
+                    } else {
+                        None
+                    }
+                });
+            ui.label(match &plan.y {
+                None => format!("x: {}", plan.x.knob.name()),
+                Some(y) => format!("x: {}, y: {}", plan.x.knob.name(), y.knob.name()),
+            });
+            if let Some(idx) = response.inner
+                && !state.hh.simulating()
+            {
+                (state.hh.setup, state.hh.params) = plan.point(idx);
+                state.ui.run.error = state.hh.init().err();
+            }
+        });
+
+        Window::new("Strength–Duration").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let sd = &mut state.ui.strength;
+
+            Grid::new("strength duration grid")
+                .num_columns(6)
+                .spacing([10.0, 4.0])
+                .show(ui, |ui| {
+                    ui.label("Durations from");
+                    ui.add(
+                        DragValue::new(&mut sd.from)
+                            .range(0.001..=sd.to)
+                            .speed(0.01)
+                            .suffix(" ms"),
+                    );
+                    ui.label("to");
+                    ui.add(
+                        DragValue::new(&mut sd.to)
+                            .range(sd.from..=100.0)
+                            .speed(0.1)
+                            .suffix(" ms"),
+                    );
+                    ui.add(
+                        DragValue::new(&mut sd.points)
+                            .range(2..=50)
+                            .suffix(" points"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Stimulation");
+                    for polarity in [
+                        threshold::Polarity::Depolarizing,
+                        threshold::Polarity::Hyperpolarizing,
+                    ] {
+                        ui.selectable_value(&mut sd.polarity, polarity, polarity.name());
+                    }
+                    ui.label("up to");
+                    ui.add(
+                        DragValue::new(&mut sd.max)
+                            .range(1.0..=2000.0)
+                            .suffix(" μA/cm²"),
+                    );
+                    ui.end_row();
+                });
+            ui.horizontal(|ui| {
+                if ui.button("Find thresholds").clicked() {
+                    sd.search = Some(threshold::StrengthDuration::new(
+                        &state.hh.setup,
+                        &state.hh.params,
+                        sd.polarity,
+                        sd.max,
+                        threshold::log_spaced(sd.from, sd.to, sd.points),
+                    ));
+                }
+                if let Some(search) = &sd.search {
+                    let progress = search.thresholds.len() as f32 / search.durations.len() as f32;
+                    ui.add(ProgressBar::new(progress).show_percentage());
+                }
+            });
+
+            let Some(search) = &mut sd.search else {
+                return;
+            };
+            if !search.finished() {
+                let start = miniquad::date::now();
+                while !search.finished() && miniquad::date::now() - start < FRAME_BUDGET {
+                    search.step();
+                }
+                ui.ctx().request_repaint();
+            }
+            let (durations, thresholds) = search.found();
+            let missed = search.thresholds.iter().filter(|t| t.is_none()).count();
+            if missed > 0 {
+                ui.label(format!(
+                    "{missed} duration(s) did not fire up to {} μA/cm².",
+                    search.max
+                ));
+            }
+            let fits: Vec<threshold::Fit> = [threshold::Law::Lapicque, threshold::Law::Weiss]
+                .iter()
+                .filter_map(|law| law.fit(&durations, &thresholds))
+                .collect();
+            Grid::new("strength duration fits")
+                .num_columns(4)
+                .spacing([20.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Law");
+                    ui.label("Rheobase");
+                    ui.label("Chronaxie");
+                    ui.label("RMS relative error");
+                    ui.end_row();
+                    for fit in &fits {
+                        ui.label(fit.law.name());
+                        ui.label(format!("{:.3} μA/cm²", fit.rheobase));
+                        ui.label(format!("{:.3} ms", fit.chronaxie));
+                        ui.label(format!("{:.2}%", fit.error * 100.0));
+                        ui.end_row();
+                    }
+                });
+
+            Plot::new("strength duration plot")
+                .height(ui.available_height().max(200.0))
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    let measured: Vec<[f64; 2]> = durations
+                        .iter()
+                        .zip(&thresholds)
+                        .map(|(&d, &i)| [d, i])
+                        .collect();
+                    plot_ui.points(
+                        egui_plot::Points::new(PlotPoints::from(measured))
+                            .radius(3.0)
+                            .name("threshold"),
+                    );
+                    let (first, last) = (
+                        search.durations[0],
+                        search.durations[search.durations.len() - 1],
+                    );
+                    let curve = threshold::log_spaced(first, last, 200);
+                    for fit in &fits {
+                        let points: Vec<[f64; 2]> =
+                            curve.iter().map(|&d| [d, fit.threshold(d)]).collect();
+                        plot_ui.line(Line::new(PlotPoints::from(points)).name(fit.law.name()));
+                    }
+                    if let Some(fit) = fits.first() {
+                        plot_ui.hline(egui_plot::HLine::new(fit.rheobase).name("rheobase"));
+                        plot_ui.vline(egui_plot::VLine::new(fit.chronaxie).name("chronaxie"));
+                    }
+                });
+        });
+
+        Window::new("Refractory Period").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let rp = &mut state.ui.refractory;
+
+            ui.label("The simulation pulse conditions; a second pulse tests the threshold.");
+            Grid::new("refractory grid")
+                .num_columns(6)
+                .spacing([10.0, 4.0])
+                .show(ui, |ui| {
+                    ui.label("Intervals from");
+                    ui.add(
+                        DragValue::new(&mut rp.from)
+                            .range(0.1..=rp.to)
+                            .speed(0.1)
+                            .suffix(" ms"),
+                    );
+                    ui.label("to");
+                    ui.add(
+                        DragValue::new(&mut rp.to)
+                            .range(rp.from..=100.0)
+                            .speed(0.1)
+                            .suffix(" ms"),
+                    );
+                    ui.add(
+                        DragValue::new(&mut rp.points)
+                            .range(2..=100)
+                            .suffix(" points"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Test pulse");
+                    ui.add(
+                        DragValue::new(&mut rp.test_duration)
+                            .range(0.01..=10.0)
+                            .speed(0.01)
+                            .suffix(" ms"),
+                    );
+                    ui.label("up to");
+                    ui.add(
+                        DragValue::new(&mut rp.max)
+                            .range(1.0..=2000.0)
+                            .suffix(" μA/cm²"),
+                    );
+                    ui.end_row();
+                });
+            ui.horizontal(|ui| {
+                if ui.button("Measure").clicked() {
+                    let step = (rp.to - rp.from) / (rp.points - 1) as Float;
+                    let intervals = (0..rp.points)
+                        .map(|k| rp.from + step * k as Float)
+                        .collect();
+                    rp.protocol = Some(threshold::PairedPulse::new(
+                        &state.hh.setup,
+                        &state.hh.params,
+                        rp.test_duration,
+                        rp.max,
+                        intervals,
+                    ));
+                }
+                if let Some(protocol) = &rp.protocol {
+                    ui.add(ProgressBar::new(protocol.progress() as f32).show_percentage());
+                }
+            });
+
+            let Some(protocol) = &mut rp.protocol else {
+                return;
+            };
+            if !protocol.finished() {
+                let start = miniquad::date::now();
+                while !protocol.finished() && miniquad::date::now() - start < FRAME_BUDGET {
+                    protocol.step();
+                }
+                ui.ctx().request_repaint();
+            }
+            if !protocol.has_control() {
+                return;
+            }
+            if protocol.conditioning_spikes == 0 {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    "The conditioning pulse does not fire; strengthen the simulation pulse.",
+                );
+            }
+            match protocol.control {
+                Some(control) => ui.label(format!("Test pulse alone: {control:.3} μA/cm²")),
+                None => ui.label("The test pulse alone does not fire."),
+            };
+            if let Some((below, above)) = protocol.absolute_refractory() {
+                ui.label(format!(
+                    "Absolute refractory period: between {below:.2} and {above:.2} ms"
+                ));
+            }
+            if let Some(tau) = protocol.recovery_tau() {
+                ui.label(format!("Recovery time constant: {tau:.2} ms"));
+            }
+
+            let found: Vec<[f64; 2]> = protocol
+                .intervals
+                .iter()
+                .zip(&protocol.thresholds)
+                .filter_map(|(&d, &theta)| Some([d, theta?]))
+                .collect();
+            let height = ui.available_height().max(300.0);
+            Plot::new("refractory threshold plot")
+                .height(height * 0.5)
+                .link_axis("refractory", true, false)
+                .link_cursor("refractory", true, false)
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(PlotPoints::from(found.clone())).name("threshold"));
+                    plot_ui.points(
+                        egui_plot::Points::new(PlotPoints::from(found.clone())).radius(3.0),
+                    );
+                    if let Some(control) = protocol.control {
+                        plot_ui.hline(egui_plot::HLine::new(control).name("test pulse alone"));
+                    }
+                });
+            Plot::new("refractory gate plot")
+                .height(height * 0.5)
+                .link_axis("refractory", true, false)
+                .link_cursor("refractory", true, false)
+                .include_y(0.0)
+                .include_y(1.0)
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    if let Some(control) = protocol.control {
+                        let excitability: Vec<[f64; 2]> = found
+                            .iter()
+                            .map(|&[d, theta]| [d, control / theta])
+                            .collect();
+                        plot_ui.line(
+                            Line::new(PlotPoints::from(excitability))
+                                .name("excitability (alone / paired threshold)"),
+                        );
+                    }
+                    let dt = protocol.setup.dt;
+                    let history = &protocol.conditioning;
+                    let x = visible_x(plot_ui);
+                    let h = decimated_line(plot_ui, x.clone(), history.len(), |k| {
+                        [k as f64 * dt, history[k].h()]
+                    });
+                    plot_ui.line(h.name("h"));
+                    let n = decimated_line(plot_ui, x, history.len(), |k| {
+                        [k as f64 * dt, history[k].n()]
+                    });
+                    plot_ui.line(n.name("n"));
+                });
+        });
+
+        Window::new("Network").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let net = &mut state.ui.network;
+
+            ui.horizontal(|ui| {
+                ui.label("Presets");
+                for preset in network::Preset::ALL {
+                    if ui.button(preset.name()).clicked() {
+                        net.network = network::Network::preset(preset);
+                    }
+                }
+            });
+            ui.collapsing("Edit network", |ui| network_editor(ui, &mut net.network));
+            if let Some(gap) = net.network.gaps.first_mut() {
+                ui.horizontal(|ui| {
+                    ui.label("Coupling");
+                    let slider = ui.add(
+                        egui::Slider::new(&mut gap.conductance, 0.0..=2.0)
+                            .logarithmic(true)
+                            .smallest_positive(1e-3)
+                            .suffix(" mS/cm²"),
+                    );
+                    let (params, dt) = (&state.hh.params, state.hh.setup.dt);
+                    let current = net.coupling.as_ref().is_some_and(|c| {
+                        c.conductance == gap.conductance && c.dt == dt && c.params == *params
+                    });
+                    // each coefficient is a simulation, so wait for the slider to be let go
+                    if !current && !slider.dragged() {
+                        net.coupling = Some(Coupling {
+                            conductance: gap.conductance,
+                            dt,
+                            params: params.clone(),
+                            coefficient: network::coupling_coefficient(params, gap.conductance, dt),
+                        });
+                    }
+                    if let Some(coupling) = &net.coupling {
+                        ui.label(format!("coupling coefficient {:.3}", coupling.coefficient));
+                    }
+                });
+            }
+            ui.label(
+                "Reversal potentials are relative to rest; neurons share the model parameters.",
+            );
+            ui.horizontal(|ui| {
+                ui.label("Duration");
+                ui.add(
+                    DragValue::new(&mut net.end)
+                        .range(1.0..=5000.0)
+                        .speed(1.0)
+                        .suffix(" ms"),
+                );
+                if ui.button("Simulate").clicked() {
+                    net.sim = Some(network::Simulation::new(
+                        net.network.clone(),
+                        state.hh.params.clone(),
+                        state.hh.setup.dt,
+                        net.end,
+                    ));
+                }
+                if let Some(sim) = &net.sim {
+                    let progress = sim.steps_done as f32 / sim.total_steps() as f32;
+                    ui.add(ProgressBar::new(progress).show_percentage());
+                }
+            });
+
+            let Some(sim) = &mut net.sim else {
+                return;
+            };
+            if !sim.finished() {
+                let start = miniquad::date::now();
+                while !sim.finished() && miniquad::date::now() - start < FRAME_BUDGET {
+                    sim.advance(100);
+                }
+                ui.ctx().request_repaint();
+            }
 
-#[derive(Default)]
-struct State {
-    ui: UiState,
-    hh: hh::State,
-}
+            let neurons = &sim.network.neurons;
+            ui.horizontal_wrapped(|ui| {
+                for (idx, neuron) in neurons.iter().enumerate() {
+                    ui.label(format!(
+                        "{}: {} spikes",
+                        neuron.name,
+                        sim.spike_times(idx).len()
+                    ));
+                }
+                for gap in &sim.network.gaps {
+                    let (a, b) = (sim.spike_times(gap.a), sim.spike_times(gap.b));
+                    ui.label(format!(
+                        "{} and {} spike within 1 ms of each other {:.0}% of the time",
+                        neurons[gap.a].name,
+                        neurons[gap.b].name,
+                        network::synchrony(&a, &b, 1.0) * 100.0
+                    ));
+                }
+            });
+            let height = ui.available_height().max(300.0);
+            Plot::new("network raster plot")
+                .height(height * 0.3)
+                .link_axis("network", true, false)
+                .link_cursor("network", true, false)
+                .include_x(0.0)
+                .include_x(sim.end)
+                .include_y(-0.5)
+                .include_y(neurons.len() as f64 - 0.5)
+                .y_axis_formatter(|mark, _| {
+                    let idx = mark.value.round();
+                    if (mark.value - idx).abs() < 1e-6 && idx >= 0.0 {
+                        neurons
+                            .get(idx as usize)
+                            .map_or(String::new(), |n| n.name.clone())
+                    } else {
+                        String::new()
+                    }
+                })
+                .show(ui, |plot_ui| {
+                    let points: Vec<[f64; 2]> =
+                        sim.spikes.iter().map(|&(t, n)| [t, n as f64]).collect();
+                    plot_ui.points(
+                        egui_plot::Points::new(PlotPoints::from(points))
+                            .shape(egui_plot::MarkerShape::Diamond)
+                            .radius(3.0),
+                    );
+                });
+            Plot::new("network voltage plot")
+                .height(height * 0.7)
+                .link_axis("network", true, false)
+                .link_cursor("network", true, false)
+                .include_x(0.0)
+                .include_x(sim.end)
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    let dt = sim.dt;
+                    for (neuron, v) in neurons.iter().zip(&sim.v) {
+                        let line = decimated_line(plot_ui, visible_x(plot_ui), v.len(), |k| {
+                            [k as f64 * dt, v[k]]
+                        });
+                        plot_ui.line(line.name(&neuron.name));
+                    }
+                });
+        });
 
-fn main() {
-    let state = Rc::<RefCell<State>>::new(RefCell::new(State::default()));
+        Window::new("Energy per Spike").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let energy_ui = &mut state.ui.energy;
 
-    let app = move |_mq_ctx: &mut dyn miniquad::RenderingBackend, egui_ctx: &egui::Context| {
-        Window::new("Console").title_bar(true).show(egui_ctx, |ui| {
-            Grid::new("console grid")
-                .num_columns(2)
-                .spacing([40.0, 4.0])
-                .striped(true)
+            ui.label(format!(
+                "Charges in nC/cm², ATP at {} Na⁺ extruded per molecule.",
+                energy::NA_PER_ATP
+            ));
+            let params = &state.hh.params;
+            if params.custom.is_some() || params.mechanism.is_some() {
+                ui.label(
+                    "Costs are of the built-in sodium and potassium currents, \
+                     so not of a custom model or mechanism.",
+                );
+                return;
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Analyse the setup").clicked() {
+                    energy_ui.running = Some(energy::Analysis::new(&state.hh.setup, params));
+                }
+                if let Some(analysis) = &energy_ui.running {
+                    ui.add(ProgressBar::new(analysis.progress() as f32).show_percentage());
+                }
+            });
+            if let Some(analysis) = &mut energy_ui.running {
+                let start = miniquad::date::now();
+                while !analysis.finished() && miniquad::date::now() - start < FRAME_BUDGET {
+                    analysis.step();
+                }
+                if analysis.finished() {
+                    energy_ui.costs = analysis.costs().unwrap_or_default();
+                    energy_ui.running = None;
+                }
+                ui.ctx().request_repaint();
+            }
+            if energy_ui.costs.is_empty() {
+                ui.label("No spikes analysed.");
+            } else {
+                Grid::new("spike cost grid")
+                    .num_columns(8)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for header in [
+                            "Spike at",
+                            "Q_Na",
+                            "Q_K",
+                            "Q_min = C ΔV",
+                            "Na/K overlap",
+                            "Excess ratio",
+                            "ATP per cm²",
+                            "ATP per µm²",
+                        ] {
+                            ui.label(RichText::new(header).strong());
+                        }
+                        ui.end_row();
+                        for cost in &energy_ui.costs {
+                            ui.label(format!("{:.2} ms", cost.t));
+                            ui.label(format!("{:.1}", cost.na));
+                            ui.label(format!("{:.1}", cost.k));
+                            ui.label(format!("{:.1}", cost.min));
+                            ui.label(format!("{:.1}", cost.overlap));
+                            ui.label(format!("{:.2}", cost.excess_ratio()));
+                            ui.label(format!("{:.3e}", cost.atp()));
+                            ui.label(format!("{:.3e}", cost.atp() * 1e-8));
+                            ui.end_row();
+                        }
+                    });
+            }
+            ui.separator();
+
+            ui.label(RichText::new("Comparison").strong());
+            Grid::new("energy axis grid")
+                .num_columns(8)
+                .spacing([10.0, 4.0])
                 .show(ui, |ui| {
-                    ui.label("Theme");
-                    widgets::global_dark_light_mode_buttons(ui);
-                    ui.end_row();
+                    axis_editor(ui, "Vary", &mut energy_ui.axis);
+                });
+            ui.horizontal(|ui| {
+                if ui.button("Compare along the axis").clicked() {
+                    let axis = &energy_ui.axis;
+                    energy_ui.rows = (0..axis.points)
+                        .map(|idx| {
+                            let x = axis.value(idx);
+                            let (mut setup, mut params) =
+                                (state.hh.setup.clone(), state.hh.params.clone());
+                            axis.knob.set(&mut setup, &mut params, x);
+                            EnergyRow {
+                                name: format!("{} = {x:.3}", axis.knob.name()),
+                                x: Some(x),
+                                run: (setup, params),
+                                summary: None,
+                            }
+                        })
+                        .collect();
+                }
+                if ui
+                    .add_enabled(
+                        !state.hh.runs.list.is_empty(),
+                        egui::Button::new("Compare kept runs"),
+                    )
+                    .clicked()
+                {
+                    energy_ui.rows = state
+                        .hh
+                        .runs
+                        .list
+                        .iter()
+                        .map(|run| EnergyRow {
+                            name: run.name.clone(),
+                            x: None,
+                            run: (run.setup.clone(), run.params.clone()),
+                            summary: None,
+                        })
+                        .collect();
+                }
 
-                    ui.label("Source code");
-                    ui.hyperlink_to(
-                        format!("{0} Open on GitHub", egui::special_emojis::GITHUB),
-                        "https://github.com/Richardn2002/hodgkin-huxley-playground",
+                // one run at a time, as many as fit in the frame
+                let done = energy_ui
+                    .rows
+                    .iter()
+                    .filter(|r| r.summary.is_some())
+                    .count();
+                if done < energy_ui.rows.len() {
+                    let progress = done as f32 / energy_ui.rows.len() as f32;
+                    ui.add(ProgressBar::new(progress).show_percentage());
+                    let start = miniquad::date::now();
+                    for row in energy_ui.rows.iter_mut().filter(|r| r.summary.is_none()) {
+                        if miniquad::date::now() - start >= FRAME_BUDGET {
+                            break;
+                        }
+                        let (setup, params) = &row.run;
+                        row.summary = Some(energy::Summary::of(setup, params));
+                    }
+                    ui.ctx().request_repaint();
+                }
+            });
+            if !energy_ui.rows.is_empty() {
+                Grid::new("energy comparison grid")
+                    .num_columns(6)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for header in [
+                            "",
+                            "Spikes",
+                            "Q_Na per spike",
+                            "Q_K per spike",
+                            "Excess ratio",
+                            "ATP per spike per cm²",
+                        ] {
+                            ui.label(RichText::new(header).strong());
+                        }
+                        ui.end_row();
+                        for row in &energy_ui.rows {
+                            ui.label(&row.name);
+                            let Some(summary) = &row.summary else {
+                                ui.label("…");
+                                ui.end_row();
+                                continue;
+                            };
+                            ui.label(summary.spikes.to_string());
+                            ui.label(format!("{:.1}", summary.na));
+                            ui.label(format!("{:.1}", summary.k));
+                            ui.label(format!("{:.2}", summary.excess_ratio));
+                            ui.label(format!("{:.3e}", summary.atp));
+                            ui.end_row();
+                        }
+                    });
+            }
+            if energy_ui.rows.iter().all(|row| row.x.is_some()) && energy_ui.rows.len() > 1 {
+                let points: Vec<[f64; 2]> = energy_ui
+                    .rows
+                    .iter()
+                    .filter_map(|row| Some([row.x?, row.summary?.excess_ratio]))
+                    .filter(|[_, y]| y.is_finite())
+                    .collect();
+                Plot::new("energy comparison plot")
+                    .height(ui.available_height().max(150.0))
+                    .legend(Legend::default())
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(Line::new(points).name("excess ratio"));
+                    });
+            }
+        });
+
+        Window::new("Phase Response").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let prc_ui = &mut state.ui.prc;
+
+            ui.label("A constant current drives tonic firing; a brief pulse perturbs each phase.");
+            Grid::new("prc grid")
+                .num_columns(6)
+                .spacing([10.0, 4.0])
+                .show(ui, |ui| {
+                    ui.label("Drive");
+                    ui.add(
+                        DragValue::new(&mut prc_ui.drive)
+                            .range(0.0..=200.0)
+                            .speed(0.1)
+                            .suffix(" μA/cm²"),
                     );
+                    ui.label("Phases");
+                    ui.add(DragValue::new(&mut prc_ui.phases).range(2..=500));
                     ui.end_row();
 
-                    ui.label("Hint");
-                    ui.label("Double click on plot to reset axes.");
+                    ui.label("Perturbation");
+                    ui.add(
+                        DragValue::new(&mut prc_ui.perturbation.amplitude)
+                            .range(-100.0..=100.0)
+                            .speed(0.1)
+                            .suffix(" μA/cm²"),
+                    );
+                    ui.label("for");
+                    ui.add(
+                        DragValue::new(&mut prc_ui.perturbation.duration)
+                            .range(0.01..=10.0)
+                            .speed(0.01)
+                            .suffix(" ms"),
+                    );
                     ui.end_row();
+                });
+            ui.horizontal(|ui| {
+                if ui.button("Measure").clicked() {
+                    let (params, dt) = (&state.hh.params, state.hh.setup.dt);
+                    prc_ui.search = Some(prc::Search::new(params, prc_ui.drive, dt));
+                    prc_ui.direct = None;
+                    prc_ui.adjoint.clear();
+                    prc_ui.error = None;
+                }
+                if let Some(search) = &prc_ui.search {
+                    ui.label("Finding the limit cycle");
+                    ui.add(ProgressBar::new(search.progress() as f32).show_percentage());
+                } else if let Some(direct) = &prc_ui.direct {
+                    let progress = direct.shifts.len() as f32 / direct.samples.len() as f32;
+                    ui.add(ProgressBar::new(progress).show_percentage());
+                }
+            });
+            if let Some(e) = &prc_ui.error {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
 
-                    ui.label("Powered by");
-                    ui.hyperlink_to("Rust", "https://www.rust-lang.org/");
-                    ui.end_row();
-                    ui.label("");
-                    ui.hyperlink_to("egui", "https://github.com/emilk/egui");
-                    ui.end_row();
-                    ui.label("");
-                    ui.hyperlink_to("miniquad", "https://github.com/not-fl3/miniquad");
+            if let Some(search) = &mut prc_ui.search {
+                let start = miniquad::date::now();
+                while !search.finished() && miniquad::date::now() - start < FRAME_BUDGET {
+                    search.step();
+                }
+                ui.ctx().request_repaint();
+                if !search.finished() {
+                    return;
+                }
+                let params = &search.params;
+                match search.cycle() {
+                    Some(cycle) => {
+                        let scale = prc_ui.perturbation.kick(params) / cycle.period;
+                        prc_ui.adjoint = prc::adjoint(cycle, params)
+                            .iter()
+                            .enumerate()
+                            .map(|(idx, z)| [cycle.phase(idx), z * scale])
+                            .collect();
+                        prc_ui.direct = Some(prc::Direct::new(
+                            cycle.clone(),
+                            params,
+                            prc_ui.perturbation,
+                            prc_ui.phases,
+                        ));
+                    }
+                    None => {
+                        prc_ui.error =
+                            Some("The drive does not settle into tonic firing.".to_string());
+                    }
+                }
+                prc_ui.search = None;
+            }
+            let Some(direct) = &mut prc_ui.direct else {
+                return;
+            };
+            if !direct.finished() {
+                let start = miniquad::date::now();
+                while !direct.finished() && miniquad::date::now() - start < FRAME_BUDGET {
+                    direct.step();
+                }
+                ui.ctx().request_repaint();
+            }
+            ui.label(format!(
+                "Period {:.3} ms, {:.1} Hz",
+                direct.cycle.period,
+                1e3 / direct.cycle.period
+            ));
+
+            let curve = direct.curve();
+            Plot::new("prc plot")
+                .height(ui.available_height().max(200.0))
+                .include_x(0.0)
+                .include_x(1.0)
+                .x_axis_label("phase")
+                .y_axis_label("phase advance")
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(PlotPoints::from(curve.clone())).name("direct"));
+                    plot_ui.points(egui_plot::Points::new(PlotPoints::from(curve)).radius(2.5));
+                    plot_ui.line(
+                        Line::new(PlotPoints::from(prc_ui.adjoint.clone()))
+                            .style(egui_plot::LineStyle::dashed_loose())
+                            .name("adjoint iPRC × pulse charge"),
+                    );
+                });
+        });
+
+        Window::new("Impedance").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let imp = &mut state.ui.impedance;
+
+            Grid::new("impedance grid")
+                .num_columns(8)
+                .spacing([10.0, 4.0])
+                .show(ui, |ui| {
+                    let chirp = &mut imp.chirp;
+                    ui.label("ZAP from");
+                    ui.add(
+                        DragValue::new(&mut chirp.from)
+                            .range(0.1..=chirp.to)
+                            .speed(0.5)
+                            .suffix(" Hz"),
+                    );
+                    ui.label("to");
+                    ui.add(
+                        DragValue::new(&mut chirp.to)
+                            .range(chirp.from..=2000.0)
+                            .speed(1.0)
+                            .suffix(" Hz"),
+                    );
+                    ui.label("over");
+                    ui.add(
+                        DragValue::new(&mut chirp.duration)
+                            .range(10.0..=20000.0)
+                            .speed(10.0)
+                            .suffix(" ms"),
+                    );
+                    ui.label("amplitude");
+                    ui.add(
+                        DragValue::new(&mut chirp.amplitude)
+                            .range(0.0..=20.0)
+                            .speed(0.01)
+                            .suffix(" μA/cm²"),
+                    );
                     ui.end_row();
-                })
+                });
+            ui.horizontal(|ui| {
+                if ui.button("Compute").clicked() {
+                    let (params, dt) = (&state.hh.params, state.hh.setup.dt);
+                    imp.running = Some(impedance::Zap::new(params, &imp.chirp, dt));
+                }
+                if let Some(zap) = &imp.running {
+                    ui.add(ProgressBar::new(zap.progress() as f32).show_percentage());
+                }
+            });
+            if let Some(zap) = &mut imp.running {
+                let start = miniquad::date::now();
+                while !zap.finished() && miniquad::date::now() - start < FRAME_BUDGET {
+                    zap.step();
+                }
+                if zap.finished() {
+                    imp.zap = zap.points().to_vec();
+                    let frequencies: Vec<Float> = imp.zap.iter().map(|p| p.f).collect();
+                    imp.linear = impedance::linear(zap.params(), &frequencies);
+                    imp.running = None;
+                }
+                ui.ctx().request_repaint();
+            }
+            if imp.zap.is_empty() {
+                return;
+            }
+
+            for (name, points) in [("ZAP", Some(&imp.zap)), ("Linearised", imp.linear.as_ref())] {
+                match points.and_then(|p| impedance::Resonance::of(p)) {
+                    Some(r) => ui.label(format!(
+                        "{name}: resonance at {:.1} Hz, Q = {:.2}",
+                        r.frequency, r.q
+                    )),
+                    None => ui.label(format!("{name}: not available for this model")),
+                };
+            }
+            ui.label("Q is |Z| at resonance over |Z| at the lowest frequency.");
+
+            let height = ui.available_height().max(300.0);
+            let zap = impedance_lines(&imp.zap);
+            let linear = imp.linear.as_deref().map(impedance_lines);
+            Plot::new("impedance magnitude plot")
+                .height(height * 0.6)
+                .link_axis("impedance", true, false)
+                .link_cursor("impedance", true, false)
+                .y_axis_label("|Z| (kΩ·cm²)")
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(zap.0.clone()).name("ZAP"));
+                    if let Some((magnitude, _)) = &linear {
+                        plot_ui.line(Line::new(magnitude.clone()).name("linearised"));
+                    }
+                });
+            Plot::new("impedance phase plot")
+                .height(height * 0.4)
+                .link_axis("impedance", true, false)
+                .link_cursor("impedance", true, false)
+                .x_axis_label("frequency (Hz)")
+                .y_axis_label("phase (°)")
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(zap.1).name("ZAP"));
+                    if let Some((_, phase)) = linear {
+                        plot_ui.line(Line::new(phase).name("linearised"));
+                    }
+                });
         });
 
-        Window::new("Rate Functions").show(egui_ctx, |ui| {
-            let plot = Plot::new("rate function plot").legend(Legend::default());
-            plot.show(ui, |plot_ui| {
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        rate::alpha_m,
-                        -50.0..150.0,
-                        200,
-                    ))
-                    .name("α_m"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        rate::beta_m,
-                        -50.0..150.0,
-                        200,
-                    ))
-                    .name("β_m"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        rate::alpha_h,
-                        -50.0..150.0,
-                        200,
-                    ))
-                    .name("α_h"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        rate::beta_h,
-                        -50.0..150.0,
-                        200,
-                    ))
-                    .name("β_h"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        rate::alpha_n,
-                        -50.0..150.0,
-                        200,
-                    ))
-                    .name("α_n"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        rate::beta_n,
-                        -50.0..150.0,
-                        200,
-                    ))
-                    .name("β_n"),
+        Window::new("Spectrum").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let sp = &mut state.ui.spectrum;
+            let recorder = &state.hh.recorder;
+
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("spectrum var")
+                    .selected_text(sp.var.name())
+                    .show_ui(ui, |ui| {
+                        for var in recorder.config.vars.iter().copied() {
+                            ui.selectable_value(&mut sp.var, var, var.name());
+                        }
+                    });
+                egui::ComboBox::from_id_source("spectrum window")
+                    .selected_text(sp.window.name())
+                    .show_ui(ui, |ui| {
+                        for window in spectrum::Window::ALL {
+                            ui.selectable_value(&mut sp.window, window, window.name());
+                        }
+                    });
+                egui::ComboBox::from_id_source("spectrum segment")
+                    .selected_text(format!("{} samples", sp.segment))
+                    .show_ui(ui, |ui| {
+                        for bits in 8..=17 {
+                            let n = 1 << bits;
+                            ui.selectable_value(&mut sp.segment, n, format!("{n} samples"));
+                        }
+                    })
+                    .response
+                    .on_hover_text("Longer segments resolve finer frequencies but average fewer");
+                ui.label("Skip");
+                ui.add(
+                    DragValue::new(&mut sp.skip)
+                        .range(0.0..=10_000.0)
+                        .speed(1.0)
+                        .suffix(" ms"),
                 );
+                ui.checkbox(&mut sp.log, "Log-log");
             });
+            if ui.button("Compute from the current run").clicked() {
+                sp.psd.clear();
+                sp.error = match recorder.column(sp.var) {
+                    None => Some(format!("{} is not recorded.", sp.var.name())),
+                    Some(_) if recorder.len() < 2 => Some("Nothing recorded yet.".to_string()),
+                    Some(column) => {
+                        let dt = recorder.t[1] - recorder.t[0];
+                        let from = recorder.t.partition_point(|&t| t < recorder.t[0] + sp.skip);
+                        let samples: Vec<Float> = column.iter().skip(from).copied().collect();
+                        sp.psd = spectrum::welch(&samples, dt, sp.segment, sp.window);
+                        sp.psd
+                            .is_empty()
+                            .then(|| "Nothing left after skipping.".to_string())
+                    }
+                };
+            }
+            if let Some(e) = &sp.error {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
+
+            // log-log plots the logarithms, labelling the axes with the values
+            let log = sp.log;
+            let points: Vec<[f64; 2]> = sp
+                .psd
+                .iter()
+                .filter(|[f, p]| !log || (*f > 0.0 && *p > 0.0))
+                .map(|&[f, p]| if log { [f.log10(), p.log10()] } else { [f, p] })
+                .collect();
+            let unlog = move |x: f64| if log { 10f64.powf(x) } else { x };
+            Plot::new("spectrum plot")
+                .height(ui.available_height().max(200.0))
+                .x_axis_label("frequency (Hz)")
+                .y_axis_label(format!("PSD of {} per Hz", sp.var.name()))
+                .x_axis_formatter(move |mark, _| format!("{:.3}", unlog(mark.value)))
+                .y_axis_formatter(move |mark, _| format!("{:.2e}", unlog(mark.value)))
+                .label_formatter(move |_, point| {
+                    format!("{:.2} Hz\n{:.3e}", unlog(point.x), unlog(point.y))
+                })
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(points).name(sp.window.name()));
+                });
         });
 
-        Window::new("First-order Kinetics").show(egui_ctx, |ui| {
-            ui.label("For a large collection of identical gates, define");
-            ui.label(RichText::new("τ_x = 1/(α_x + β_x)").font(FontId::proportional(20.0)));
-            ui.label(RichText::new("x_∞ = α_x/(α_x + β_x)").font(FontId::proportional(20.0)));
-            ui.label("Then gate dynamics follow,");
-            ui.label(RichText::new("τ_x dx/dt = -x + x_∞").font(FontId::proportional(20.0)));
+        Window::new("Runs").show(egui_ctx, |ui| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let simulating = state.hh.simulating();
+            let runs = &mut state.hh.runs;
 
-            let plot = Plot::new("rate function plot")
-                .height(ui.available_height())
-                .legend(Legend::default());
-            plot.show(ui, |plot_ui| {
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        hh::tau_m,
-                        -150.0..100.0,
-                        250,
-                    ))
-                    .name("τ_m"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        hh::m_inf,
-                        -150.0..100.0,
-                        250,
-                    ))
-                    .name("m_∞"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        hh::tau_h,
-                        -150.0..100.0,
-                        250,
-                    ))
-                    .name("τ_h"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        hh::h_inf,
-                        -150.0..100.0,
-                        250,
-                    ))
-                    .name("h_∞"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        hh::tau_n,
-                        -150.0..100.0,
-                        250,
-                    ))
-                    .name("τ_n"),
-                );
-                plot_ui.line(
-                    Line::new(PlotPoints::from_explicit_callback(
-                        hh::n_inf,
-                        -150.0..100.0,
-                        250,
-                    ))
-                    .name("n_∞"),
-                );
+            ui.label("Each run is kept here when the next one starts.");
+            ui.horizontal(|ui| {
+                ui.label("Keep");
+                if ui
+                    .add(
+                        DragValue::new(&mut runs.keep)
+                            .range(0..=100)
+                            .suffix(" runs"),
+                    )
+                    .on_hover_text("Pinned runs are kept in addition")
+                    .changed()
+                {
+                    runs.trim();
+                }
+            });
+
+            let mut remove = None;
+            let mut rerun = None;
+            Grid::new("runs grid").num_columns(6).show(ui, |ui| {
+                for (idx, run) in runs.list.iter_mut().enumerate() {
+                    ui.checkbox(&mut run.visible, "");
+                    ui.color_edit_button_srgba(&mut run.color);
+                    ui.add(TextEdit::singleline(&mut run.name).desired_width(120.0));
+                    ui.toggle_value(&mut run.pinned, "📌")
+                        .on_hover_text("Keep regardless of the limit");
+                    if ui
+                        .add_enabled(!simulating, egui::Button::new("Re-run"))
+                        .on_hover_text("Simulate again with this run's setup and parameters")
+                        .clicked()
+                    {
+                        rerun = Some(idx);
+                    }
+                    if ui.button("Delete").clicked() {
+                        remove = Some(idx);
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some(idx) = remove {
+                runs.remove(idx);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut runs.diff, "Plot difference");
+                let choices: Vec<(runs::Pick, String)> = std::iter::once(runs::Pick::Current)
+                    .chain((0..runs.list.len()).map(runs::Pick::Kept))
+                    .map(|pick| (pick, runs.name(pick).to_string()))
+                    .collect();
+                for (label, pick) in [("A", &mut runs.a), ("B", &mut runs.b)] {
+                    let name = choices
+                        .iter()
+                        .find(|(c, _)| c == pick)
+                        .map(|(_, n)| n.as_str());
+                    egui::ComboBox::from_label(label)
+                        .selected_text(name.unwrap_or("none"))
+                        .show_ui(ui, |ui| {
+                            for (choice, name) in &choices {
+                                ui.selectable_value(pick, *choice, name);
+                            }
+                        });
+                }
+                ui.label("A − B");
             });
+
+            if let Some(idx) = rerun {
+                let run = &state.hh.runs.list[idx];
+                state.hh.setup = run.setup.clone();
+                state.hh.params = run.params.clone();
+                state.ui.run.error = state.hh.init().err();
+            }
         });
 
         Window::new("Full Simulation").show(egui_ctx, |ui| {
@@ -216,15 +3003,23 @@ fn main() {
                             .range(0.001..=1.0)
                             .speed(0.001),
                     );
-                    ui.label("Steps per frame");
+                    ui.label("Temperature");
                     ui.add(
-                        DragValue::new(&mut state.hh.setup.steps_per_frame)
-                            .range(1..=10000)
-                            .speed(100),
+                        DragValue::new(&mut state.hh.params.temperature)
+                            .range(-10.0..=50.0)
+                            .speed(0.1)
+                            .suffix(" °C"),
                     );
                     ui.end_row();
 
                     ui.label("Pulse settings");
+                    if state.hh.setup.replay.is_some() {
+                        if ui.button("Back to pulse").clicked() {
+                            state.hh.setup.replay = None;
+                        }
+                        ui.end_row();
+                        return;
+                    }
                     ui.label("");
                     ui.label("Start");
                     let limit = state.hh.setup.pulse.end;
@@ -248,15 +3043,76 @@ fn main() {
                     );
                     ui.end_row();
                 });
+            ui.collapsing("Recording", |ui| {
+                recording_editor(ui, &mut state.hh);
+            });
+            ui.collapsing("Dynamic clamp", |ui| {
+                clamp_editor(ui, &mut state.hh);
+            });
+            ui.collapsing("Pharmacology", |ui| {
+                drug_editor(ui, &mut state.hh);
+            });
+            ui.collapsing("Noise", |ui| {
+                noise_editor(ui, &mut state.hh);
+            });
+            if let Some(e) = &state.ui.run.error {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
             ui.separator();
 
             ui.horizontal(|ui| {
                 if ui.button("Simulate").clicked() {
-                    state.hh.init();
+                    state.ui.run.error = state.hh.init().err();
+                }
+                let run = state.hh.run_state();
+                if run == hh::RunState::Paused {
+                    if ui.button("Resume").clicked() {
+                        state.hh.unpause();
+                    }
+                } else if ui
+                    .add_enabled(run == hh::RunState::Running, egui::Button::new("Pause"))
+                    .clicked()
+                {
+                    state.hh.pause();
+                }
+                if ui
+                    .add_enabled(run == hh::RunState::Paused, egui::Button::new("Step"))
+                    .on_hover_text("Advance by a single time step")
+                    .clicked()
+                {
+                    state.hh.single_step();
                 }
+                if ui
+                    .add_enabled(state.hh.simulating(), egui::Button::new("Cancel"))
+                    .clicked()
+                {
+                    state.hh.cancel();
+                }
+                ui.add_enabled_ui(state.hh.can_extend(), |ui| {
+                    if ui
+                        .button("Continue")
+                        .on_hover_text("Extend the simulation end and resume from the last state")
+                        .on_disabled_hover_text(
+                            "Only a stopped run whose setup and parameters are unchanged \
+                             can continue",
+                        )
+                        .clicked()
+                    {
+                        let by = state.ui.run.continue_by;
+                        state.hh.extend(by);
+                    }
+                    ui.add(
+                        DragValue::new(&mut state.ui.run.continue_by)
+                            .range(0.1..=200.0)
+                            .speed(0.5)
+                            .prefix("by ")
+                            .suffix(" ms"),
+                    );
+                });
+                ui.label(run.name());
 
                 let progress_bar = ProgressBar::new(
-                    state.hh.points_avail as f32 / state.hh.setup.total_steps() as f32,
+                    state.hh.steps_done as f32 / state.hh.setup.total_steps() as f32,
                 )
                 .show_percentage()
                 .desired_width(ui.available_width())
@@ -277,6 +3133,15 @@ fn main() {
                 .height(height_for_plots * 0.15)
                 .legend(Legend::default());
             plot.show(ui, |plot_ui| {
+                drug_timeline(plot_ui, &state.hh);
+                if let Some(waveform) = &state.hh.setup.replay {
+                    let x = visible_x(plot_ui);
+                    let line = decimated_line(plot_ui, x, waveform.t.len(), |k| {
+                        [waveform.t[k], waveform.i[k]]
+                    });
+                    plot_ui.line(line.name("replayed current"));
+                    return;
+                }
                 plot_ui.line(
                     Line::new(PlotPoints::from_parametric_callback(
                         |t| match t {
@@ -295,46 +3160,28 @@ fn main() {
                 );
             });
 
-            /// helper function to extract subsampled data to plot from potentially incomplete and too many simulated data points
-            fn line_from_sim(
-                sim: &hh::State,
-                extract: impl Fn(hh::Axon) -> Float,
-                points: usize,
-            ) -> Line {
-                Line::new(PlotPoints::from_parametric_callback(
-                    |t| {
-                        let percentage = t / points as f64;
-                        let perfect_idx = percentage * sim.setup.total_steps() as f64;
-                        let left_idx = perfect_idx.floor() as usize;
-                        let right_idx = left_idx + 1;
-
-                        let left = if left_idx < sim.points_avail {
-                            extract(sim.history[left_idx])
-                        } else {
-                            0.0
-                        };
-                        let right = if right_idx < sim.points_avail {
-                            extract(sim.history[right_idx])
-                        } else {
-                            0.0
-                        };
-                        let interp = left * (right_idx as f64 - perfect_idx)
-                            + right * (perfect_idx - left_idx as f64);
-
-                        (percentage * sim.setup.end, interp)
-                    },
-                    0.0..points as f64,
-                    points,
-                ))
-            }
-
             let plot = Plot::new("simulated voltage plot")
+                .include_x(0.0)
+                .include_x(state.hh.setup.end)
                 .link_axis(ui.id(), true, false)
                 .link_cursor(ui.id(), true, false)
                 .height(height_for_plots * 0.45)
                 .legend(Legend::default());
             plot.show(ui, |plot_ui| {
-                plot_ui.line(line_from_sim(&state.hh, |a| a.v(), PLOT_POINTS).name("voltage"));
+                run_lines(plot_ui, &state.hh, record::Var::V, "voltage");
+
+                for overlay in state.ui.overlays.iter().filter(|o| o.visible) {
+                    for (idx, sweep) in overlay.trace.sweeps.iter().enumerate() {
+                        let x = visible_x(plot_ui);
+                        let line = decimated_line(plot_ui, x, sweep.t.len(), |k| {
+                            [
+                                sweep.t[k] + overlay.time_shift,
+                                sweep.v[k] + overlay.v_offset,
+                            ]
+                        });
+                        plot_ui.line(line.name(format!("{} #{}", overlay.trace.name, idx + 1)));
+                    }
+                }
             });
 
             ui.horizontal(|ui| {
@@ -345,49 +3192,72 @@ fn main() {
                     ExtraPlot::Conductance,
                     "Conductances",
                 );
+                if state.ui.extra_plot == ExtraPlot::Current && state.hh.steps_done > 1 {
+                    let balance = state.hh.balance;
+                    let text = format!(
+                        "worst current imbalance {:.3} µA/cm² at {:.2} ms",
+                        balance.worst, balance.at
+                    );
+                    // a trapezoidal average misses a spike by well under this at a fine step
+                    if balance.worst.is_nan() || balance.worst > 1.0 {
+                        ui.colored_label(ui.visuals().warn_fg_color, text)
+                    } else {
+                        ui.label(text)
+                    }
+                    .on_hover_text(
+                        "C dV/dt of each step against the average ionic and injected currents; \
+                         large values call for a smaller step",
+                    );
+                }
             });
 
+            let vars: &[record::Var] = match state.ui.extra_plot {
+                ExtraPlot::Current => &[
+                    record::Var::INa,
+                    record::Var::IK,
+                    record::Var::IL,
+                    record::Var::IIon,
+                    record::Var::ICap,
+                    record::Var::Stimulus,
+                ],
+                ExtraPlot::Gate => &[record::Var::M, record::Var::H, record::Var::N],
+                ExtraPlot::Conductance => &[record::Var::GNa, record::Var::GK],
+            };
+            let recorded = &state.hh.recorder.config.vars;
+            let missing: Vec<&str> = vars
+                .iter()
+                .filter(|v| !recorded.contains(v))
+                .map(|v| v.name())
+                .collect();
+            if !missing.is_empty() {
+                ui.label(format!(
+                    "Not recorded: {}. Tick them under Recording in Full Simulation.",
+                    missing.join(", ")
+                ));
+            }
             let extra_plot = Plot::new("simulated extra plot")
+                .include_x(0.0)
+                .include_x(state.hh.setup.end)
                 .link_axis(ui.id(), true, false)
                 .link_cursor(ui.id(), true, false)
                 .height(ui.available_height())
                 .legend(Legend::default());
-            match state.ui.extra_plot {
-                ExtraPlot::Current => {
-                    extra_plot.show(ui, |plot_ui| {
-                        plot_ui
-                            .line(line_from_sim(&state.hh, |a| a.i_na(), PLOT_POINTS).name("I_Na"));
-                        plot_ui
-                            .line(line_from_sim(&state.hh, |a| a.i_k(), PLOT_POINTS).name("I_K"));
-                    });
+            extra_plot.show(ui, |plot_ui| {
+                for &var in vars {
+                    run_lines(plot_ui, &state.hh, var, var.name());
                 }
-                ExtraPlot::Gate => {
-                    extra_plot.show(ui, |plot_ui| {
-                        plot_ui.line(line_from_sim(&state.hh, |a| a.m(), PLOT_POINTS).name("m"));
-                        plot_ui.line(line_from_sim(&state.hh, |a| a.h(), PLOT_POINTS).name("h"));
-                        plot_ui.line(line_from_sim(&state.hh, |a| a.n(), PLOT_POINTS).name("n"));
-                        plot_ui.line(
-                            line_from_sim(&state.hh, |a| a.m_inf(), PLOT_POINTS).name("m_inf"),
-                        );
-                        plot_ui.line(
-                            line_from_sim(&state.hh, |a| a.h_inf(), PLOT_POINTS).name("h_inf"),
-                        );
-                        plot_ui.line(
-                            line_from_sim(&state.hh, |a| a.n_inf(), PLOT_POINTS).name("n_inf"),
-                        );
-                    });
-                }
-                ExtraPlot::Conductance => {
-                    extra_plot.show(ui, |plot_ui| {
-                        plot_ui.line(
-                            line_from_sim(&state.hh, |a| a.cond_na(), PLOT_POINTS).name("g_Na"),
-                        );
-                        plot_ui.line(
-                            line_from_sim(&state.hh, |a| a.cond_k(), PLOT_POINTS).name("g_K"),
-                        );
-                    });
+                if state.ui.extra_plot == ExtraPlot::Gate && !state.hh.runs.diff {
+                    let (p, rec) = (state.hh.run_params(), &state.hh.recorder);
+                    let inf: [(&str, &dyn Fn(Float) -> Float); 3] = [
+                        ("m_inf", &|v| p.m_inf(v)),
+                        ("h_inf", &|v| p.h_inf(v)),
+                        ("n_inf", &|v| p.n_inf(v)),
+                    ];
+                    for (name, f) in inf {
+                        plot_ui.line(line_from_record(plot_ui, rec, record::Var::V, f).name(name));
+                    }
                 }
-            }
+            });
         });
     };
 
@@ -0,0 +1,597 @@
+use crate::{
+    Float,
+    hh::{self, Axon, Params, Pulse},
+    neuroml::V_REST,
+    spikes,
+};
+
+use std::collections::VecDeque;
+
+/// mM of extracellular magnesium blocking NMDA receptors
+const MG: Float = 1.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    /// instant rise, exponential decay
+    Exponential,
+    /// `t/τ exp(1 - t/τ)`, τ being `tau_decay`
+    Alpha,
+    Ampa,
+    /// slow and blocked by magnesium at rest
+    Nmda,
+    GabaA,
+    /// slow inhibition through potassium
+    GabaB,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 6] = [
+        Kind::Exponential,
+        Kind::Alpha,
+        Kind::Ampa,
+        Kind::Nmda,
+        Kind::GabaA,
+        Kind::GabaB,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Exponential => "exponential",
+            Kind::Alpha => "alpha",
+            Kind::Ampa => "AMPA",
+            Kind::Nmda => "NMDA",
+            Kind::GabaA => "GABA_A",
+            Kind::GabaB => "GABA_B",
+        }
+    }
+
+    /// rise and decay time constants in ms, and reversal potential relative to rest
+    pub fn defaults(&self) -> (Float, Float, Float) {
+        match self {
+            Kind::Exponential => (0.0, 5.0, -V_REST),
+            Kind::Alpha => (0.0, 2.0, -V_REST),
+            Kind::Ampa => (0.2, 2.0, -V_REST),
+            Kind::Nmda => (2.0, 100.0, -V_REST),
+            Kind::GabaA => (0.5, 5.0, -75.0 - V_REST),
+            Kind::GabaB => (50.0, 200.0, -90.0 - V_REST),
+        }
+    }
+
+    /// fraction of the conductance open at `v`, relative to rest
+    pub fn block(&self, v: Float) -> Float {
+        match self {
+            // Jahr and Stevens (1990)
+            Kind::Nmda => 1.0 / (1.0 + MG / 3.57 * (-0.062 * (v + V_REST)).exp()),
+            _ => 1.0,
+        }
+    }
+}
+
+/// A conductance synapse, activated `delay` ms after each spike of `from`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Synapse {
+    pub from: usize,
+    pub to: usize,
+    pub kind: Kind,
+    /// peak conductance of a single event, mS/cm²
+    pub weight: Float,
+    pub delay: Float,
+    /// ms; unused by exponential and alpha synapses
+    pub tau_rise: Float,
+    pub tau_decay: Float,
+    /// mV relative to rest
+    pub reversal: Float,
+}
+
+impl Synapse {
+    pub fn new(from: usize, to: usize, kind: Kind) -> Self {
+        let (tau_rise, tau_decay, reversal) = kind.defaults();
+        Self {
+            from,
+            to,
+            kind,
+            weight: 0.5,
+            delay: 1.0,
+            tau_rise,
+            tau_decay,
+            reversal,
+        }
+    }
+
+    /// whether the synapse follows an alpha function: by kind, or as the limit of a dual
+    /// exponential whose time constants coincide
+    fn is_alpha(&self) -> bool {
+        match self.kind {
+            Kind::Alpha => true,
+            Kind::Exponential => false,
+            _ => (self.tau_decay - self.tau_rise).abs() <= 1e-3 * self.tau_decay,
+        }
+    }
+
+    /// let `gating` of the synapse decay over `dt`
+    fn decay(&self, gating: &mut [Float; 2], dt: Float) {
+        let fall = (-dt / self.tau_decay).exp();
+        match self.kind {
+            Kind::Exponential => gating[1] *= fall,
+            // exact solution of x' = -x/τ, s' = (x - s)/τ
+            _ if self.is_alpha() => {
+                gating[1] = (gating[1] + gating[0] * dt / self.tau_decay) * fall;
+                gating[0] *= fall;
+            }
+            _ => {
+                gating[0] *= (-dt / self.tau_rise.max(1e-6)).exp();
+                gating[1] *= fall;
+            }
+        }
+    }
+
+    /// a presynaptic spike arriving, raising the open fraction to a peak of 1 by itself
+    fn activate(&self, gating: &mut [Float; 2]) {
+        match self.kind {
+            Kind::Exponential => gating[1] += 1.0,
+            _ if self.is_alpha() => gating[0] += std::f64::consts::E,
+            _ => {
+                let (rise, decay) = (self.tau_rise.max(1e-6), self.tau_decay);
+                let peak = rise * decay / (decay - rise) * (decay / rise).ln();
+                let norm = 1.0 / ((-peak / decay).exp() - (-peak / rise).exp());
+                gating[0] += norm;
+                gating[1] += norm;
+            }
+        }
+    }
+
+    /// open fraction
+    fn open(&self, gating: &[Float; 2]) -> Float {
+        if self.kind == Kind::Exponential || self.is_alpha() {
+            gating[1]
+        } else {
+            gating[1] - gating[0]
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Neuron {
+    pub name: String,
+    /// constant injected current, μA/cm²
+    pub bias: Float,
+    pub pulse: Pulse,
+}
+
+impl Neuron {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            bias: 0.0,
+            pulse: Pulse {
+                start: 0.0,
+                end: 0.0,
+                magnitude: 0.0,
+            },
+        }
+    }
+}
+
+/// An ohmic electrical synapse, passing `conductance · (V_b − V_a)` into `a` and the opposite
+/// into `b`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gap {
+    pub a: usize,
+    pub b: usize,
+    /// mS/cm²
+    pub conductance: Float,
+}
+
+/// Identical neurons connected by synapses.
+#[derive(Clone, Default)]
+pub struct Network {
+    pub neurons: Vec<Neuron>,
+    pub synapses: Vec<Synapse>,
+    pub gaps: Vec<Gap>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Preset {
+    /// a driven neuron exciting a second one through AMPA
+    Excitation,
+    /// a tonically firing neuron silenced by an inhibitory one
+    Inhibition,
+    /// two neurons inhibiting each other, firing in alternation through rebound
+    HalfCenter,
+    /// two tonically firing neurons of different rates, electrically coupled
+    GapPair,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [
+        Preset::Excitation,
+        Preset::Inhibition,
+        Preset::HalfCenter,
+        Preset::GapPair,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Excitation => "excitation",
+            Preset::Inhibition => "inhibition",
+            Preset::HalfCenter => "half-center oscillator",
+            Preset::GapPair => "gap-coupled pair",
+        }
+    }
+}
+
+impl Network {
+    pub fn preset(preset: Preset) -> Self {
+        let neuron = |name: &str, bias: Float, start: Float, magnitude: Float| Neuron {
+            bias,
+            pulse: Pulse {
+                start,
+                end: start + 1.0,
+                magnitude,
+            },
+            ..Neuron::new(name.to_string())
+        };
+        match preset {
+            Preset::Excitation => Self {
+                neurons: vec![neuron("A", 0.0, 5.0, 20.0), neuron("B", 0.0, 0.0, 0.0)],
+                synapses: vec![Synapse {
+                    weight: 1.0,
+                    ..Synapse::new(0, 1, Kind::Ampa)
+                }],
+                gaps: Vec::new(),
+            },
+            Preset::Inhibition => Self {
+                neurons: vec![
+                    neuron("inhibitor", 0.0, 20.0, 20.0),
+                    neuron("tonic", 10.0, 0.0, 0.0),
+                ],
+                synapses: vec![Synapse {
+                    weight: 1.0,
+                    tau_decay: 20.0,
+                    ..Synapse::new(0, 1, Kind::GabaA)
+                }],
+                gaps: Vec::new(),
+            },
+            Preset::HalfCenter => {
+                // strong enough to hyperpolarize into an anode break spike on release
+                let inhibit = |from, to| Synapse {
+                    weight: 5.0,
+                    reversal: -90.0 - V_REST,
+                    ..Synapse::new(from, to, Kind::GabaA)
+                };
+                Self {
+                    neurons: vec![
+                        neuron("left", 0.0, 1.0, 20.0),
+                        neuron("right", 0.0, 0.0, 0.0),
+                    ],
+                    synapses: vec![inhibit(0, 1), inhibit(1, 0)],
+                    gaps: Vec::new(),
+                }
+            }
+            Preset::GapPair => Self {
+                neurons: vec![
+                    neuron("fast", 12.0, 0.0, 0.0),
+                    neuron("slow", 7.0, 0.0, 0.0),
+                ],
+                synapses: Vec::new(),
+                gaps: vec![Gap {
+                    a: 0,
+                    b: 1,
+                    conductance: 0.1,
+                }],
+            },
+        }
+    }
+
+    /// current each gap junction passes into each neuron at voltages `v`; they sum to zero
+    pub fn gap_currents(&self, v: &[Float]) -> Vec<Float> {
+        let mut current = vec![0.0; v.len()];
+        for gap in &self.gaps {
+            let i = gap.conductance * (v[gap.b] - v[gap.a]);
+            current[gap.a] += i;
+            current[gap.b] -= i;
+        }
+        current
+    }
+}
+
+/// A run of a network, advanced a number of steps at a time.
+pub struct Simulation {
+    pub network: Network,
+    pub params: Params,
+    pub dt: Float,
+    pub end: Float,
+    pub steps_done: usize,
+    axons: Vec<Axon>,
+    /// rise and decay terms of each synapse
+    gating: Vec<[Float; 2]>,
+    /// arrival times of spikes on their way to each synapse
+    pending: Vec<VecDeque<Float>>,
+    /// voltage of each neuron at every step
+    pub v: Vec<Vec<Float>>,
+    /// time and neuron of every spike
+    pub spikes: Vec<(Float, usize)>,
+}
+
+impl Simulation {
+    pub fn new(network: Network, params: Params, dt: Float, end: Float) -> Self {
+        let rest = params.steady_state(0.0);
+        let (neurons, synapses) = (network.neurons.len(), network.synapses.len());
+        Self {
+            network,
+            params,
+            dt,
+            end,
+            steps_done: 1,
+            axons: vec![rest; neurons],
+            gating: vec![[0.0; 2]; synapses],
+            pending: vec![VecDeque::new(); synapses],
+            v: vec![vec![rest.v()]; neurons],
+            spikes: Vec::new(),
+        }
+    }
+
+    pub fn total_steps(&self) -> usize {
+        (self.end / self.dt).floor() as usize
+    }
+
+    pub fn finished(&self) -> bool {
+        self.steps_done >= self.total_steps()
+    }
+
+    /// synaptic current into neuron `idx` at its voltage `v`
+    fn synaptic_current(&self, idx: usize, v: Float) -> Float {
+        self.network
+            .synapses
+            .iter()
+            .zip(&self.gating)
+            .filter(|(s, _)| s.to == idx)
+            .map(|(s, g)| s.weight * s.open(g) * s.kind.block(v) * (s.reversal - v))
+            .sum()
+    }
+
+    /// Advance by up to `steps` steps, integrating the neurons together so that the gap junction
+    /// and synaptic currents follow their voltages within a step. Synaptic gating is held over
+    /// each step.
+    pub fn advance(&mut self, steps: usize) {
+        let total = self.total_steps();
+        for _ in 0..steps {
+            if self.steps_done >= total {
+                return;
+            }
+            let t = (self.steps_done - 1) as Float * self.dt;
+            let next = t + self.dt;
+
+            let currents = |t: Float, axons: &[Axon]| {
+                let v: Vec<Float> = axons.iter().map(Axon::v).collect();
+                let gap = self.network.gap_currents(&v);
+                let neurons = self.network.neurons.iter().zip(gap).zip(&v);
+                neurons
+                    .enumerate()
+                    .map(|(idx, ((neuron, gap), &v))| {
+                        neuron.bias + neuron.pulse.current(t) + gap + self.synaptic_current(idx, v)
+                    })
+                    .collect()
+            };
+            let after = hh::integrate_coupled(&self.params, &self.axons, t, self.dt, currents);
+            for (idx, after) in after.into_iter().enumerate() {
+                let before = self.axons[idx].v();
+                let crossing = spikes::crossing((t, before), (next, after.v()), spikes::THRESHOLD);
+                if let Some(crossing) = crossing {
+                    self.spikes.push((crossing, idx));
+                    for (synapse, pending) in self.network.synapses.iter().zip(&mut self.pending) {
+                        if synapse.from == idx {
+                            pending.push_back(crossing + synapse.delay);
+                        }
+                    }
+                }
+                self.axons[idx] = after;
+                self.v[idx].push(after.v());
+            }
+
+            for ((synapse, gating), pending) in self
+                .network
+                .synapses
+                .iter()
+                .zip(&mut self.gating)
+                .zip(&mut self.pending)
+            {
+                synapse.decay(gating, self.dt);
+                while pending.front().is_some_and(|&arrival| arrival <= next) {
+                    pending.pop_front();
+                    synapse.activate(gating);
+                }
+            }
+            self.steps_done += 1;
+        }
+    }
+
+    /// spikes of neuron `idx`
+    pub fn spike_times(&self, idx: usize) -> Vec<Float> {
+        self.spikes
+            .iter()
+            .filter(|(_, n)| *n == idx)
+            .map(|(t, _)| *t)
+            .collect()
+    }
+}
+
+/// Steady ratio of the voltage changes of two otherwise silent cells coupled by `conductance`,
+/// when a small hyperpolarizing current is injected into the first.
+pub fn coupling_coefficient(params: &Params, conductance: Float, dt: Float) -> Float {
+    let mut injected = Neuron::new("injected".to_string());
+    injected.pulse = Pulse {
+        start: 0.0,
+        end: 1e3,
+        magnitude: -1.0,
+    };
+    // the uncoupled third cell accounts for any drift of the initial state towards rest
+    let network = Network {
+        neurons: vec![
+            injected,
+            Neuron::new("coupled".to_string()),
+            Neuron::new("control".to_string()),
+        ],
+        synapses: Vec::new(),
+        gaps: vec![Gap {
+            a: 0,
+            b: 1,
+            conductance,
+        }],
+    };
+    let mut sim = Simulation::new(network, params.clone(), dt, 100.0);
+    while !sim.finished() {
+        sim.advance(1000);
+    }
+    let end = |v: &[Float]| v[v.len() - 1];
+    let rest = end(&sim.v[2]);
+    (end(&sim.v[1]) - rest) / (end(&sim.v[0]) - rest)
+}
+
+/// Fraction of spikes of either train with one of the other within `window` ms.
+pub fn synchrony(a: &[Float], b: &[Float], window: Float) -> Float {
+    let matched = |from: &[Float], to: &[Float]| {
+        from.iter()
+            .filter(|&&t| to.iter().any(|&u| (t - u).abs() <= window))
+            .count()
+    };
+    let total = a.len() + b.len();
+    if total == 0 {
+        return 0.0;
+    }
+    (matched(a, b) + matched(b, a)) as Float / total as Float
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// open fraction of a synapse after a single event, every `dt` for `steps`
+    fn response(synapse: &Synapse, dt: Float, steps: usize) -> Vec<Float> {
+        let mut gating = [0.0; 2];
+        synapse.activate(&mut gating);
+        (0..steps)
+            .map(|_| {
+                synapse.decay(&mut gating, dt);
+                synapse.open(&gating)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_events_peak_at_one() {
+        for kind in Kind::ALL {
+            let synapse = Synapse::new(0, 0, kind);
+            let trace = response(&synapse, 0.01, 100_000);
+            let peak = trace.iter().copied().fold(0.0, Float::max);
+            assert!((peak - 1.0).abs() < 1e-2, "{} peaks at {peak}", kind.name());
+            assert!(*trace.last().unwrap() < 0.05);
+        }
+        let alpha = Synapse::new(0, 0, Kind::Alpha);
+        let trace = response(&alpha, 0.01, 1000);
+        let at = trace
+            .iter()
+            .position(|&s| s == trace.iter().copied().fold(0.0, Float::max));
+        assert!((at.unwrap() as Float * 0.01 + 0.01 - alpha.tau_decay).abs() < 0.02);
+
+        // magnesium unblocks with depolarization
+        assert!(Kind::Nmda.block(0.0) < 0.1);
+        assert!(Kind::Nmda.block(60.0) > 0.5);
+        assert_eq!(Kind::Ampa.block(0.0), 1.0);
+    }
+
+    #[test]
+    fn equal_time_constants_act_as_alpha() {
+        let mut synapse = Synapse::new(0, 0, Kind::Ampa);
+        synapse.tau_rise = synapse.tau_decay;
+        let trace = response(&synapse, 0.01, 10_000);
+        assert!(trace.iter().all(|s| s.is_finite()));
+        let peak = trace.iter().copied().fold(0.0, Float::max);
+        assert!((peak - 1.0).abs() < 1e-2, "peaks at {peak}");
+
+        let mut network = Network::preset(Preset::Excitation);
+        for synapse in &mut network.synapses {
+            synapse.tau_rise = synapse.tau_decay;
+        }
+        let sim = run(network, 30.0);
+        assert!(sim.v.iter().flatten().all(|v| v.is_finite()));
+    }
+
+    fn run(network: Network, end: Float) -> Simulation {
+        let mut sim = Simulation::new(network, Params::default(), 0.01, end);
+        while !sim.finished() {
+            sim.advance(1000);
+        }
+        sim
+    }
+
+    #[test]
+    fn excitation_is_delayed_and_inhibition_silences() {
+        let sim = run(Network::preset(Preset::Excitation), 30.0);
+        let (a, b) = (sim.spike_times(0), sim.spike_times(1));
+        assert_eq!((a.len(), b.len()), (1, 1));
+        assert!(b[0] > a[0] + sim.network.synapses[0].delay);
+        assert_eq!(sim.v[1].len(), sim.total_steps());
+
+        let mut unconnected = Network::preset(Preset::Excitation);
+        unconnected.synapses.clear();
+        assert!(run(unconnected, 30.0).spike_times(1).is_empty());
+
+        let inhibited = run(Network::preset(Preset::Inhibition), 100.0);
+        let mut alone = Network::preset(Preset::Inhibition);
+        alone.synapses.clear();
+        let alone = run(alone, 100.0);
+        assert!(inhibited.spike_times(1).len() < alone.spike_times(1).len());
+    }
+
+    #[test]
+    fn half_center_alternates() {
+        let sim = run(Network::preset(Preset::HalfCenter), 200.0);
+        assert!(sim.spikes.len() >= 6);
+        assert!(sim.spikes.windows(2).all(|w| w[0].1 != w[1].1));
+    }
+
+    #[test]
+    fn gap_currents_are_symmetric() {
+        let mut net = Network {
+            neurons: (0..3).map(|k| Neuron::new(k.to_string())).collect(),
+            ..Default::default()
+        };
+        for (a, b, conductance) in [(0, 1, 0.3), (1, 2, 0.2), (2, 0, 0.1)] {
+            net.gaps.push(Gap { a, b, conductance });
+        }
+        let v = [10.0, -5.0, 3.0];
+        let current = net.gap_currents(&v);
+        assert!(current.iter().sum::<Float>().abs() < 1e-12);
+        assert!((current[0] - (0.3 * -15.0 + 0.1 * -7.0)).abs() < 1e-12);
+
+        // which end is which makes no difference
+        for gap in &mut net.gaps {
+            std::mem::swap(&mut gap.a, &mut gap.b);
+        }
+        assert_eq!(net.gap_currents(&v), current);
+    }
+
+    #[test]
+    fn coupling_synchronizes() {
+        let params = Params::default();
+        assert_eq!(coupling_coefficient(&params, 0.0, 0.01), 0.0);
+        let (weak, strong) = (
+            coupling_coefficient(&params, 0.1, 0.01),
+            coupling_coefficient(&params, 1.0, 0.01),
+        );
+        assert!(0.0 < weak && weak < strong && strong < 1.0);
+        // stiff coupling, g dt / C well above 1, stays stable
+        let stiff = coupling_coefficient(&params, 10.0, 0.12);
+        assert!(strong < stiff && stiff < 1.0, "{stiff}");
+
+        let sync = |conductance| {
+            let mut net = Network::preset(Preset::GapPair);
+            net.gaps[0].conductance = conductance;
+            let sim = run(net, 300.0);
+            synchrony(&sim.spike_times(0), &sim.spike_times(1), 1.0)
+        };
+        assert!(sync(0.0) < 0.5);
+        assert!(sync(0.5) > 0.9);
+    }
+}
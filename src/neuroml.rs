@@ -0,0 +1,497 @@
+use crate::{
+    Float,
+    hh::Params,
+    rate::{Family, Gate, Q10, Rate},
+};
+
+/// NeuroML voltages are absolute while the model puts rest at 0 mV; this is the usual offset.
+pub const V_REST: Float = -65.0;
+
+/// Just enough of XML for NeuroML: elements and attributes. Text, comments, processing
+/// instructions and doctypes are skipped.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// this element and everything below it, depth first
+    fn descendants(&self) -> Vec<&Element> {
+        let mut all = vec![self];
+        for child in &self.children {
+            all.extend(child.descendants());
+        }
+        all
+    }
+
+    /// `name` or an `ionChannel`-style element whose `type` attribute is `name`
+    fn is(&self, name: &str) -> bool {
+        self.name == name || self.attr("type") == Some(name)
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_xml(text: &str) -> Result<Element, String> {
+    let mut stack = vec![Element::default()];
+    let mut rest = text;
+    let line_of = |rest: &str| text[..text.len() - rest.len()].matches('\n').count() + 1;
+
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open..];
+        let skip_to = |rest: &str, end: &str| -> Result<usize, String> {
+            rest.find(end).map(|i| i + end.len()).ok_or_else(|| {
+                let start: String = rest.chars().take(2).collect();
+                format!("line {}: unterminated `{start}`", line_of(rest))
+            })
+        };
+        if rest.starts_with("<!--") {
+            rest = &rest[skip_to(rest, "-->")?..];
+        } else if rest.starts_with("<?") {
+            rest = &rest[skip_to(rest, "?>")?..];
+        } else if rest.starts_with("<!") {
+            rest = &rest[skip_to(rest, ">")?..];
+        } else if let Some(close) = rest.strip_prefix("</") {
+            let end = skip_to(rest, ">")?;
+            let name = close[..end - 3].trim();
+            let element = stack.pop().filter(|_| !stack.is_empty());
+            match element {
+                Some(element) if element.name == local_name(name) => {
+                    stack
+                        .last_mut()
+                        .expect("root is never popped")
+                        .children
+                        .push(element);
+                }
+                _ => return Err(format!("line {}: unexpected `</{name}>`", line_of(rest))),
+            }
+            rest = &rest[end..];
+        } else {
+            let end = skip_to(rest, ">")?;
+            let tag = &rest[1..end - 1];
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let element = parse_tag(tag).map_err(|e| format!("line {}: {e}", line_of(rest)))?;
+            if self_closing {
+                stack
+                    .last_mut()
+                    .expect("root is never popped")
+                    .children
+                    .push(element);
+            } else {
+                stack.push(element);
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(format!(
+            "`<{}>` is never closed",
+            stack.last().map_or("", |e| e.name.as_str())
+        ));
+    }
+    Ok(stack.pop().expect("checked above"))
+}
+
+/// drop the namespace prefix
+fn local_name(name: &str) -> String {
+    name.rsplit(':').next().unwrap_or(name).to_string()
+}
+
+fn parse_tag(tag: &str) -> Result<Element, String> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let mut element = Element {
+        name: local_name(&tag[..name_end]),
+        ..Default::default()
+    };
+
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| format!("malformed attributes in `<{}>`", element.name))?;
+        let key = local_name(rest[..eq].trim());
+        let value = rest[eq + 1..].trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|q| *q == '"' || *q == '\'')
+            .ok_or_else(|| format!("unquoted attribute `{key}`"))?;
+        let close = value[1..]
+            .find(quote)
+            .ok_or_else(|| format!("unterminated attribute `{key}`"))?;
+        element.attrs.push((key, unescape(&value[1..1 + close])));
+        rest = value[close + 2..].trim_start();
+    }
+    Ok(element)
+}
+
+/// A NeuroML quantity such as `-40mV` or `0.125 per_ms`, converted to the model's units.
+fn quantity(text: &str) -> Result<Float, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| c.is_alphabetic() || c == '_')
+        .filter(|&i| {
+            // keep the exponent of `1e-3mV` with the number
+            !(text[i..].starts_with(['e', 'E'])
+                && text[i + 1..].starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+'))
+        })
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: Float = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("malformed quantity `{text}`"))?;
+    let scale = match unit.trim() {
+        "" | "mV" | "per_ms" | "ms" | "degC" | "mS_per_cm2" | "uF_per_cm2" => 1.0,
+        "V" => 1e3,
+        "per_s" => 1e-3,
+        "s" => 1e3,
+        "S_per_m2" => 0.1,
+        "S_per_cm2" => 1e3,
+        "F_per_m2" => 100.0,
+        "F_per_cm2" => 1e6,
+        unit => return Err(format!("unsupported unit `{unit}` in `{text}`")),
+    };
+    Ok(number * scale)
+}
+
+fn attr_quantity(element: &Element, key: &str) -> Result<Float, String> {
+    quantity(
+        element
+            .attr(key)
+            .ok_or_else(|| format!("`<{}>` lacks `{key}`", element.name))?,
+    )
+}
+
+fn import_rate(element: &Element, v_rest: Float) -> Result<Rate, String> {
+    let family = match element.attr("type") {
+        Some("HHExpRate") => Family::Exponential,
+        Some("HHSigmoidRate") => Family::Sigmoid,
+        Some("HHExpLinearRate") => Family::Linoid,
+        Some(other) => return Err(format!("unsupported rate type `{other}`")),
+        None => return Err(format!("`<{}>` lacks `type`", element.name)),
+    };
+    Ok(Rate::new(
+        family,
+        attr_quantity(element, "rate")?,
+        attr_quantity(element, "midpoint")? - v_rest,
+        attr_quantity(element, "scale")?,
+    ))
+}
+
+/// gate index and the exponent the built-in equations use for it
+fn gate_slot(species: &str, gate: &str) -> Option<(usize, u32)> {
+    match (species, gate) {
+        ("na", "m") => Some((0, 3)),
+        ("na", "h") => Some((1, 1)),
+        ("k", "n") => Some((2, 4)),
+        _ => None,
+    }
+}
+
+/// Read `ionChannelHH` definitions with `gateHHrates` gates into `base`, as well as channel
+/// densities, reversal potentials and capacitance if the file also defines a cell.
+///
+/// Returns the updated parameters with notes on everything that was ignored.
+pub fn import(text: &str, base: &Params, v_rest: Float) -> Result<(Params, Vec<String>), String> {
+    let root = parse_xml(text)?;
+    let mut params = base.clone();
+    let mut notes = Vec::new();
+    let mut species_of: Vec<(String, String)> = Vec::new();
+    let mut gates_read = 0;
+
+    for channel in root
+        .descendants()
+        .into_iter()
+        .filter(|e| e.is("ionChannelHH") || e.is("ionChannel") || e.is("ionChannelPassive"))
+    {
+        let id = channel.attr("id").unwrap_or_default().to_string();
+        let species = channel
+            .attr("species")
+            .unwrap_or(if channel.is("ionChannelPassive") {
+                "passive"
+            } else {
+                ""
+            })
+            .to_ascii_lowercase();
+        species_of.push((id.clone(), species.clone()));
+
+        for gate in channel
+            .children
+            .iter()
+            .filter(|c| c.name.starts_with("gate"))
+        {
+            let gate_id = gate.attr("id").unwrap_or_default();
+            let Some((slot, exponent)) = gate_slot(&species, gate_id) else {
+                notes.push(format!("ignored gate `{gate_id}` of channel `{id}`"));
+                continue;
+            };
+            if !gate.is("gateHHrates") {
+                notes.push(format!(
+                    "ignored gate `{gate_id}`: only gateHHrates is supported"
+                ));
+                continue;
+            }
+            let instances: u32 = gate
+                .attr("instances")
+                .and_then(|i| i.parse().ok())
+                .unwrap_or(1);
+            if instances != exponent {
+                notes.push(format!(
+                    "gate `{gate_id}` has {instances} instance(s), the model uses {exponent}"
+                ));
+            }
+
+            let rate = |name: &str| -> Result<Rate, String> {
+                let element = gate
+                    .children
+                    .iter()
+                    .find(|c| c.name == name)
+                    .ok_or_else(|| format!("gate `{gate_id}` lacks `<{name}>`"))?;
+                import_rate(element, v_rest).map_err(|e| format!("gate `{gate_id}`: {e}"))
+            };
+            params.kinetics.gates[slot] = Gate::Rates {
+                alpha: rate("forwardRate")?,
+                beta: rate("reverseRate")?,
+            };
+
+            match gate.children.iter().find(|c| c.name == "q10Settings") {
+                Some(q10) if q10.attr("type") == Some("q10ExpTemp") => {
+                    params.kinetics.q10[slot] = Q10 {
+                        factor: attr_quantity(q10, "q10Factor")?,
+                        reference: attr_quantity(q10, "experimentalTemp")?,
+                    };
+                }
+                Some(q10) => notes.push(format!(
+                    "ignored q10Settings of type `{}`",
+                    q10.attr("type").unwrap_or_default()
+                )),
+                // no temperature dependence
+                None => {
+                    params.kinetics.q10[slot] = Q10 {
+                        factor: 1.0,
+                        reference: params.temperature,
+                    }
+                }
+            }
+            gates_read += 1;
+        }
+    }
+
+    for density in root
+        .descendants()
+        .into_iter()
+        .filter(|e| e.name == "channelDensity")
+    {
+        let channel = density.attr("ionChannel").unwrap_or_default();
+        let species = species_of
+            .iter()
+            .find(|(id, _)| id == channel)
+            .map(|(_, s)| s.as_str())
+            .or(density.attr("ion"))
+            .unwrap_or_default();
+        let g = attr_quantity(density, "condDensity")?;
+        let e = attr_quantity(density, "erev")? - v_rest;
+        match species {
+            "na" => (params.g_na, params.e_na) = (g, e),
+            "k" => (params.g_k, params.e_k) = (g, e),
+            "passive" | "non_specific" | "" => (params.g_l, params.e_l) = (g, e),
+            other => notes.push(format!("ignored density of `{other}` channel `{channel}`")),
+        }
+    }
+    if let Some(c) = root
+        .descendants()
+        .into_iter()
+        .find(|e| e.name == "specificCapacitance")
+    {
+        params.c_m = attr_quantity(c, "value")?;
+    }
+
+    if gates_read == 0 {
+        return Err("no ionChannelHH gate with id m, h or n found".to_string());
+    }
+    Ok((params, notes))
+}
+
+fn export_rate(tag: &str, rate: &Rate, v_rest: Float) -> String {
+    let kind = match rate.family {
+        Family::Exponential => "HHExpRate",
+        Family::Sigmoid => "HHSigmoidRate",
+        Family::Linoid => "HHExpLinearRate",
+    };
+    format!(
+        "            <{tag} type=\"{kind}\" rate=\"{}per_ms\" midpoint=\"{}mV\" scale=\"{}mV\"/>\n",
+        rate.rate,
+        rate.midpoint + v_rest,
+        rate.scale
+    )
+}
+
+/// NeuroML 2 document with Na, K and leak channels and a single compartment cell using them.
+/// Fails if a gate is not described by α/β rates.
+pub fn export(params: &Params, v_rest: Float) -> Result<String, String> {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <neuroml xmlns=\"http://www.neuroml.org/schema/neuroml2\" id=\"hodgkin_huxley\">\n",
+    );
+
+    for (channel, species, gates) in [
+        ("naChan", "na", [("m", 0, 3), ("h", 1, 1)].as_slice()),
+        ("kChan", "k", [("n", 2, 4)].as_slice()),
+    ] {
+        out += &format!(
+            "    <ionChannelHH id=\"{channel}\" conductance=\"10pS\" species=\"{species}\">\n"
+        );
+        for &(id, slot, instances) in gates {
+            let Gate::Rates { alpha, beta } = &params.kinetics.gates[slot] else {
+                return Err(format!(
+                    "gate {id} uses a τ table, which gateHHrates cannot express"
+                ));
+            };
+            let q10 = params.kinetics.q10[slot];
+            out += &format!(
+                "        <gateHHrates id=\"{id}\" instances=\"{instances}\">\n\
+                 \x20           <q10Settings type=\"q10ExpTemp\" q10Factor=\"{}\" experimentalTemp=\"{} degC\"/>\n",
+                q10.factor, q10.reference
+            );
+            out += &export_rate("forwardRate", alpha, v_rest);
+            out += &export_rate("reverseRate", beta, v_rest);
+            out += "        </gateHHrates>\n";
+        }
+        out += "    </ionChannelHH>\n";
+    }
+    out += "    <ionChannelPassive id=\"leakChan\" conductance=\"10pS\"/>\n";
+
+    out += &format!(
+        "    <cell id=\"hhCell\">\n\
+         \x20       <biophysicalProperties id=\"bioPhys\">\n\
+         \x20           <membraneProperties>\n\
+         \x20               <channelDensity id=\"naChans\" ionChannel=\"naChan\" condDensity=\"{} mS_per_cm2\" erev=\"{}mV\" ion=\"na\"/>\n\
+         \x20               <channelDensity id=\"kChans\" ionChannel=\"kChan\" condDensity=\"{} mS_per_cm2\" erev=\"{}mV\" ion=\"k\"/>\n\
+         \x20               <channelDensity id=\"leak\" ionChannel=\"leakChan\" condDensity=\"{} mS_per_cm2\" erev=\"{}mV\" ion=\"non_specific\"/>\n\
+         \x20               <specificCapacitance value=\"{} uF_per_cm2\"/>\n\
+         \x20           </membraneProperties>\n\
+         \x20       </biophysicalProperties>\n\
+         \x20   </cell>\n",
+        params.g_na,
+        params.e_na + v_rest,
+        params.g_k,
+        params.e_k + v_rest,
+        params.g_l,
+        params.e_l + v_rest,
+        params.c_m,
+    );
+    out += "</neuroml>\n";
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate;
+
+    /// the Na channel as distributed with the NeuroML 2 examples
+    const NA_CHANNEL: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<neuroml xmlns="http://www.neuroml.org/schema/neuroml2" id="NML2_SimpleIonChannel">
+    <!-- Na channel of the squid giant axon -->
+    <ionChannelHH id="naChan" conductance="10pS" species="na">
+        <gateHHrates id="m" instances="3">
+            <forwardRate type="HHExpLinearRate" rate="1per_ms" midpoint="-40mV" scale="10mV"/>
+            <reverseRate type="HHExpRate" rate="4per_ms" midpoint="-65mV" scale="-18mV"/>
+        </gateHHrates>
+        <gateHHrates id="h" instances="1">
+            <forwardRate type="HHExpRate" rate="0.07per_ms" midpoint="-65mV" scale="-20mV"/>
+            <reverseRate type="HHSigmoidRate" rate="1per_ms" midpoint="-35mV" scale="10mV"/>
+        </gateHHrates>
+    </ionChannelHH>
+    <ionChannelHH id="kChan" conductance="10pS" species="k">
+        <gateHHrates id="n" instances="4">
+            <q10Settings type="q10ExpTemp" q10Factor="3" experimentalTemp="6.3 degC"/>
+            <forwardRate type="HHExpLinearRate" rate="0.1per_ms" midpoint="-55mV" scale="10mV"/>
+            <reverseRate type="HHExpRate" rate="0.125per_ms" midpoint="-65mV" scale="-80mV"/>
+        </gateHHrates>
+    </ionChannelHH>
+</neuroml>"#;
+
+    #[test]
+    fn imports_reference_channels() {
+        let (params, notes) = import(NA_CHANNEL, &Params::default(), V_REST).unwrap();
+        assert!(notes.is_empty(), "{notes:?}");
+        let k = &params.kinetics;
+        for v in (-100..=150).map(|v| v as Float) {
+            let close = |a: Float, b: Float| (a - b).abs() <= 1e-12 * a.abs().max(1.0);
+            assert!(close(k.m().alpha(v), rate::ALPHA_M.eval(v)));
+            assert!(close(k.m().beta(v), rate::BETA_M.eval(v)));
+            assert!(close(k.h().alpha(v), rate::ALPHA_H.eval(v)));
+            assert!(close(k.h().beta(v), rate::BETA_H.eval(v)));
+            assert!(close(k.n().alpha(v), rate::ALPHA_N.eval(v)));
+            assert!(close(k.n().beta(v), rate::BETA_N.eval(v)));
+        }
+        // m and h have no q10Settings, so no temperature dependence
+        assert_eq!(k.q10[0].factor, 1.0);
+        assert_eq!(k.q10[2], rate::HH_Q10);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut original = Params {
+            g_na: 100.0,
+            e_l: 11.0,
+            ..Default::default()
+        };
+        original.kinetics.q10[1].factor = 2.5;
+        let text = export(&original, V_REST).unwrap();
+
+        let start = Params {
+            g_na: 0.0,
+            e_l: 0.0,
+            kinetics: rate::Kinetics {
+                gates: [
+                    rate::Kinetics::default().gates[0].to_boltzmann(),
+                    rate::Kinetics::default().gates[1].to_boltzmann(),
+                    rate::Kinetics::default().gates[2].to_boltzmann(),
+                ],
+                q10: [rate::HH_Q10; 3],
+            },
+            ..Default::default()
+        };
+        let (imported, notes) = import(&text, &start, V_REST).unwrap();
+        assert!(notes.is_empty(), "{notes:?}");
+        assert_eq!(imported, original);
+    }
+
+    #[test]
+    fn reports_malformed_input() {
+        assert!(import("<neuroml><ionChannelHH>", &Params::default(), V_REST).is_err());
+        let err = import("<neuroml><é", &Params::default(), V_REST).unwrap_err();
+        assert!(err.ends_with("unterminated `<é`"), "{err}");
+        let err = import(
+            r#"<neuroml><ionChannelHH id="x" species="na"><gateHHrates id="m" instances="3">
+               <forwardRate type="HHFancyRate"/></gateHHrates></ionChannelHH></neuroml>"#,
+            &Params::default(),
+            V_REST,
+        )
+        .unwrap_err();
+        assert_eq!(err, "gate `m`: unsupported rate type `HHFancyRate`");
+    }
+}
@@ -0,0 +1,1243 @@
+use crate::{
+    Float,
+    expr::{Ast, BinOp, Error, Func, Parser, Span, Token, tokenize},
+    hh::Params,
+};
+
+/// NMODL keeps full current densities in mA/cm², the model uses µA/cm².
+const CURRENT_SCALE: Float = 1e3;
+const MAX_GLOBALS: usize = 256;
+const MAX_LOCALS: usize = 32;
+/// `hh::Axon` slots left for mechanism states after V, preferred by name
+const AXON_STATES: [&str; 3] = ["m", "h", "n"];
+
+/// names with their spans, as declared
+type Names = Vec<(String, Span)>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Cond<T> {
+    Cmp(CmpOp, T, T),
+    And(Box<Cond<T>>, Box<Cond<T>>),
+    Or(Box<Cond<T>>, Box<Cond<T>>),
+    Not(Box<Cond<T>>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum StmtAst {
+    Local(Names),
+    Assign {
+        name: String,
+        span: Span,
+        derivative: bool,
+        value: Ast,
+    },
+    Call(String, Vec<Ast>, Span),
+    If(Cond<Ast>, Vec<StmtAst>, Vec<StmtAst>),
+    Solve(String, Span),
+}
+
+struct RoutineAst {
+    name: String,
+    span: Span,
+    params: Names,
+    function: bool,
+    body: Vec<StmtAst>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Var {
+    Global(usize),
+    Local(usize),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Value {
+    Num(Float),
+    Var(Var),
+    Neg(Box<Value>),
+    Bin(BinOp, Box<Value>, Box<Value>),
+    Builtin(Func, Vec<Value>),
+    Call(usize, Vec<Value>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Stmt {
+    Assign(Var, Value),
+    Call(usize, Vec<Value>),
+    If(Cond<Value>, Vec<Stmt>, Vec<Stmt>),
+}
+
+/// A PROCEDURE, FUNCTION or one of the fixed blocks, run in its own frame of locals.
+#[derive(Clone, PartialEq, Debug)]
+struct Routine {
+    arity: usize,
+    /// local slot holding a FUNCTION's value
+    result: Option<usize>,
+    body: Vec<Stmt>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Ion {
+    Na,
+    K,
+}
+
+/// A density mechanism compiled from a `.mod` file. Its currents replace the built-in ionic
+/// currents; the capacitance, reversal potentials and temperature still come from
+/// `hh::Params`.
+///
+/// Supported are the NEURON (SUFFIX, USEION for na and k, NONSPECIFIC_CURRENT, RANGE, GLOBAL),
+/// PARAMETER, CONSTANT, STATE, ASSIGNED, BREAKPOINT with `SOLVE … METHOD cnexp`, INITIAL,
+/// DERIVATIVE, PROCEDURE and FUNCTION blocks, with LOCAL variables and `if`/`else`. UNITS,
+/// TABLE and UNITSON/UNITSOFF are accepted and ignored.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mechanism {
+    pub name: String,
+    /// absolute membrane potential at the model's 0 mV
+    pub v_rest: Float,
+    /// initial values of the globals: parameters, then assigned variables and states
+    globals: Vec<Float>,
+    v: usize,
+    celsius: usize,
+    t: usize,
+    ions: Vec<(usize, Ion)>,
+    currents: Vec<usize>,
+    states: Vec<String>,
+    /// global slot of each state, of its derivative and its `hh::Axon` slot
+    state_slots: Vec<(usize, usize, usize)>,
+    routines: Vec<Routine>,
+    breakpoint: Routine,
+    derivative: Option<Routine>,
+    initial: Option<Routine>,
+}
+
+/// Blank out comments, COMMENT blocks and the TITLE line, keeping every offset in place.
+fn strip_comments(source: &str) -> Result<String, Error> {
+    let mut out = String::with_capacity(source.len());
+    let mut in_comment = false;
+    let mut offset = 0;
+    let blank = |text: &str| -> String {
+        text.chars()
+            .map(|c| " ".repeat(c.len_utf8()))
+            .collect::<String>()
+    };
+
+    for line in source.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        let word = text.split_whitespace().next().unwrap_or_default();
+        if in_comment {
+            in_comment = word != "ENDCOMMENT";
+            out += &blank(text);
+        } else if word == "COMMENT" {
+            in_comment = true;
+            out += &blank(text);
+        } else if word == "TITLE" {
+            out += &blank(text);
+        } else if word == "VERBATIM" {
+            let start = offset + text.find("VERBATIM").expect("found above");
+            return Err(Error::new(
+                "VERBATIM blocks of C code are not supported",
+                start..start + "VERBATIM".len(),
+            ));
+        } else {
+            let end = text.find([':', '?']).unwrap_or(text.len());
+            out += &text[..end];
+            out += &blank(&text[end..]);
+        }
+        out += newline;
+        offset += line.len();
+    }
+    Ok(out)
+}
+
+fn at_word(p: &Parser, word: &str) -> bool {
+    matches!(p.peek(), Token::Ident(w) if w == word)
+}
+
+fn at_line_end(p: &Parser) -> bool {
+    matches!(p.peek(), Token::Newline | Token::Eof)
+}
+
+/// skip a unit such as `(mA/cm2)`
+fn skip_units(p: &mut Parser) -> Result<(), Error> {
+    if !p.at_punct("(") {
+        return Ok(());
+    }
+    let mut depth = 0;
+    loop {
+        match p.next() {
+            (Token::Punct("("), _) => depth += 1,
+            (Token::Punct(")"), _) => depth -= 1,
+            (Token::Newline | Token::Eof, span) => {
+                return Err(Error::new("unterminated unit", span));
+            }
+            _ => {}
+        }
+        if depth == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// skip everything up to the end of the line
+fn skip_line(p: &mut Parser) {
+    while !at_line_end(p) {
+        p.next();
+    }
+}
+
+fn skip_braces(p: &mut Parser) -> Result<(), Error> {
+    p.skip_newlines();
+    p.expect_punct("{")?;
+    let mut depth = 1;
+    while depth > 0 {
+        match p.next() {
+            (Token::Punct("{"), _) => depth += 1,
+            (Token::Punct("}"), _) => depth -= 1,
+            (Token::Eof, span) => return Err(Error::new("expected `}`", span)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn name_list(p: &mut Parser) -> Result<Names, Error> {
+    let mut names = vec![p.ident()?];
+    while p.eat_punct(",") {
+        names.push(p.ident()?);
+    }
+    Ok(names)
+}
+
+fn args(p: &mut Parser) -> Result<Vec<Ast>, Error> {
+    p.expect_punct("(")?;
+    let mut args = Vec::new();
+    if !p.at_punct(")") {
+        loop {
+            args.push(p.expr()?);
+            if !p.eat_punct(",") {
+                break;
+            }
+        }
+    }
+    p.expect_punct(")")?;
+    Ok(args)
+}
+
+fn condition(p: &mut Parser) -> Result<Cond<Ast>, Error> {
+    let mut lhs = conjunction(p)?;
+    while p.eat_punct("||") {
+        lhs = Cond::Or(Box::new(lhs), Box::new(conjunction(p)?));
+    }
+    Ok(lhs)
+}
+
+fn conjunction(p: &mut Parser) -> Result<Cond<Ast>, Error> {
+    let mut lhs = comparison(p)?;
+    while p.eat_punct("&&") {
+        lhs = Cond::And(Box::new(lhs), Box::new(comparison(p)?));
+    }
+    Ok(lhs)
+}
+
+fn comparison(p: &mut Parser) -> Result<Cond<Ast>, Error> {
+    if p.eat_punct("!") {
+        return Ok(Cond::Not(Box::new(comparison(p)?)));
+    }
+    let lhs = p.expr()?;
+    let op = match p.peek() {
+        Token::Punct("<") => CmpOp::Lt,
+        Token::Punct("<=") => CmpOp::Le,
+        Token::Punct(">") => CmpOp::Gt,
+        Token::Punct(">=") => CmpOp::Ge,
+        Token::Punct("==") => CmpOp::Eq,
+        Token::Punct("!=") => CmpOp::Ne,
+        _ => return Err(Error::new("expected a comparison", p.span())),
+    };
+    p.next();
+    Ok(Cond::Cmp(op, lhs, p.expr()?))
+}
+
+/// `{ statements }`
+fn block(p: &mut Parser) -> Result<Vec<StmtAst>, Error> {
+    p.skip_newlines();
+    p.expect_punct("{")?;
+    let mut body = Vec::new();
+    loop {
+        p.skip_newlines();
+        if p.eat_punct("}") {
+            return Ok(body);
+        }
+        if *p.peek() == Token::Eof {
+            return Err(Error::new("expected `}`", p.span()));
+        }
+        let (word, span) = p.ident()?;
+        match word.as_str() {
+            "LOCAL" => body.push(StmtAst::Local(name_list(p)?)),
+            // tables only speed NEURON up
+            "TABLE" => {
+                skip_line(p);
+                continue;
+            }
+            "UNITSON" | "UNITSOFF" => continue,
+            "SOLVE" => {
+                let (name, name_span) = p.ident()?;
+                if at_word(p, "METHOD") {
+                    p.next();
+                    let (method, span) = p.ident()?;
+                    if method != "cnexp" && method != "derivimplicit" {
+                        return Err(Error::new(
+                            format!("METHOD `{method}` is not supported, only cnexp"),
+                            span,
+                        ));
+                    }
+                } else if !at_line_end(p) {
+                    return Err(Error::new("expected METHOD", p.span()));
+                }
+                body.push(StmtAst::Solve(name, name_span));
+            }
+            "if" => {
+                body.push(if_statement(p)?);
+                continue;
+            }
+            _ => {
+                if p.eat_punct("'") {
+                    p.expect_punct("=")?;
+                    body.push(StmtAst::Assign {
+                        name: word,
+                        span,
+                        derivative: true,
+                        value: p.expr()?,
+                    });
+                } else if p.eat_punct("=") {
+                    body.push(StmtAst::Assign {
+                        name: word,
+                        span,
+                        derivative: false,
+                        value: p.expr()?,
+                    });
+                } else if p.at_punct("(") {
+                    let args = args(p)?;
+                    body.push(StmtAst::Call(word, args, span));
+                } else {
+                    return Err(Error::new(format!("unsupported statement `{word}`"), span));
+                }
+            }
+        }
+        if !at_line_end(p) && !p.at_punct("}") {
+            return Err(Error::new("expected the end of the line", p.span()));
+        }
+    }
+}
+
+/// after `if`
+fn if_statement(p: &mut Parser) -> Result<StmtAst, Error> {
+    p.expect_punct("(")?;
+    let cond = condition(p)?;
+    p.expect_punct(")")?;
+    let then = block(p)?;
+    p.skip_newlines();
+    let mut otherwise = Vec::new();
+    if at_word(p, "else") {
+        p.next();
+        if at_word(p, "if") {
+            p.next();
+            otherwise.push(if_statement(p)?);
+        } else {
+            otherwise = block(p)?;
+        }
+    }
+    Ok(StmtAst::If(cond, then, otherwise))
+}
+
+/// `name (units) <limits>` or `name = value (units)`, one or more per line
+fn declarations(p: &mut Parser, values: bool) -> Result<Vec<(String, Span, Option<Float>)>, Error> {
+    p.skip_newlines();
+    p.expect_punct("{")?;
+    let mut decls = Vec::new();
+    loop {
+        p.skip_newlines();
+        if p.eat_punct("}") {
+            return Ok(decls);
+        }
+        let (name, span) = p.ident()?;
+        let mut value = None;
+        if values && p.eat_punct("=") {
+            let sign = if p.eat_punct("-") { -1.0 } else { 1.0 };
+            match p.next() {
+                (Token::Num(x), _) => value = Some(sign * x),
+                (_, span) => return Err(Error::new("expected a number", span)),
+            }
+        }
+        skip_units(p)?;
+        if p.eat_punct("<") {
+            while !p.eat_punct(">") {
+                if at_line_end(p) {
+                    return Err(Error::new("expected `>`", p.span()));
+                }
+                p.next();
+            }
+        }
+        if at_word(p, "FROM") {
+            skip_line(p);
+        }
+        decls.push((name, span, value));
+    }
+}
+
+#[derive(Default)]
+struct Source {
+    name: String,
+    /// ion, its span and the variables read and written
+    ions: Vec<(String, Span, Names, Names)>,
+    nonspecific: Names,
+    parameters: Vec<(String, Span, Option<Float>)>,
+    states: Vec<(String, Span, Option<Float>)>,
+    assigned: Vec<(String, Span, Option<Float>)>,
+    breakpoint: Option<(Vec<StmtAst>, Span)>,
+    initial: Option<Vec<StmtAst>>,
+    derivative: Option<(String, Vec<StmtAst>)>,
+    routines: Vec<RoutineAst>,
+}
+
+fn neuron_block(p: &mut Parser, source: &mut Source) -> Result<(), Error> {
+    p.skip_newlines();
+    p.expect_punct("{")?;
+    loop {
+        p.skip_newlines();
+        if p.eat_punct("}") {
+            return Ok(());
+        }
+        let (word, span) = p.ident()?;
+        match word.as_str() {
+            "SUFFIX" => source.name = p.ident()?.0,
+            "USEION" => {
+                let (ion, ion_span) = p.ident()?;
+                let (mut read, mut write) = (Vec::new(), Vec::new());
+                loop {
+                    if at_word(p, "READ") {
+                        p.next();
+                        read.extend(name_list(p)?);
+                    } else if at_word(p, "WRITE") {
+                        p.next();
+                        write.extend(name_list(p)?);
+                    } else if at_word(p, "VALENCE") {
+                        p.next();
+                        p.eat_punct("-");
+                        p.next();
+                    } else {
+                        break;
+                    }
+                }
+                source.ions.push((ion, ion_span, read, write));
+            }
+            "NONSPECIFIC_CURRENT" => source.nonspecific.extend(name_list(p)?),
+            "RANGE" | "GLOBAL" => {
+                name_list(p)?;
+            }
+            "THREADSAFE" => {}
+            _ => {
+                return Err(Error::new(
+                    format!("`{word}` is not supported, only density mechanisms"),
+                    span,
+                ));
+            }
+        }
+        if !at_line_end(p) && !p.at_punct("}") {
+            return Err(Error::new("expected the end of the line", p.span()));
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Source, Error> {
+    let mut p = Parser::new(tokenize(text)?);
+    let mut source = Source::default();
+    loop {
+        p.skip_newlines();
+        if *p.peek() == Token::Eof {
+            return Ok(source);
+        }
+        let (word, span) = p.ident()?;
+        match word.as_str() {
+            "NEURON" => neuron_block(&mut p, &mut source)?,
+            "UNITS" | "INDEPENDENT" => skip_braces(&mut p)?,
+            "UNITSON" | "UNITSOFF" => {}
+            "PARAMETER" | "CONSTANT" => source.parameters.extend(declarations(&mut p, true)?),
+            "STATE" => source.states.extend(declarations(&mut p, false)?),
+            "ASSIGNED" => source.assigned.extend(declarations(&mut p, false)?),
+            "BREAKPOINT" => source.breakpoint = Some((block(&mut p)?, span)),
+            "INITIAL" => source.initial = Some(block(&mut p)?),
+            "DERIVATIVE" => {
+                let (name, span) = p.ident()?;
+                if source.derivative.is_some() {
+                    return Err(Error::new("only one DERIVATIVE block is supported", span));
+                }
+                source.derivative = Some((name, block(&mut p)?));
+            }
+            "PROCEDURE" | "FUNCTION" => {
+                let (name, span) = p.ident()?;
+                p.expect_punct("(")?;
+                let mut params = Vec::new();
+                if !p.at_punct(")") {
+                    loop {
+                        params.push(p.ident()?);
+                        skip_units(&mut p)?;
+                        if !p.eat_punct(",") {
+                            break;
+                        }
+                    }
+                }
+                p.expect_punct(")")?;
+                skip_units(&mut p)?;
+                source.routines.push(RoutineAst {
+                    name,
+                    span,
+                    params,
+                    function: word == "FUNCTION",
+                    body: block(&mut p)?,
+                });
+            }
+            _ => return Err(Error::new(format!("unsupported block `{word}`"), span)),
+        }
+    }
+}
+
+/// Names visible while resolving one routine.
+struct Scope<'a> {
+    globals: &'a [String],
+    routines: &'a [RoutineAst],
+    /// state names, whose derivatives sit at `derivatives`
+    states: &'a [String],
+    derivatives: Option<&'a [usize]>,
+    locals: Vec<String>,
+}
+
+impl Scope<'_> {
+    fn var(&self, name: &str) -> Option<Var> {
+        if let Some(l) = self.locals.iter().rposition(|n| n == name) {
+            return Some(Var::Local(l));
+        }
+        self.globals.iter().position(|n| n == name).map(Var::Global)
+    }
+
+    fn value(&self, ast: &Ast) -> Result<Value, Error> {
+        Ok(match ast {
+            Ast::Num(x, _) => Value::Num(*x),
+            Ast::Name(name, span) => Value::Var(
+                self.var(name)
+                    .ok_or_else(|| Error::new(format!("unknown name `{name}`"), span.clone()))?,
+            ),
+            Ast::Neg(operand, _) => Value::Neg(Box::new(self.value(operand)?)),
+            Ast::Bin(op, l, r) => {
+                Value::Bin(*op, Box::new(self.value(l)?), Box::new(self.value(r)?))
+            }
+            Ast::Call(name, args, span) => {
+                let values = args
+                    .iter()
+                    .map(|a| self.value(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(idx) = self.routines.iter().position(|r| r.name == *name) {
+                    if !self.routines[idx].function {
+                        return Err(Error::new(
+                            format!("PROCEDURE `{name}` has no value"),
+                            span.clone(),
+                        ));
+                    }
+                    self.check_arity(name, self.routines[idx].params.len(), args, span)?;
+                    return Ok(Value::Call(idx, values));
+                }
+                let (func, arity) = Func::lookup(name).ok_or_else(|| {
+                    Error::new(format!("unknown function `{name}`"), span.clone())
+                })?;
+                self.check_arity(name, arity, args, span)?;
+                Value::Builtin(func, values)
+            }
+        })
+    }
+
+    fn check_arity(
+        &self,
+        name: &str,
+        arity: usize,
+        args: &[Ast],
+        span: &Span,
+    ) -> Result<(), Error> {
+        if args.len() != arity {
+            return Err(Error::new(
+                format!("`{name}` takes {arity} argument(s), got {}", args.len()),
+                span.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn local(&mut self, name: &str, span: &Span) -> Result<usize, Error> {
+        if self.locals.len() == MAX_LOCALS {
+            return Err(Error::new("too many local variables", span.clone()));
+        }
+        self.locals.push(name.to_string());
+        Ok(self.locals.len() - 1)
+    }
+
+    fn cond(&self, cond: &Cond<Ast>) -> Result<Cond<Value>, Error> {
+        Ok(match cond {
+            Cond::Cmp(op, l, r) => Cond::Cmp(*op, self.value(l)?, self.value(r)?),
+            Cond::And(l, r) => Cond::And(Box::new(self.cond(l)?), Box::new(self.cond(r)?)),
+            Cond::Or(l, r) => Cond::Or(Box::new(self.cond(l)?), Box::new(self.cond(r)?)),
+            Cond::Not(c) => Cond::Not(Box::new(self.cond(c)?)),
+        })
+    }
+
+    fn body(&mut self, body: &[StmtAst]) -> Result<Vec<Stmt>, Error> {
+        let mut out = Vec::new();
+        for stmt in body {
+            match stmt {
+                StmtAst::Local(names) => {
+                    for (name, span) in names {
+                        self.local(name, span)?;
+                    }
+                }
+                StmtAst::Assign {
+                    name,
+                    span,
+                    derivative: true,
+                    value,
+                } => {
+                    let Some(derivatives) = self.derivatives else {
+                        return Err(Error::new(
+                            "derivatives belong in the DERIVATIVE block",
+                            span.clone(),
+                        ));
+                    };
+                    let state = self.states.iter().position(|s| s == name).ok_or_else(|| {
+                        Error::new(format!("`{name}` is not a STATE"), span.clone())
+                    })?;
+                    out.push(Stmt::Assign(
+                        Var::Global(derivatives[state]),
+                        self.value(value)?,
+                    ));
+                }
+                StmtAst::Assign {
+                    name, span, value, ..
+                } => {
+                    let var = self.var(name).ok_or_else(|| {
+                        Error::new(format!("unknown name `{name}`"), span.clone())
+                    })?;
+                    out.push(Stmt::Assign(var, self.value(value)?));
+                }
+                StmtAst::Call(name, args, span) => {
+                    let idx = self
+                        .routines
+                        .iter()
+                        .position(|r| r.name == *name)
+                        .ok_or_else(|| {
+                            Error::new(format!("unknown procedure `{name}`"), span.clone())
+                        })?;
+                    self.check_arity(name, self.routines[idx].params.len(), args, span)?;
+                    let values = args
+                        .iter()
+                        .map(|a| self.value(a))
+                        .collect::<Result<_, _>>()?;
+                    out.push(Stmt::Call(idx, values));
+                }
+                StmtAst::If(cond, then, otherwise) => {
+                    let cond = self.cond(cond)?;
+                    // locals declared inside a branch stay visible after it, like in C89 NMODL
+                    let then = self.body(then)?;
+                    let otherwise = self.body(otherwise)?;
+                    out.push(Stmt::If(cond, then, otherwise));
+                }
+                // checked by `compile`, a no-op at run time
+                StmtAst::Solve(..) => {}
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// whether `body` assigns the derivative of `state` anywhere
+fn assigns_derivative(body: &[StmtAst], state: &str) -> bool {
+    body.iter().any(|stmt| match stmt {
+        StmtAst::Assign {
+            name,
+            derivative: true,
+            ..
+        } => name == state,
+        StmtAst::If(_, then, otherwise) => {
+            assigns_derivative(then, state) || assigns_derivative(otherwise, state)
+        }
+        _ => false,
+    })
+}
+
+#[derive(Default)]
+struct Globals {
+    names: Vec<String>,
+    values: Vec<Float>,
+}
+
+impl Globals {
+    /// slot of `name`, declaring it if needed
+    fn slot(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|n| n == name) {
+            Some(idx) => idx,
+            None => {
+                self.names.push(name.to_string());
+                self.values.push(0.0);
+                self.names.len() - 1
+            }
+        }
+    }
+}
+
+/// Compile the text of a `.mod` file. `v_rest` is the absolute potential the model's 0 mV
+/// stands for.
+pub fn compile(text: &str, v_rest: Float) -> Result<Mechanism, Error> {
+    let source = parse(&strip_comments(text)?)?;
+
+    let mut globals = Globals::default();
+    let (v, celsius, t) = (
+        globals.slot("v"),
+        globals.slot("celsius"),
+        globals.slot("t"),
+    );
+
+    for (name, span, value) in &source.parameters {
+        if name == "v" || name == "t" {
+            return Err(Error::new(format!("`{name}` is built in"), span.clone()));
+        }
+        let slot = globals.slot(name);
+        globals.values[slot] = value.unwrap_or(0.0);
+    }
+
+    let mut states = Vec::new();
+    let mut state_slots = Vec::new();
+    for (name, span, _) in &source.states {
+        if globals.names.contains(name) {
+            return Err(Error::new(
+                format!("`{name}` is already declared"),
+                span.clone(),
+            ));
+        }
+        if states.len() == AXON_STATES.len() {
+            return Err(Error::new(
+                format!("at most {} states are supported", AXON_STATES.len()),
+                span.clone(),
+            ));
+        }
+        state_slots.push((globals.slot(name), globals.slot(&format!("{name}'")), 0));
+        states.push(name.clone());
+    }
+    // states named like the built-in gates take their place, the rest fill the gaps
+    for (idx, name) in states.iter().enumerate() {
+        if let Some(slot) = AXON_STATES.iter().position(|s| s == name) {
+            state_slots[idx].2 = slot + 1;
+        }
+    }
+    for idx in 0..states.len() {
+        if state_slots[idx].2 == 0 {
+            state_slots[idx].2 = (1..=AXON_STATES.len())
+                .find(|slot| !state_slots.iter().any(|s| s.2 == *slot))
+                .expect("at most as many states as slots");
+        }
+    }
+
+    for (name, _, _) in &source.assigned {
+        globals.slot(name);
+    }
+
+    let mut ions = Vec::new();
+    let mut currents = Vec::new();
+    for (ion, span, read, write) in &source.ions {
+        let kind = match ion.as_str() {
+            "na" => Ion::Na,
+            "k" => Ion::K,
+            _ => {
+                return Err(Error::new(
+                    format!("ion `{ion}` is not supported, only na and k"),
+                    span.clone(),
+                ));
+            }
+        };
+        for (name, span) in read {
+            if *name != format!("e{ion}") {
+                return Err(Error::new(
+                    format!("reading `{name}` is not supported, only `e{ion}`"),
+                    span.clone(),
+                ));
+            }
+            ions.push((globals.slot(name), kind));
+        }
+        for (name, span) in write {
+            if *name != format!("i{ion}") {
+                return Err(Error::new(
+                    format!("writing `{name}` is not supported, only `i{ion}`"),
+                    span.clone(),
+                ));
+            }
+            currents.push(globals.slot(name));
+        }
+    }
+    for (name, _) in &source.nonspecific {
+        currents.push(globals.slot(name));
+    }
+    if globals.names.len() > MAX_GLOBALS {
+        return Err(Error::new("too many variables", 0..0));
+    }
+
+    let derivative_slots: Vec<usize> = state_slots.iter().map(|s| s.1).collect();
+    let scope = |derivatives| Scope {
+        globals: &globals.names,
+        routines: &source.routines,
+        states: &states,
+        derivatives,
+        locals: Vec::new(),
+    };
+
+    let mut routines = Vec::new();
+    for (idx, routine) in source.routines.iter().enumerate() {
+        if source.routines[..idx]
+            .iter()
+            .any(|r| r.name == routine.name)
+        {
+            return Err(Error::new(
+                format!("`{}` is already defined", routine.name),
+                routine.span.clone(),
+            ));
+        }
+        let mut scope = scope(None);
+        for (name, span) in &routine.params {
+            scope.local(name, span)?;
+        }
+        let result = if routine.function {
+            Some(scope.local(&routine.name, &routine.span)?)
+        } else {
+            None
+        };
+        routines.push(Routine {
+            arity: routine.params.len(),
+            result,
+            body: scope.body(&routine.body)?,
+        });
+    }
+    let fixed = |body: &[StmtAst], derivatives| -> Result<Routine, Error> {
+        Ok(Routine {
+            arity: 0,
+            result: None,
+            body: scope(derivatives).body(body)?,
+        })
+    };
+
+    let (breakpoint, breakpoint_span) = source
+        .breakpoint
+        .as_ref()
+        .ok_or_else(|| Error::new("missing BREAKPOINT block", 0..0))?;
+    let solve = breakpoint.iter().find_map(|stmt| match stmt {
+        StmtAst::Solve(name, span) => Some((name, span)),
+        _ => None,
+    });
+    let derivative = match (&source.derivative, solve) {
+        (Some((name, body)), Some((solved, span))) => {
+            if solved != name {
+                return Err(Error::new(
+                    format!("unknown DERIVATIVE block `{solved}`"),
+                    span.clone(),
+                ));
+            }
+            for (state, span, _) in &source.states {
+                if !assigns_derivative(body, state) {
+                    return Err(Error::new(
+                        format!("missing equation for `{state}'`"),
+                        span.clone(),
+                    ));
+                }
+            }
+            Some(fixed(body, Some(&derivative_slots))?)
+        }
+        (None, Some((solved, span))) => {
+            return Err(Error::new(
+                format!("unknown DERIVATIVE block `{solved}`"),
+                span.clone(),
+            ));
+        }
+        (_, None) if !states.is_empty() => {
+            return Err(Error::new(
+                "BREAKPOINT must SOLVE the DERIVATIVE block",
+                breakpoint_span.clone(),
+            ));
+        }
+        (_, None) => None,
+    };
+
+    Ok(Mechanism {
+        name: source.name.clone(),
+        v_rest,
+        v,
+        celsius,
+        t,
+        ions,
+        currents,
+        state_slots,
+        breakpoint: fixed(breakpoint, None)?,
+        derivative,
+        initial: match &source.initial {
+            Some(body) => Some(fixed(body, None)?),
+            None => None,
+        },
+        routines,
+        states,
+        globals: globals.values,
+    })
+}
+
+impl Mechanism {
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+
+    fn call(&self, routine: &Routine, args: &[Float], globals: &mut [Float]) -> Float {
+        let mut locals = [0.0; MAX_LOCALS];
+        locals[..routine.arity].copy_from_slice(args);
+        self.exec(&routine.body, &mut locals, globals);
+        routine.result.map_or(0.0, |r| locals[r])
+    }
+
+    fn exec(&self, body: &[Stmt], locals: &mut [Float], globals: &mut [Float]) {
+        for stmt in body {
+            match stmt {
+                Stmt::Assign(var, value) => {
+                    let x = self.eval(value, locals, globals);
+                    match var {
+                        Var::Global(g) => globals[*g] = x,
+                        Var::Local(l) => locals[*l] = x,
+                    }
+                }
+                Stmt::Call(idx, args) => {
+                    self.invoke(*idx, args, locals, globals);
+                }
+                Stmt::If(cond, then, otherwise) => {
+                    if self.test(cond, locals, globals) {
+                        self.exec(then, locals, globals);
+                    } else {
+                        self.exec(otherwise, locals, globals);
+                    }
+                }
+            }
+        }
+    }
+
+    fn test(&self, cond: &Cond<Value>, locals: &[Float], globals: &mut [Float]) -> bool {
+        match cond {
+            Cond::Cmp(op, l, r) => {
+                let (l, r) = (self.eval(l, locals, globals), self.eval(r, locals, globals));
+                match op {
+                    CmpOp::Lt => l < r,
+                    CmpOp::Le => l <= r,
+                    CmpOp::Gt => l > r,
+                    CmpOp::Ge => l >= r,
+                    CmpOp::Eq => l == r,
+                    CmpOp::Ne => l != r,
+                }
+            }
+            Cond::And(l, r) => self.test(l, locals, globals) && self.test(r, locals, globals),
+            Cond::Or(l, r) => self.test(l, locals, globals) || self.test(r, locals, globals),
+            Cond::Not(c) => !self.test(c, locals, globals),
+        }
+    }
+
+    fn eval(&self, value: &Value, locals: &[Float], globals: &mut [Float]) -> Float {
+        match value {
+            Value::Num(x) => *x,
+            Value::Var(Var::Global(g)) => globals[*g],
+            Value::Var(Var::Local(l)) => locals[*l],
+            Value::Neg(v) => -self.eval(v, locals, globals),
+            Value::Bin(op, l, r) => {
+                let l = self.eval(l, locals, globals);
+                op.apply(l, self.eval(r, locals, globals))
+            }
+            Value::Builtin(func, args) => {
+                let mut values = [0.0; 2];
+                for (v, a) in values.iter_mut().zip(args) {
+                    *v = self.eval(a, locals, globals);
+                }
+                func.apply(&values)
+            }
+            Value::Call(idx, args) => self.invoke(*idx, args, locals, globals),
+        }
+    }
+
+    /// evaluate `args` in the caller's frame and call routine `idx` with them
+    fn invoke(&self, idx: usize, args: &[Value], locals: &[Float], globals: &mut [Float]) -> Float {
+        let mut values = [0.0; MAX_LOCALS];
+        for (v, a) in values.iter_mut().zip(args) {
+            *v = self.eval(a, locals, globals);
+        }
+        self.call(&self.routines[idx], &values[..args.len()], globals)
+    }
+
+    /// globals at the given `hh::Axon` state
+    fn frame(&self, params: &Params, state: &[Float; 4], t: Float) -> [Float; MAX_GLOBALS] {
+        let mut globals = [0.0; MAX_GLOBALS];
+        globals[..self.globals.len()].copy_from_slice(&self.globals);
+        globals[self.v] = state[0] + self.v_rest;
+        globals[self.celsius] = params.temperature;
+        globals[self.t] = t;
+        for &(slot, ion) in &self.ions {
+            globals[slot] = self.v_rest
+                + match ion {
+                    Ion::Na => params.e_na,
+                    Ion::K => params.e_k,
+                };
+        }
+        for &(global, _, slot) in &self.state_slots {
+            globals[global] = state[slot];
+        }
+        globals
+    }
+
+    /// same contract as `hh::Params::derivative`
+    pub fn derivative(
+        &self,
+        params: &Params,
+        state: &[Float; 4],
+        t: Float,
+        i: Float,
+        d_state: &mut [Float; 4],
+    ) {
+        let mut globals = self.frame(params, state, t);
+        if let Some(derivative) = &self.derivative {
+            self.call(derivative, &[], &mut globals);
+        }
+        self.call(&self.breakpoint, &[], &mut globals);
+
+        let current: Float = self.currents.iter().map(|&c| globals[c]).sum();
+        *d_state = [0.0; 4];
+        d_state[0] = (i - CURRENT_SCALE * current) / params.c_m;
+        for &(_, derivative, slot) in &self.state_slots {
+            d_state[slot] = globals[derivative];
+        }
+    }
+
+    /// state after the INITIAL block at `v0`; states it leaves alone start at 0
+    pub fn initial(&self, params: &Params, v0: Float) -> [Float; 4] {
+        let mut state = [0.0; 4];
+        state[0] = v0;
+        let mut globals = self.frame(params, &state, 0.0);
+        if let Some(initial) = &self.initial {
+            self.call(initial, &[], &mut globals);
+        }
+        for &(global, _, slot) in &self.state_slots {
+            state[slot] = globals[global];
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hh, neuroml::V_REST};
+
+    /// `hh.mod` as shipped with NEURON
+    const HH_MOD: &str = r#"TITLE hh.mod   squid sodium, potassium, and leak channels
+
+COMMENT
+ This is the original Hodgkin-Huxley treatment for the set of sodium,
+  potassium, and leakage channels found in the squid giant axon membrane.
+  ("A quantitative description of membrane current and its application
+  conduction and excitation in nerve" J.Physiol. (Lond.) 117:500-544 (1952).)
+ Membrane voltage is in absolute mV and has been reversed in polarity
+  from the original HH convention and shifted to reflect a resting potential
+  of -65 mV.
+ENDCOMMENT
+
+UNITS {
+        (mA) = (milliamp)
+        (mV) = (millivolt)
+        (S) = (siemens)
+}
+
+? interface
+NEURON {
+        SUFFIX hh
+        USEION na READ ena WRITE ina
+        USEION k READ ek WRITE ik
+        NONSPECIFIC_CURRENT il
+        RANGE gnabar, gkbar, gl, el, gna, gk
+        :GLOBAL minf, hinf, ninf, mtau, htau, ntau
+        RANGE minf, hinf, ninf, mtau, htau, ntau
+        THREADSAFE : assigned GLOBALs will be per thread
+}
+
+PARAMETER {
+        gnabar = .12 (S/cm2)    <0,1e9>
+        gkbar = .036 (S/cm2)    <0,1e9>
+        gl = .0003 (S/cm2)      <0,1e9>
+        el = -54.3 (mV)
+}
+
+STATE {
+        m h n
+}
+
+ASSIGNED {
+        v (mV)
+        celsius (degC)
+        ena (mV)
+        ek (mV)
+
+        gna (S/cm2)
+        gk (S/cm2)
+        ina (mA/cm2)
+        ik (mA/cm2)
+        il (mA/cm2)
+        minf hinf ninf
+        mtau (ms) htau (ms) ntau (ms)
+}
+
+? currents
+BREAKPOINT {
+        SOLVE states METHOD cnexp
+        gna = gnabar*m*m*m*h
+        ina = gna*(v - ena)
+        gk = gkbar*n*n*n*n
+        ik = gk*(v - ek)
+        il = gl*(v - el)
+}
+
+INITIAL {
+        rates(v)
+        m = minf
+        h = hinf
+        n = ninf
+}
+
+? states
+DERIVATIVE states {
+        rates(v)
+        m' =  (minf-m)/mtau
+        h' = (hinf-h)/htau
+        n' = (ninf-n)/ntau
+}
+
+:LOCAL q10
+
+? rates
+PROCEDURE rates(v(mV)) {  :Computes rate and other constants at current v.
+                      :Call once from HOC to initialize inf at resting v.
+        LOCAL  alpha, beta, sum, q10
+        TABLE minf, mtau, hinf, htau, ninf, ntau DEPEND celsius FROM -100 TO 100 WITH 200
+
+UNITSOFF
+        q10 = 3^((celsius - 6.3)/10)
+                :"m" sodium activation system
+        alpha = .1 * vtrap(-(v+40),10)
+        beta =  4 * exp(-(v+65)/18)
+        sum = alpha + beta
+        mtau = 1/(q10*sum)
+        minf = alpha/sum
+                :"h" sodium inactivation system
+        alpha = .07 * exp(-(v+65)/20)
+        beta = 1 / (exp(-(v+35)/10) + 1)
+        sum = alpha + beta
+        htau = 1/(q10*sum)
+        hinf = alpha/sum
+                :"n" potassium activation system
+        alpha = .01*vtrap(-(v+55),10)
+        beta = .125*exp(-(v+65)/80)
+        sum = alpha + beta
+        ntau = 1/(q10*sum)
+        ninf = alpha/sum
+}
+
+FUNCTION vtrap(x,y) {  :Traps for 0 in denominator of rate eqns.
+        if (fabs(x/y) < 1e-6) {
+                vtrap = y*(1 - x/y/2)
+        }else{
+                vtrap = x/(exp(x/y) - 1)
+        }
+}
+
+UNITSON
+"#;
+
+    #[test]
+    fn hodgkin_huxley_reference() {
+        let mechanism = compile(HH_MOD, V_REST).unwrap();
+        assert_eq!(mechanism.name, "hh");
+        assert_eq!(mechanism.states(), ["m", "h", "n"]);
+
+        for temperature in [6.3, 18.5] {
+            // NEURON rounds the leak reversal to -54.3 mV
+            let builtin = hh::Params {
+                temperature,
+                e_l: -54.3 - V_REST,
+                ..Default::default()
+            };
+            let imported = hh::Params {
+                mechanism: Some(mechanism.clone()),
+                ..builtin.clone()
+            };
+            let setup = hh::Setup::default();
+            let expected = hh::simulate(&setup, &builtin);
+            let actual = hh::simulate(&setup, &imported);
+            assert_eq!(expected.len(), actual.len());
+            for (e, a) in expected.iter().zip(&actual) {
+                assert!((e.v() - a.v()).abs() < 1e-6, "{} vs {}", e.v(), a.v());
+                assert!((e.m() - a.m()).abs() < 1e-9);
+                assert!((e.h() - a.h()).abs() < 1e-9);
+                assert!((e.n() - a.n()).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_point_at_unsupported_constructs() {
+        let err = |source: &str| {
+            let err = compile(source, V_REST).unwrap_err();
+            (err.message, source[err.span].to_string())
+        };
+        assert_eq!(
+            err("NEURON { SUFFIX x }\nKINETIC kin {\n}\n"),
+            ("unsupported block `KINETIC`".into(), "KINETIC".into())
+        );
+        assert_eq!(
+            err("NEURON {\n POINT_PROCESS syn\n}\n"),
+            (
+                "`POINT_PROCESS` is not supported, only density mechanisms".into(),
+                "POINT_PROCESS".into()
+            )
+        );
+        assert_eq!(
+            err("STATE { m }\nBREAKPOINT {\n SOLVE s METHOD sparse\n}\n"),
+            (
+                "METHOD `sparse` is not supported, only cnexp".into(),
+                "sparse".into()
+            )
+        );
+        assert_eq!(
+            err("NEURON {\n USEION ca READ eca WRITE ica\n}\n"),
+            (
+                "ion `ca` is not supported, only na and k".into(),
+                "ca".into()
+            )
+        );
+        assert_eq!(
+            err("BREAKPOINT {\n x = 1\n}\n"),
+            ("unknown name `x`".into(), "x".into())
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{Float, rng::Rng};
+
+fn clamp_unit(x: &mut [Float]) {
+    for xi in x {
+        *xi = xi.clamp(0.0, 1.0);
+    }
+}
+
+/// What the next evaluation of a Nelder–Mead iteration is for.
+enum Phase {
+    Reflect,
+    /// the reflection, with its cost, beat the best vertex, so try going twice as far
+    Expand(Vec<Float>, Float),
+    /// move `coeff` from the centroid, kept if it beats `bound`
+    Contract {
+        coeff: Float,
+        bound: Float,
+    },
+    /// re-evaluate the vertex at this index after shrinking towards the best
+    Shrink(usize),
+}
+
+/// Minimizes over the unit cube, advanced one evaluation at a time so that a caller can
+/// spread a long optimization over many frames.
+pub struct NelderMead {
+    /// n + 1 vertices with their cost, best first after every iteration
+    simplex: Vec<(Vec<Float>, Float)>,
+    /// vertices of the initial simplex not evaluated yet
+    pending: Vec<Vec<Float>>,
+    phase: Phase,
+}
+
+impl NelderMead {
+    /// Only `start` is evaluated here, the other vertices by the first calls to `step`.
+    pub fn new(start: &[Float], step: Float, f: &mut impl FnMut(&[Float]) -> Float) -> Self {
+        let pending = (0..start.len())
+            .rev()
+            .map(|i| {
+                let mut x = start.to_vec();
+                // step inwards if the start sits on the upper bound
+                x[i] += if x[i] + step <= 1.0 { step } else { -step };
+                clamp_unit(&mut x);
+                x
+            })
+            .collect();
+        Self {
+            simplex: vec![(start.to_vec(), f(start))],
+            pending,
+            phase: Phase::Reflect,
+        }
+    }
+
+    fn sort(&mut self) {
+        self.simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    }
+
+    /// centroid of all but the worst vertex, moved by `coeff` times (centroid - worst)
+    fn towards(&self, coeff: Float) -> Vec<Float> {
+        let n = self.simplex.len() - 1;
+        let worst = &self.simplex[n].0;
+        let mut x: Vec<Float> = (0..worst.len())
+            .map(|i| self.simplex[..n].iter().map(|v| v.0[i]).sum::<Float>() / n as Float)
+            .collect();
+        for (xi, wi) in x.iter_mut().zip(worst) {
+            *xi += coeff * (*xi - wi);
+        }
+        clamp_unit(&mut x);
+        x
+    }
+
+    /// replace the worst vertex and end the iteration
+    fn accept(&mut self, x: Vec<Float>, cost: Float) -> bool {
+        let n = self.simplex.len() - 1;
+        self.simplex[n] = (x, cost);
+        self.sort();
+        self.phase = Phase::Reflect;
+        true
+    }
+
+    /// One evaluation of `f`; true when it completed an iteration.
+    pub fn step(&mut self, f: &mut impl FnMut(&[Float]) -> Float) -> bool {
+        if let Some(x) = self.pending.pop() {
+            let cost = f(&x);
+            self.simplex.push((x, cost));
+            self.sort();
+            return false;
+        }
+        let n = self.simplex.len() - 1;
+        if n == 0 {
+            return true;
+        }
+
+        match std::mem::replace(&mut self.phase, Phase::Reflect) {
+            Phase::Reflect => {
+                let (best, second_worst, worst) =
+                    (self.simplex[0].1, self.simplex[n - 1].1, self.simplex[n].1);
+                let reflected = self.towards(1.0);
+                let reflected_cost = f(&reflected);
+                if reflected_cost < best {
+                    self.phase = Phase::Expand(reflected, reflected_cost);
+                    false
+                } else if reflected_cost < second_worst {
+                    self.accept(reflected, reflected_cost)
+                } else {
+                    self.phase = if reflected_cost < worst {
+                        Phase::Contract {
+                            coeff: 0.5,
+                            bound: reflected_cost,
+                        }
+                    } else {
+                        Phase::Contract {
+                            coeff: -0.5,
+                            bound: worst,
+                        }
+                    };
+                    false
+                }
+            }
+            Phase::Expand(reflected, reflected_cost) => {
+                let expanded = self.towards(2.0);
+                let expanded_cost = f(&expanded);
+                if expanded_cost < reflected_cost {
+                    self.accept(expanded, expanded_cost)
+                } else {
+                    self.accept(reflected, reflected_cost)
+                }
+            }
+            Phase::Contract { coeff, bound } => {
+                let contracted = self.towards(coeff);
+                let contracted_cost = f(&contracted);
+                if contracted_cost < bound {
+                    return self.accept(contracted, contracted_cost);
+                }
+                let best = self.simplex[0].0.clone();
+                for vertex in &mut self.simplex[1..] {
+                    for (xi, bi) in vertex.0.iter_mut().zip(&best) {
+                        *xi = bi + 0.5 * (*xi - bi);
+                    }
+                }
+                self.phase = Phase::Shrink(1);
+                false
+            }
+            Phase::Shrink(k) => {
+                self.simplex[k].1 = f(&self.simplex[k].0);
+                if k < n {
+                    self.phase = Phase::Shrink(k + 1);
+                    return false;
+                }
+                self.sort();
+                true
+            }
+        }
+    }
+
+    pub fn best(&self) -> (&[Float], Float) {
+        (&self.simplex[0].0, self.simplex[0].1)
+    }
+}
+
+/// DE/rand/1/bin over the unit cube, advanced one evaluation at a time.
+pub struct DifferentialEvolution {
+    population: Vec<(Vec<Float>, Float)>,
+    /// population once fully seeded
+    size: usize,
+    /// member the next trial competes with
+    target: usize,
+    weight: Float,
+    crossover: Float,
+    rng: Rng,
+}
+
+impl DifferentialEvolution {
+    /// The population is seeded uniformly by the first calls to `step`, plus `start` itself,
+    /// evaluated here, so the result is never worse.
+    pub fn new(start: &[Float], seed: u64, f: &mut impl FnMut(&[Float]) -> Float) -> Self {
+        Self {
+            population: vec![(start.to_vec(), f(start))],
+            size: (10 * start.len()).clamp(8, 60),
+            target: 0,
+            weight: 0.7,
+            crossover: 0.9,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// One evaluation of `f`; true when it completed a generation.
+    pub fn step(&mut self, f: &mut impl FnMut(&[Float]) -> Float) -> bool {
+        let dims = self.population[0].0.len();
+        if self.population.len() < self.size {
+            let x: Vec<Float> = (0..dims).map(|_| self.rng.uniform()).collect();
+            let cost = f(&x);
+            self.population.push((x, cost));
+            return false;
+        }
+
+        let (size, target) = (self.size, self.target);
+        let mut pick = || loop {
+            let k = self.rng.below(size);
+            if k != target {
+                break k;
+            }
+        };
+        let (a, b, c) = (pick(), pick(), pick());
+        let forced = self.rng.below(dims);
+
+        let mut trial = self.population[target].0.clone();
+        for (i, xi) in trial.iter_mut().enumerate() {
+            if i == forced || self.rng.uniform() < self.crossover {
+                *xi = self.population[a].0[i]
+                    + self.weight * (self.population[b].0[i] - self.population[c].0[i]);
+            }
+        }
+        clamp_unit(&mut trial);
+
+        let cost = f(&trial);
+        if cost <= self.population[target].1 {
+            self.population[target] = (trial, cost);
+        }
+        self.target = (target + 1) % size;
+        self.target == 0
+    }
+
+    pub fn best(&self) -> (&[Float], Float) {
+        let best = self
+            .population
+            .iter()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("population is never empty");
+        (&best.0, best.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bowl(x: &[Float]) -> Float {
+        x.iter()
+            .enumerate()
+            .map(|(i, xi)| (xi - 0.1 * (i + 2) as Float).powi(2))
+            .sum()
+    }
+
+    #[test]
+    fn nelder_mead() {
+        let mut f = bowl;
+        let mut nm = NelderMead::new(&[0.9, 0.9, 0.9], 0.1, &mut f);
+        for _ in 0..1000 {
+            nm.step(&mut f);
+        }
+        assert!(nm.best().1 < 1e-10);
+    }
+
+    #[test]
+    fn differential_evolution() {
+        let mut f = bowl;
+        let mut de = DifferentialEvolution::new(&[0.9, 0.9, 0.9], 1, &mut f);
+        let mut generations = 0;
+        while generations < 200 {
+            generations += usize::from(de.step(&mut f));
+        }
+        assert!(de.best().1 < 1e-8);
+    }
+}
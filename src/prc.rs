@@ -0,0 +1,376 @@
+use crate::{
+    Float,
+    hh::{self, Axon, Params, STATES},
+    spikes,
+};
+
+/// Two periods in a row this close, in ms, count as the limit cycle.
+const PERIOD_TOLERANCE: Float = 1e-6;
+
+/// Spikes given up after when the period has not settled, in ms of simulated time.
+const SETTLE_LIMIT: Float = 5000.0;
+
+/// Integration steps a `Search` takes per call to `step`.
+const STEPS_PER_CALL: usize = 1000;
+
+/// Periods the adjoint is integrated back over for its other components to decay.
+const ADJOINT_PERIODS: usize = 10;
+
+/// upward crossing of `spikes::THRESHOLD` between `before` at `t` and `after` a `dt` later
+fn crossing(before: &Axon, after: &Axon, t: Float, dt: Float) -> Option<Float> {
+    spikes::crossing((t, before.v()), (t + dt, after.v()), spikes::THRESHOLD)
+}
+
+/// One period of tonic firing under a constant current, phase 0 being the threshold crossing
+/// of a spike.
+#[derive(Clone)]
+pub struct LimitCycle {
+    /// µA/cm²
+    pub drive: Float,
+    pub dt: Float,
+    /// ms between spikes
+    pub period: Float,
+    /// states every `dt` from `offset` after the crossing, over one period
+    pub states: Vec<Axon>,
+    pub offset: Float,
+}
+
+impl LimitCycle {
+    /// phase of `states[idx]`, in 0..1
+    pub fn phase(&self, idx: usize) -> Float {
+        (self.offset + idx as Float * self.dt) / self.period
+    }
+}
+
+/// The search for the limit cycle under a constant current: drive the cell from rest until
+/// successive periods agree, then record the next period. Advanced a bounded number of steps
+/// per call so that it can run alongside the UI.
+pub struct Search {
+    pub params: Params,
+    drive: Float,
+    dt: Float,
+    axon: Axon,
+    t: Float,
+    last: Option<Float>,
+    period: Option<Float>,
+    /// crossing the recorded period starts at, the offset after it, and the states since
+    recording: Option<(Float, Float, Vec<Axon>)>,
+    /// None while searching, then the cycle if the cell fires repetitively
+    outcome: Option<Option<LimitCycle>>,
+}
+
+impl Search {
+    pub fn new(params: &Params, drive: Float, dt: Float) -> Self {
+        Self {
+            params: params.clone(),
+            drive,
+            dt,
+            axon: params.steady_state(0.0),
+            t: 0.0,
+            last: None,
+            period: None,
+            recording: None,
+            outcome: None,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.outcome.is_some()
+    }
+
+    /// fraction of the time allowed for the period to settle used so far
+    pub fn progress(&self) -> Float {
+        (self.t / SETTLE_LIMIT).min(1.0)
+    }
+
+    /// the limit cycle, once finished, if there is one
+    pub fn cycle(&self) -> Option<&LimitCycle> {
+        self.outcome.as_ref()?.as_ref()
+    }
+
+    pub fn step(&mut self) {
+        let (drive, dt) = (self.drive, self.dt);
+        let current = |_: Float, _: &Axon| drive;
+        for _ in 0..STEPS_PER_CALL {
+            if self.finished() {
+                return;
+            }
+            let (axon, t) = (self.axon, self.t);
+            let next = hh::integrate(&self.params, axon, t, dt, current);
+            let spike = crossing(&axon, &next, t, dt);
+            (self.axon, self.t) = (next, t + dt);
+
+            if let Some((start, offset, states)) = &mut self.recording {
+                if let Some(end) = spike {
+                    self.outcome = Some(Some(LimitCycle {
+                        drive,
+                        dt,
+                        period: end - *start,
+                        states: std::mem::take(states),
+                        offset: *offset,
+                    }));
+                } else if self.t - *start > 2.0 * self.period.unwrap_or(0.0) {
+                    // the settled period did not come round again
+                    self.outcome = Some(None);
+                } else {
+                    states.push(next);
+                }
+                continue;
+            }
+
+            if !next.v().is_finite() || self.t >= SETTLE_LIMIT {
+                self.outcome = Some(None);
+                continue;
+            }
+            let Some(spike) = spike else {
+                continue;
+            };
+            let new = self.last.map(|last| spike - last);
+            let settled = matches!((self.period, new), (Some(p), Some(n)) if (p - n).abs() < PERIOD_TOLERANCE);
+            (self.last, self.period) = (Some(spike), new);
+            if settled {
+                // record the next period
+                self.recording = Some((spike, self.t - spike, vec![next]));
+            }
+        }
+    }
+}
+
+/// A brief current pulse delivered at some phase of the cycle.
+#[derive(Clone, Copy, Debug)]
+pub struct Perturbation {
+    /// µA/cm² on top of the drive
+    pub amplitude: Float,
+    /// ms
+    pub duration: Float,
+}
+
+impl Perturbation {
+    /// mV the pulse would displace a passive membrane by
+    pub fn kick(&self, params: &Params) -> Float {
+        self.amplitude * self.duration / params.c_m
+    }
+}
+
+/// Phase response measured directly, perturbing the cycle at one phase per `step` and timing
+/// the next spike.
+pub struct Direct {
+    pub cycle: LimitCycle,
+    pub params: Params,
+    pub perturbation: Perturbation,
+    /// indices into `cycle.states` to perturb at
+    pub samples: Vec<usize>,
+    /// phase advance as a fraction of the period, one per sample done so far
+    pub shifts: Vec<Float>,
+}
+
+impl Direct {
+    pub fn new(
+        cycle: LimitCycle,
+        params: &Params,
+        perturbation: Perturbation,
+        phases: usize,
+    ) -> Self {
+        let n = cycle.states.len();
+        Self {
+            samples: (0..phases).map(|k| k * n / phases).collect(),
+            cycle,
+            params: params.clone(),
+            perturbation,
+            shifts: Vec::new(),
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.shifts.len() == self.samples.len()
+    }
+
+    pub fn step(&mut self) {
+        let Some(&idx) = self.samples.get(self.shifts.len()) else {
+            return;
+        };
+        let cycle = &self.cycle;
+        let (dt, start) = (cycle.dt, cycle.offset + idx as Float * cycle.dt);
+        let Perturbation {
+            amplitude,
+            duration,
+        } = self.perturbation;
+        let current = |t: Float, _: &Axon| {
+            let on = t >= start && t < start + duration;
+            cycle.drive + if on { amplitude } else { 0.0 }
+        };
+
+        // time the next spike, giving up after two periods
+        let (mut axon, mut t) = (cycle.states[idx], start);
+        let mut spike = None;
+        while spike.is_none() && t < start + 2.0 * cycle.period {
+            let next = hh::integrate(&self.params, axon, t, dt, current);
+            spike = crossing(&axon, &next, t, dt);
+            (axon, t) = (next, t + dt);
+        }
+        let shift = spike.map_or(Float::NAN, |s| (cycle.period - s) / cycle.period);
+        self.shifts.push(shift);
+    }
+
+    /// phases done so far, with their advances
+    pub fn curve(&self) -> Vec<[Float; 2]> {
+        self.samples
+            .iter()
+            .zip(&self.shifts)
+            .map(|(&idx, &shift)| [self.cycle.phase(idx), shift])
+            .collect()
+    }
+}
+
+/// Jacobian of the equations of `params` at `state`, `[row][column]`
+fn jacobian(
+    params: &Params,
+    state: &[Float; STATES],
+    t: Float,
+    i: Float,
+) -> [[Float; STATES]; STATES] {
+    let mut jacobian = [[0.0; STATES]; STATES];
+    let (mut up, mut down) = ([0.0; STATES], [0.0; STATES]);
+    for column in 0..STATES {
+        let h = 1e-6 * state[column].abs().max(1.0);
+        let (mut plus, mut minus) = (*state, *state);
+        plus[column] += h;
+        minus[column] -= h;
+        params.derivative(&plus, t, i, &mut up);
+        params.derivative(&minus, t, i, &mut down);
+        for (row, d) in jacobian.iter_mut().enumerate() {
+            d[column] = (up[row] - down[row]) / (2.0 * h);
+        }
+    }
+    jacobian
+}
+
+/// `Jᵀ z`
+fn transposed(jacobian: &[[Float; STATES]; STATES], z: &[Float; STATES]) -> [Float; STATES] {
+    let mut out = [0.0; STATES];
+    for (row, zr) in jacobian.iter().zip(z) {
+        for (o, j) in out.iter_mut().zip(row) {
+            *o += j * zr;
+        }
+    }
+    out
+}
+
+/// Infinitesimal phase response to voltage along the cycle, in ms of advance per mV, by
+/// integrating the adjoint equation `dZ/dt = -Jᵀ Z` backwards in time, normalised so that
+/// `Z · dx/dt = 1`. One value per state of the cycle.
+pub fn adjoint(cycle: &LimitCycle, params: &Params) -> Vec<Float> {
+    let (dt, i) = (cycle.dt, cycle.drive);
+    let t = |idx: usize| cycle.offset + idx as Float * dt;
+    let jacobians: Vec<_> = cycle
+        .states
+        .iter()
+        .enumerate()
+        .map(|(idx, axon)| jacobian(params, &axon.state(), t(idx), i))
+        .collect();
+    let n = jacobians.len();
+
+    // RK4 from each state back to the one before, the one before the first being the last
+    let mut z = [0.0; STATES];
+    z[0] = 1.0;
+    let mut zs = vec![[0.0; STATES]; n];
+    let axpy = |a: Float, x: &[Float; STATES], y: &[Float; STATES]| {
+        let mut out = *y;
+        for (o, x) in out.iter_mut().zip(x) {
+            *o += a * x;
+        }
+        out
+    };
+    for _ in 0..ADJOINT_PERIODS {
+        for idx in (0..n).rev() {
+            let (here, before) = (&jacobians[idx], &jacobians[(idx + n - 1) % n]);
+            let mut middle = [[0.0; STATES]; STATES];
+            for ((m, h), b) in middle.iter_mut().zip(here).zip(before) {
+                for ((m, h), b) in m.iter_mut().zip(h).zip(b) {
+                    *m = 0.5 * (h + b);
+                }
+            }
+            let k1 = transposed(here, &z);
+            let k2 = transposed(&middle, &axpy(0.5 * dt, &k1, &z));
+            let k3 = transposed(&middle, &axpy(0.5 * dt, &k2, &z));
+            let k4 = transposed(before, &axpy(dt, &k3, &z));
+            for (k, zk) in z.iter_mut().enumerate() {
+                *zk += dt / 6.0 * (k1[k] + 2.0 * k2[k] + 2.0 * k3[k] + k4[k]);
+            }
+            zs[idx] = z;
+        }
+        // keep it from growing or shrinking without bound, the normalisation comes after
+        let scale = z.iter().map(|x| x.abs()).fold(0.0, Float::max);
+        z.iter_mut().for_each(|x| *x /= scale);
+    }
+
+    let mut flow = [0.0; STATES];
+    let normal: Float = cycle
+        .states
+        .iter()
+        .zip(&zs)
+        .enumerate()
+        .map(|(idx, (axon, z))| {
+            params.derivative(&axon.state(), t(idx), i, &mut flow);
+            z.iter().zip(&flow).map(|(z, f)| z * f).sum::<Float>()
+        })
+        .sum::<Float>()
+        / n as Float;
+    zs.iter().map(|z| z[0] / normal).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(params: &Params, drive: Float, dt: Float) -> Option<LimitCycle> {
+        let mut search = Search::new(params, drive, dt);
+        while !search.finished() {
+            search.step();
+        }
+        search.cycle().cloned()
+    }
+
+    #[test]
+    fn limit_cycle_repeats() {
+        let params = Params::default();
+        assert!(find(&params, 0.0, 0.01).is_none());
+
+        let cycle = find(&params, 10.0, 0.01).unwrap();
+        // tonic firing of the squid axon at 6.3 °C
+        assert!(cycle.period > 10.0 && cycle.period < 20.0);
+        let steps = cycle.period / cycle.dt;
+        assert!((cycle.states.len() as Float - steps).abs() <= 1.0);
+        let (first, last) = (cycle.states[0], cycle.states[cycle.states.len() - 1]);
+        assert!((first.v() - last.v()).abs() < 10.0);
+        assert!(cycle.phase(0) < 0.01);
+    }
+
+    #[test]
+    fn adjoint_predicts_weak_perturbations() {
+        let params = Params::default();
+        let cycle = find(&params, 10.0, 0.01).unwrap();
+        let perturbation = Perturbation {
+            amplitude: 2.0,
+            duration: 0.1,
+        };
+        let mut direct = Direct::new(cycle.clone(), &params, perturbation, 20);
+        while !direct.finished() {
+            direct.step();
+        }
+        let z = adjoint(&cycle, &params);
+        let kick = perturbation.kick(&params);
+        let largest = direct.shifts.iter().fold(0.0, |m: Float, s| m.max(s.abs()));
+        assert!(largest > 1e-3);
+        for (&idx, &shift) in direct.samples.iter().zip(&direct.shifts) {
+            // the pulse acts over its duration, so compare at its middle
+            let middle = (idx + 5).min(z.len() - 1);
+            let predicted = z[middle] * kick / cycle.period;
+            assert!(
+                (predicted - shift).abs() < 0.2 * largest,
+                "{} {predicted} {shift}",
+                cycle.phase(idx)
+            );
+        }
+    }
+}
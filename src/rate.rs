@@ -1,35 +1,453 @@
 use crate::Float;
 
-pub fn alpha_m(v: Float) -> Float {
-    let v_minus_25 = v - 25.0;
-    if v_minus_25.abs() < 0.01 {
-        1. + v_minus_25 / 20. + v_minus_25 * v_minus_25 / 1200.
-    } else {
-        0.1 * v_minus_25 / (1. - (-v_minus_25 / 10.).exp())
+/// Closed forms of a voltage dependent rate, with `x = (v - midpoint) / scale`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Family {
+    /// `rate * exp(x)`
+    Exponential,
+    /// `rate / (1 + exp(-x))`
+    Sigmoid,
+    /// `rate * x / (1 - exp(-x))`
+    Linoid,
+}
+
+impl Family {
+    pub const ALL: [Family; 3] = [Family::Exponential, Family::Sigmoid, Family::Linoid];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Family::Exponential => "exponential",
+            Family::Sigmoid => "sigmoid",
+            Family::Linoid => "linoid",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rate {
+    pub family: Family,
+    pub rate: Float,
+    pub midpoint: Float,
+    pub scale: Float,
+}
+
+impl Rate {
+    pub const fn new(family: Family, rate: Float, midpoint: Float, scale: Float) -> Self {
+        Self {
+            family,
+            rate,
+            midpoint,
+            scale,
+        }
+    }
+
+    pub fn eval(&self, v: Float) -> Float {
+        let x = (v - self.midpoint) / self.scale;
+        match self.family {
+            Family::Exponential => self.rate * x.exp(),
+            Family::Sigmoid => self.rate / (1. + (-x).exp()),
+            // removable singularity at x = 0, replaced by its Taylor expansion nearby
+            Family::Linoid if x.abs() < 1e-3 => self.rate * (1. + x / 2. + x * x / 12.),
+            Family::Linoid => self.rate * x / (1. - (-x).exp()),
+        }
     }
 }
 
-pub fn beta_m(v: Float) -> Float {
-    4.0 * Float::exp(-v / 18.0)
+pub const ALPHA_M: Rate = Rate::new(Family::Linoid, 1.0, 25.0, 10.0);
+pub const BETA_M: Rate = Rate::new(Family::Exponential, 4.0, 0.0, -18.0);
+pub const ALPHA_H: Rate = Rate::new(Family::Exponential, 0.07, 0.0, -20.0);
+pub const BETA_H: Rate = Rate::new(Family::Sigmoid, 1.0, 30.0, 10.0);
+pub const ALPHA_N: Rate = Rate::new(Family::Linoid, 0.1, 10.0, 10.0);
+pub const BETA_N: Rate = Rate::new(Family::Exponential, 0.125, 0.0, -80.0);
+
+/// Time constants sampled at ascending voltages, linearly interpolated and held flat outside.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TauTable {
+    pub v: Vec<Float>,
+    pub tau: Vec<Float>,
+}
+
+impl TauTable {
+    pub fn eval(&self, v: Float) -> Float {
+        let right = self.v.partition_point(|&x| x <= v);
+        if right == 0 {
+            return self.tau[0];
+        }
+        if right == self.v.len() {
+            return self.tau[right - 1];
+        }
+        let left = right - 1;
+        let frac = (v - self.v[left]) / (self.v[right] - self.v[left]);
+        self.tau[left] * (1.0 - frac) + self.tau[right] * frac
+    }
+
+    /// restore ascending order after an edit
+    pub fn sort(&mut self) {
+        let mut rows: Vec<(Float, Float)> = self
+            .v
+            .iter()
+            .copied()
+            .zip(self.tau.iter().copied())
+            .collect();
+        rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+        (self.v, self.tau) = rows.into_iter().unzip();
+    }
+}
+
+/// Kinetics of one gating variable.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Gate {
+    /// opening rate α and closing rate β
+    Rates { alpha: Rate, beta: Rate },
+    /// `x_∞ = 1 / (1 + exp(-(v - v_half) / slope))`, τ from a table
+    Boltzmann {
+        v_half: Float,
+        slope: Float,
+        tau: TauTable,
+    },
+}
+
+/// A named coefficient of a gate, for generic editors and fitting.
+pub struct Coeff<'a> {
+    pub name: &'static str,
+    /// a voltage, as opposed to a rate or a scale that keeps its sign
+    pub potential: bool,
+    pub value: &'a mut Float,
 }
 
-pub fn alpha_h(v: Float) -> Float {
-    0.07 * Float::exp(-v / 20.0)
+impl Gate {
+    pub fn alpha(&self, v: Float) -> Float {
+        match self {
+            Gate::Rates { alpha, .. } => alpha.eval(v),
+            Gate::Boltzmann { .. } => self.inf(v) / self.tau(v),
+        }
+    }
+
+    pub fn beta(&self, v: Float) -> Float {
+        match self {
+            Gate::Rates { beta, .. } => beta.eval(v),
+            Gate::Boltzmann { .. } => (1.0 - self.inf(v)) / self.tau(v),
+        }
+    }
+
+    pub fn tau(&self, v: Float) -> Float {
+        match self {
+            Gate::Rates { alpha, beta } => 1.0 / (alpha.eval(v) + beta.eval(v)),
+            Gate::Boltzmann { tau, .. } => tau.eval(v),
+        }
+    }
+
+    pub fn inf(&self, v: Float) -> Float {
+        match self {
+            Gate::Rates { alpha, beta } => {
+                let a = alpha.eval(v);
+                a / (a + beta.eval(v))
+            }
+            Gate::Boltzmann { v_half, slope, .. } => 1.0 / (1.0 + (-(v - v_half) / slope).exp()),
+        }
+    }
+
+    /// potential of half-activation, x_∞ = 1/2
+    pub fn v_half(&self) -> Float {
+        if let Gate::Boltzmann { v_half, .. } = self {
+            return *v_half;
+        }
+        // x_∞ is monotonic, so bisect
+        let (mut lo, mut hi) = (-200.0, 300.0);
+        let rising = self.inf(hi) > self.inf(lo);
+        for _ in 0..60 {
+            let mid = 0.5 * (lo + hi);
+            if (self.inf(mid) > 0.5) == rising {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        0.5 * (lo + hi)
+    }
+
+    /// Boltzmann approximation with the same half-activation and slope there, and τ sampled
+    /// every 10 mV.
+    pub fn to_boltzmann(&self) -> Gate {
+        if let Gate::Boltzmann { .. } = self {
+            return self.clone();
+        }
+
+        let v_half = self.v_half();
+        let h = 1e-3;
+        let derivative = (self.inf(v_half + h) - self.inf(v_half - h)) / (2.0 * h);
+
+        let v: Vec<Float> = (-10..=15).map(|k| k as Float * 10.0).collect();
+        Gate::Boltzmann {
+            v_half,
+            slope: 1.0 / (4.0 * derivative),
+            tau: TauTable {
+                tau: v.iter().map(|&v| self.tau(v)).collect(),
+                v,
+            },
+        }
+    }
+
+    pub fn coeffs(&mut self) -> Vec<Coeff<'_>> {
+        let coeff = |name, potential, value| Coeff {
+            name,
+            potential,
+            value,
+        };
+        match self {
+            Gate::Rates { alpha, beta } => vec![
+                coeff("α rate", false, &mut alpha.rate),
+                coeff("α midpoint", true, &mut alpha.midpoint),
+                coeff("α scale", false, &mut alpha.scale),
+                coeff("β rate", false, &mut beta.rate),
+                coeff("β midpoint", true, &mut beta.midpoint),
+                coeff("β scale", false, &mut beta.scale),
+            ],
+            Gate::Boltzmann { v_half, slope, .. } => {
+                vec![coeff("V½", true, v_half), coeff("slope", false, slope)]
+            }
+        }
+    }
 }
 
-pub fn beta_h(v: Float) -> Float {
-    1.0 / (Float::exp(3.0 - v / 10.0) + 1.0)
+/// Temperature dependence of a gate: both rates scale by `factor^((T - reference) / 10)`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Q10 {
+    pub factor: Float,
+    /// temperature in °C at which the rates were measured
+    pub reference: Float,
 }
 
-pub fn alpha_n(v: Float) -> Float {
-    let v_minus_10 = v - 10.0;
-    if v_minus_10.abs() < 0.01 {
-        0.1 + v_minus_10 / 200. + v_minus_10 * v_minus_10 / 12000.
-    } else {
-        0.01 * v_minus_10 / (1. - (-v_minus_10 / 10.).exp())
+impl Q10 {
+    pub fn phi(&self, temperature: Float) -> Float {
+        self.factor.powf((temperature - self.reference) / 10.0)
     }
 }
 
-pub fn beta_n(v: Float) -> Float {
-    Float::exp(-v / 80.0) / 8.0
+/// Hodgkin and Huxley's own temperature correction.
+pub const HH_Q10: Q10 = Q10 {
+    factor: 3.0,
+    reference: 6.3,
+};
+
+pub const GATES: [&str; 3] = ["m", "h", "n"];
+
+/// Kinetics of the gates in `GATES` order. `Default` is the original Hodgkin–Huxley fit.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Kinetics {
+    pub gates: [Gate; 3],
+    pub q10: [Q10; 3],
+}
+
+impl Default for Kinetics {
+    fn default() -> Self {
+        Self {
+            gates: [
+                Gate::Rates {
+                    alpha: ALPHA_M,
+                    beta: BETA_M,
+                },
+                Gate::Rates {
+                    alpha: ALPHA_H,
+                    beta: BETA_H,
+                },
+                Gate::Rates {
+                    alpha: ALPHA_N,
+                    beta: BETA_N,
+                },
+            ],
+            q10: [HH_Q10; 3],
+        }
+    }
+}
+
+impl Kinetics {
+    pub fn m(&self) -> &Gate {
+        &self.gates[0]
+    }
+    pub fn h(&self) -> &Gate {
+        &self.gates[1]
+    }
+    pub fn n(&self) -> &Gate {
+        &self.gates[2]
+    }
+}
+
+/// Change of a gate by a mutation: the voltage dependence shifts by `shift` mV and flattens by
+/// `slope` about the gate's half-activation, and the time constant scales by `tau_scale`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Modifier {
+    pub shift: Float,
+    pub slope: Float,
+    pub tau_scale: Float,
+}
+
+impl Default for Modifier {
+    fn default() -> Self {
+        Self {
+            shift: 0.0,
+            slope: 1.0,
+            tau_scale: 1.0,
+        }
+    }
+}
+
+impl Modifier {
+    /// potential at which the unmodified gate is where the modified one is at `v`: shifted,
+    /// and stretched by `slope` about the gate's half-activation
+    fn at(&self, gate: &Gate, v: Float) -> Float {
+        if self.slope == 1.0 {
+            return v - self.shift;
+        }
+        let v_half = gate.v_half();
+        v_half + (v - self.shift - v_half) / self.slope
+    }
+
+    /// rate factor undoing the stretch of linoid rates, whose asymptote
+    /// `rate (v - midpoint) / scale` would otherwise flatten with it
+    fn gain(&self, gate: &Gate) -> Float {
+        match gate {
+            Gate::Rates { alpha, beta }
+                if alpha.family == Family::Linoid || beta.family == Family::Linoid =>
+            {
+                self.slope
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// τ of `gate` so modified
+    pub fn tau(&self, gate: &Gate, v: Float) -> Float {
+        gate.tau(self.at(gate, v)) / self.gain(gate) * self.tau_scale
+    }
+
+    /// x_∞ of `gate` so modified
+    pub fn inf(&self, gate: &Gate, v: Float) -> Float {
+        gate.inf(self.at(gate, v))
+    }
+}
+
+/// Mutant channels, gating like the wild type modified per gate in `GATES` order, making up
+/// `fraction` of both the sodium and the potassium channels.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Mutation {
+    pub modifiers: [Modifier; 3],
+    pub fraction: Float,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_hodgkin_huxley() {
+        let close = |a: Float, b: Float| (a - b).abs() <= 1e-9 * a.abs().max(1.0);
+        for v in (-1000..=1500).map(|v| v as Float * 0.1) {
+            let (u_m, u_n) = (v - 25.0, v - 10.0);
+            if u_m.abs() > 1e-6 {
+                assert!(close(
+                    ALPHA_M.eval(v),
+                    0.1 * u_m / (1. - (-u_m / 10.).exp())
+                ));
+            }
+            if u_n.abs() > 1e-6 {
+                assert!(close(
+                    ALPHA_N.eval(v),
+                    0.01 * u_n / (1. - (-u_n / 10.).exp())
+                ));
+            }
+            assert!(close(BETA_M.eval(v), 4.0 * Float::exp(-v / 18.0)));
+            assert!(close(ALPHA_H.eval(v), 0.07 * Float::exp(-v / 20.0)));
+            assert!(close(
+                BETA_H.eval(v),
+                1.0 / (Float::exp(3.0 - v / 10.0) + 1.0)
+            ));
+            assert!(close(BETA_N.eval(v), Float::exp(-v / 80.0) / 8.0));
+        }
+    }
+
+    #[test]
+    fn linoid_is_continuous_at_singularity() {
+        assert_eq!(ALPHA_M.eval(25.0), 1.0);
+        for dv in [1e-2, 1e-4, -1e-4, -1e-2] {
+            let x: Float = dv / 10.0;
+            let exact = x / (1. - (-x).exp());
+            assert!((ALPHA_M.eval(25.0 + dv) - exact).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn boltzmann_approximates_rates() {
+        for gate in Kinetics::default().gates {
+            let approx = gate.to_boltzmann();
+            for v in [-20.0, 0.0, 20.0, 50.0] {
+                assert!((gate.tau(v) - approx.tau(v)).abs() < 1e-9);
+            }
+            let Gate::Boltzmann { v_half, .. } = approx else {
+                unreachable!()
+            };
+            assert!((gate.inf(v_half) - 0.5).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn modifiers_shift_flatten_and_slow() {
+        for gate in Kinetics::default().gates {
+            for gate in [gate.to_boltzmann(), gate] {
+                let identity = Modifier::default();
+                let shifted = Modifier {
+                    shift: 7.0,
+                    tau_scale: 2.0,
+                    ..identity
+                };
+                let flat = Modifier {
+                    slope: 2.0,
+                    ..identity
+                };
+                for v in [-30.0, 0.0, 12.5, 40.0] {
+                    assert_eq!(identity.inf(&gate, v), gate.inf(v));
+                    assert_eq!(identity.tau(&gate, v), gate.tau(v));
+                    assert!((shifted.inf(&gate, v + 7.0) - gate.inf(v)).abs() < 1e-12);
+                    assert!((shifted.tau(&gate, v + 7.0) - 2.0 * gate.tau(v)).abs() < 1e-9);
+                }
+                let steepness = |m: &Modifier| (m.inf(&gate, 30.0) - m.inf(&gate, -10.0)).abs();
+                assert!(steepness(&flat) < steepness(&identity));
+            }
+        }
+    }
+
+    #[test]
+    fn slope_keeps_linoid_asymptote() {
+        let flat = Modifier {
+            slope: 2.0,
+            ..Default::default()
+        };
+        let kinetics = Kinetics::default();
+        // far above the midpoint the linoid α dominates and 1/τ tends to its asymptote, whose
+        // gradient the slope keeps
+        let gradient = |tau: &dyn Fn(Float) -> Float| 1.0 / tau(400.0) - 1.0 / tau(300.0);
+        for gate in [kinetics.m(), kinetics.n()] {
+            let (plain, modified) = (gradient(&|v| gate.tau(v)), gradient(&|v| flat.tau(gate, v)));
+            assert!(
+                (modified - plain).abs() < 0.02 * plain,
+                "{plain} {modified}"
+            );
+        }
+    }
+
+    #[test]
+    fn slope_keeps_half_activation() {
+        for gate in Kinetics::default().gates {
+            for gate in [gate.to_boltzmann(), gate] {
+                let v_half = gate.v_half();
+                for slope in [0.5, 1.0, 2.0, 3.0] {
+                    let modifier = Modifier {
+                        slope,
+                        ..Default::default()
+                    };
+                    assert!((modifier.inf(&gate, v_half) - 0.5).abs() < 1e-9);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,310 @@
+use crate::{
+    Float,
+    hh::{Axon, Params},
+};
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+/// A quantity that can be recorded during a run.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Var {
+    V,
+    M,
+    H,
+    N,
+    INa,
+    IK,
+    /// leak current
+    IL,
+    /// sum of the ionic currents
+    IIon,
+    /// capacitive current, `C dV/dt`
+    ICap,
+    GNa,
+    GK,
+    /// injected current
+    Stimulus,
+}
+
+impl Var {
+    pub const ALL: [Var; 12] = [
+        Var::V,
+        Var::M,
+        Var::H,
+        Var::N,
+        Var::INa,
+        Var::IK,
+        Var::IL,
+        Var::IIon,
+        Var::ICap,
+        Var::GNa,
+        Var::GK,
+        Var::Stimulus,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Var::V => "V",
+            Var::M => "m",
+            Var::H => "h",
+            Var::N => "n",
+            Var::INa => "I_Na",
+            Var::IK => "I_K",
+            Var::IL => "I_L",
+            Var::IIon => "I_ion",
+            Var::ICap => "I_C",
+            Var::GNa => "g_Na",
+            Var::GK => "g_K",
+            Var::Stimulus => "I_stim",
+        }
+    }
+
+    pub fn value(&self, t: Float, axon: &Axon, params: &Params, stimulus: Float) -> Float {
+        match self {
+            Var::V => axon.v(),
+            Var::M => axon.m(),
+            Var::H => axon.h(),
+            Var::N => axon.n(),
+            Var::INa => axon.i_na(params, t),
+            Var::IK => axon.i_k(params, t),
+            Var::IL => axon.i_l(params),
+            Var::IIon => axon.i_ion(params, t),
+            Var::ICap => axon.i_cap(params, t, stimulus),
+            Var::GNa => axon.cond_na(params, t),
+            Var::GK => axon.cond_k(params, t),
+            Var::Stimulus => stimulus,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Storage {
+    /// keep every sample
+    Memory,
+    /// keep the last `Config::capacity` samples
+    Ring,
+    /// write every sample to a CSV file, keeping the last `Config::capacity` for display
+    Disk { path: String },
+}
+
+impl Storage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Storage::Memory => "memory",
+            Storage::Ring => "ring buffer",
+            Storage::Disk { .. } => "stream to disk",
+        }
+    }
+}
+
+/// What to record during a run, how often and where to.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Config {
+    pub vars: Vec<Var>,
+    /// ms between samples, rounded to a whole number of integration steps
+    pub interval: Float,
+    pub storage: Storage,
+    /// samples kept in memory by `Storage::Ring` and `Storage::Disk`
+    pub capacity: usize,
+}
+
+/// The state and the injected current; the other currents and the conductances are opt-in, as
+/// recording everything triples the memory a run takes.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vars: vec![Var::V, Var::M, Var::H, Var::N, Var::Stimulus],
+            interval: 0.01,
+            storage: Storage::Memory,
+            capacity: 100_000,
+        }
+    }
+}
+
+impl Config {
+    /// integration steps per sample
+    pub fn stride(&self, dt: Float) -> usize {
+        ((self.interval / dt).round() as usize).max(1)
+    }
+
+    /// samples a run of `steps` integration steps of `dt` produces
+    pub fn samples(&self, steps: usize, dt: Float) -> usize {
+        steps.div_ceil(self.stride(dt))
+    }
+
+    /// samples held in memory at once for such a run
+    pub fn kept(&self, steps: usize, dt: Float) -> usize {
+        let samples = self.samples(steps, dt);
+        match self.storage {
+            Storage::Memory => samples,
+            Storage::Ring | Storage::Disk { .. } => samples.min(self.capacity),
+        }
+    }
+
+    /// bytes of memory the recording of such a run needs
+    pub fn memory(&self, steps: usize, dt: Float) -> usize {
+        self.kept(steps, dt) * (self.vars.len() + 1) * size_of::<Float>()
+    }
+}
+
+/// Samples of a run, as configured by a `Config`.
+#[derive(Default)]
+pub struct Recorder {
+    pub config: Config,
+    pub t: VecDeque<Float>,
+    /// one column per variable of `config.vars`
+    columns: Vec<VecDeque<Float>>,
+    /// samples recorded so far, including those no longer in memory
+    pub recorded: usize,
+    writer: Option<BufWriter<File>>,
+    /// the first write error, after which streaming stops
+    pub error: Option<String>,
+}
+
+impl Recorder {
+    /// Start a recording, creating the CSV file of `Storage::Disk`.
+    pub fn new(config: Config) -> Result<Self, String> {
+        let writer = match &config.storage {
+            Storage::Disk { path } => {
+                let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+                let mut writer = BufWriter::new(file);
+                let header: Vec<&str> = config.vars.iter().map(Var::name).collect();
+                writeln!(writer, "t,{}", header.join(",")).map_err(|e| format!("{path}: {e}"))?;
+                Some(writer)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            columns: vec![VecDeque::new(); config.vars.len()],
+            config,
+            writer,
+            ..Default::default()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn push(&mut self, t: Float, axon: &Axon, params: &Params, stimulus: Float) {
+        self.recorded += 1;
+        self.t.push_back(t);
+        for (column, var) in self.columns.iter_mut().zip(&self.config.vars) {
+            column.push_back(var.value(t, axon, params, stimulus));
+        }
+
+        if let Some(writer) = &mut self.writer {
+            let mut row = format!("{t}");
+            for column in &self.columns {
+                row += &format!(",{}", column.back().expect("pushed above"));
+            }
+            if let Err(e) = writeln!(writer, "{row}") {
+                self.error = Some(e.to_string());
+                self.writer = None;
+            }
+        }
+
+        if self.config.storage != Storage::Memory && self.t.len() > self.config.capacity {
+            self.t.pop_front();
+            for column in &mut self.columns {
+                column.pop_front();
+            }
+        }
+    }
+
+    /// samples of `var` in memory, if it is recorded
+    pub fn column(&self, var: Var) -> Option<&VecDeque<Float>> {
+        let idx = self.config.vars.iter().position(|v| *v == var)?;
+        Some(&self.columns[idx])
+    }
+
+    /// `var` at time `t`, interpolated linearly between the samples in memory around it
+    pub fn at(&self, var: Var, t: Float) -> Option<Float> {
+        let column = self.column(var)?;
+        let after = self.t.partition_point(|&s| s < t);
+        if after == self.t.len() {
+            return None;
+        }
+        if self.t[after] == t {
+            return Some(column[after]);
+        }
+        if after == 0 {
+            return None;
+        }
+        let (t0, t1) = (self.t[after - 1], self.t[after]);
+        let w = (t - t0) / (t1 - t0);
+        Some(column[after - 1] * (1.0 - w) + column[after] * w)
+    }
+
+    /// bytes currently held
+    pub fn memory(&self) -> usize {
+        self.t.len() * (self.columns.len() + 1) * size_of::<Float>()
+    }
+
+    /// write out whatever is buffered for the CSV file
+    pub fn flush(&mut self) {
+        if let Some(writer) = &mut self.writer
+            && let Err(e) = writer.flush()
+        {
+            self.error = Some(e.to_string());
+        }
+    }
+
+    /// stop streaming to disk, keeping what is in memory
+    pub fn close(&mut self) {
+        self.flush();
+        self.writer = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_keeps_the_latest() {
+        let config = Config {
+            vars: vec![Var::V, Var::Stimulus],
+            storage: Storage::Ring,
+            capacity: 3,
+            ..Default::default()
+        };
+        assert_eq!(config.memory(1000, 0.01), 3 * 3 * size_of::<Float>());
+        let mut recorder = Recorder::new(config).unwrap();
+        let params = Params::default();
+        for k in 0..10 {
+            recorder.push(
+                k as Float,
+                &params.steady_state(0.0),
+                &params,
+                -(k as Float),
+            );
+        }
+        assert_eq!(recorder.recorded, 10);
+        assert_eq!(recorder.t, [7.0, 8.0, 9.0]);
+        assert_eq!(recorder.column(Var::Stimulus).unwrap(), &[-7.0, -8.0, -9.0]);
+        assert!(recorder.column(Var::M).is_none());
+
+        assert_eq!(recorder.at(Var::Stimulus, 8.0), Some(-8.0));
+        assert_eq!(recorder.at(Var::Stimulus, 8.25), Some(-8.25));
+        assert_eq!(recorder.at(Var::Stimulus, 6.5), None);
+        assert_eq!(recorder.at(Var::Stimulus, 9.5), None);
+        assert_eq!(recorder.at(Var::M, 8.0), None);
+    }
+
+    #[test]
+    fn interval_in_whole_steps() {
+        let config = Config {
+            interval: 0.1,
+            ..Default::default()
+        };
+        assert_eq!(config.stride(0.01), 10);
+        assert_eq!(config.samples(1000, 0.01), 100);
+        assert_eq!(config.samples(1001, 0.01), 101);
+        assert_eq!(config.stride(0.5), 1);
+    }
+}
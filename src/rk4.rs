@@ -43,6 +43,36 @@ where
     new_x
 }
 
+/// `step` for a state whose length is only known at run time.
+pub fn step_slice<F>(system: F, x: &[Float], t: Float, dt: Float) -> Vec<Float>
+where
+    F: Fn(&[Float], Float, &mut [Float]),
+{
+    let n = x.len();
+    let mut xn = x.to_vec();
+    let mut kn = vec![Float::NAN; n];
+    let mut new_x = x.to_vec();
+
+    // weights of the four stages, and how far along the step each next one is evaluated
+    let stages = [
+        (1.0 / 6.0, 0.5),
+        (1.0 / 3.0, 0.5),
+        (1.0 / 3.0, 1.0),
+        (1.0 / 6.0, 0.0),
+    ];
+    let mut at = 0.0;
+    for (weight, next) in stages {
+        system(&xn, t + dt * at, &mut kn);
+        for i in 0..n {
+            new_x[i] += kn[i] * weight * dt;
+            xn[i] = x[i] + kn[i] * next * dt;
+        }
+        at = next;
+    }
+
+    new_x
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,4 +119,23 @@ mod tests {
 
         assert!((y[0] - Float::from(5.0)).abs() < 1e-5);
     }
+
+    #[test]
+    fn slice_matches_array() {
+        fn system(y: &[Float], x: Float, dy: &mut [Float]) {
+            dy[0] = y[1] * x.cos();
+            dy[1] = -y[0];
+        }
+
+        let mut array = [1.0, 0.5];
+        let mut slice = array.to_vec();
+        for i in 0..100 {
+            let t = Float::from(i) * 0.1;
+            array = step(|y, x, dy| system(y, x, dy), array, t, 0.1);
+            slice = step_slice(system, &slice, t, 0.1);
+        }
+        for (a, b) in array.iter().zip(&slice) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
 }
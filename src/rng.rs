@@ -0,0 +1,61 @@
+use crate::Float;
+
+/// xorshift64* generator. Small, fast and reproducible from a seed, which is all the
+/// stochastic parts of the playground need.
+#[derive(Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // scramble small seeds, and never start in the all-zero state, which xorshift can
+        // never leave
+        match seed ^ 0x9E37_79B9_7F4A_7C15 {
+            0 => Self(0x9E37_79B9_7F4A_7C15),
+            state => Self(state),
+        }
+    }
+
+    /// the `index`th of a family of independent generators, for random values that must be
+    /// reproducible in any order
+    pub fn stream(seed: u64, index: u64) -> Self {
+        // splitmix64, so that neighbouring indices give unrelated states
+        let mut z = seed.wrapping_add(index.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self::new(z ^ (z >> 31))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// uniform in [0, 1)
+    pub fn uniform(&mut self) -> Float {
+        (self.next_u64() >> 11) as Float / (1u64 << 53) as Float
+    }
+
+    /// standard normal, by the Box–Muller transform
+    pub fn normal(&mut self) -> Float {
+        let (u, v) = (1.0 - self.uniform(), self.uniform());
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+
+    /// uniform in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.uniform() * n as Float) as usize % n.max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_seed_gets_stuck() {
+        let mut rng = Rng::new(0x9E37_79B9_7F4A_7C15);
+        assert!((0..4).any(|_| rng.next_u64() != 0));
+    }
+}
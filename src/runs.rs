@@ -0,0 +1,187 @@
+use crate::{
+    Float,
+    hh::{Params, Setup},
+    record::{Recorder, Var},
+};
+
+use egui::Color32;
+
+/// colours given to runs in turn
+const PALETTE: [Color32; 8] = [
+    Color32::from_rgb(230, 120, 40),
+    Color32::from_rgb(60, 150, 220),
+    Color32::from_rgb(90, 180, 80),
+    Color32::from_rgb(200, 70, 160),
+    Color32::from_rgb(220, 190, 50),
+    Color32::from_rgb(120, 100, 220),
+    Color32::from_rgb(60, 190, 170),
+    Color32::from_rgb(200, 60, 60),
+];
+
+/// A completed run, kept for comparison.
+pub struct Run {
+    pub name: String,
+    pub color: Color32,
+    pub visible: bool,
+    /// never dropped to make room for newer runs
+    pub pinned: bool,
+    /// what the run was made with, to run it again
+    pub setup: Setup,
+    pub params: Params,
+    pub recorder: Recorder,
+}
+
+/// A recording to compare: the current run or a kept one.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Pick {
+    #[default]
+    Current,
+    Kept(usize),
+}
+
+pub struct Runs {
+    pub list: Vec<Run>,
+    /// unpinned runs kept, the oldest being dropped first
+    pub keep: usize,
+    /// plot `a` minus `b` instead of the runs themselves
+    pub diff: bool,
+    pub a: Pick,
+    pub b: Pick,
+    /// runs added so far, for naming and colouring them
+    added: usize,
+}
+
+impl Default for Runs {
+    fn default() -> Self {
+        Self {
+            list: Vec::new(),
+            keep: 8,
+            diff: false,
+            a: Pick::Current,
+            b: Pick::Kept(0),
+            added: 0,
+        }
+    }
+}
+
+impl Runs {
+    /// keep a finished recording, dropping the oldest unpinned runs beyond `keep`
+    pub fn add(&mut self, mut recorder: Recorder, setup: Setup, params: Params) {
+        recorder.close();
+        self.added += 1;
+        self.list.push(Run {
+            name: format!("Run {}", self.added),
+            color: PALETTE[(self.added - 1) % PALETTE.len()],
+            visible: true,
+            pinned: false,
+            setup,
+            params,
+            recorder,
+        });
+        self.trim();
+    }
+
+    /// drop the oldest unpinned runs beyond `keep`
+    pub fn trim(&mut self) {
+        while self.list.iter().filter(|r| !r.pinned).count() > self.keep {
+            let oldest = self.list.iter().position(|r| !r.pinned).expect("counted");
+            self.remove(oldest);
+        }
+    }
+
+    /// remove a run, keeping `a` and `b` on the same runs, or the current one if theirs is gone
+    pub fn remove(&mut self, idx: usize) {
+        self.list.remove(idx);
+        for pick in [&mut self.a, &mut self.b] {
+            match *pick {
+                Pick::Kept(i) if i == idx => *pick = Pick::Current,
+                Pick::Kept(i) if i > idx => *pick = Pick::Kept(i - 1),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn name(&self, pick: Pick) -> &str {
+        match pick {
+            Pick::Current => "current run",
+            Pick::Kept(idx) => self.list.get(idx).map_or("none", |r| &r.name),
+        }
+    }
+}
+
+/// `var` of `a` minus that of `b`, at the times of `a` where `b` has been recorded
+pub fn difference(a: &Recorder, b: &Recorder, var: Var) -> Vec<[Float; 2]> {
+    let Some(column) = a.column(var) else {
+        return Vec::new();
+    };
+    a.t.iter()
+        .zip(column)
+        .filter_map(|(&t, &x)| Some([t, x - b.at(var, t)?]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hh, record::Config};
+
+    fn recording(setup: &Setup, params: &Params) -> Recorder {
+        let mut recorder = Recorder::new(Config::default()).unwrap();
+        for (k, axon) in hh::simulate(setup, params).iter().enumerate() {
+            let t = k as Float * setup.dt;
+            recorder.push(t, axon, params, setup.current(t));
+        }
+        recorder
+    }
+
+    #[test]
+    fn keeps_pinned_and_follows_picks() {
+        let mut runs = Runs {
+            keep: 2,
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            runs.add(Recorder::default(), Setup::default(), Params::default());
+        }
+        runs.list[0].pinned = true;
+        runs.a = Pick::Kept(1);
+        runs.b = Pick::Kept(0);
+        for _ in 0..3 {
+            runs.add(Recorder::default(), Setup::default(), Params::default());
+        }
+        let names: Vec<&str> = runs.list.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["Run 1", "Run 4", "Run 5"]);
+        assert_eq!((runs.a, runs.b), (Pick::Current, Pick::Kept(0)));
+
+        runs.a = Pick::Kept(2);
+        runs.remove(1);
+        assert_eq!((runs.a, runs.b), (Pick::Kept(1), Pick::Kept(0)));
+        assert_eq!(runs.name(runs.a), "Run 5");
+    }
+
+    #[test]
+    fn difference_of_runs() {
+        let setup = Setup {
+            end: 20.0,
+            ..Default::default()
+        };
+        let before = Params::default();
+        let after = Params {
+            g_k: before.g_k * 0.5,
+            ..before.clone()
+        };
+        let (a, b) = (recording(&setup, &after), recording(&setup, &before));
+
+        let same = difference(&a, &a, Var::V);
+        assert_eq!(same.len(), a.len());
+        assert!(same.iter().all(|[_, d]| *d == 0.0));
+
+        let diff = difference(&a, &b, Var::V);
+        let (va, vb) = (a.column(Var::V).unwrap(), b.column(Var::V).unwrap());
+        for (k, [t, d]) in diff.iter().enumerate() {
+            assert_eq!(*t, a.t[k]);
+            assert!((d - (va[k] - vb[k])).abs() < 1e-12);
+        }
+        assert!(diff.iter().any(|[_, d]| d.abs() > 1.0));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{Float, fft};
+
+use std::f64::consts::PI;
+
+/// Taper applied to each segment before its transform, trading frequency resolution for
+/// leakage from strong peaks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    pub const ALL: [Window; 4] = [
+        Window::Rectangular,
+        Window::Hann,
+        Window::Hamming,
+        Window::Blackman,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Window::Rectangular => "rectangular",
+            Window::Hann => "Hann",
+            Window::Hamming => "Hamming",
+            Window::Blackman => "Blackman",
+        }
+    }
+
+    /// weight of sample `k` of a segment of `n`
+    pub fn weight(&self, k: usize, n: usize) -> Float {
+        let x = 2.0 * PI * k as Float / (n - 1).max(1) as Float;
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Hann => 0.5 - 0.5 * x.cos(),
+            Window::Hamming => 0.54 - 0.46 * x.cos(),
+            Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+        }
+    }
+}
+
+/// One-sided power spectral density of `samples` taken every `dt` ms, by Welch's method:
+/// the periodograms of half-overlapping segments of `segment` samples, each with its mean
+/// removed and tapered by `window`, averaged. `segment` must be a power of two; fewer samples
+/// give a single zero-padded segment. Frequencies in Hz, densities in units² per Hz.
+pub fn welch(samples: &[Float], dt: Float, segment: usize, window: Window) -> Vec<[Float; 2]> {
+    assert!(segment.is_power_of_two(), "segments of {segment} samples");
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let length = segment.min(samples.len());
+    let weights: Vec<Float> = (0..length).map(|k| window.weight(k, length)).collect();
+    let power: Float = weights.iter().map(|w| w * w).sum();
+    let rate = 1e3 / dt;
+
+    let mut density = vec![0.0; segment / 2 + 1];
+    let mut segments = 0;
+    let mut start = 0;
+    while start + length <= samples.len() {
+        let piece = &samples[start..start + length];
+        let mean = piece.iter().sum::<Float>() / length as Float;
+        let mut tapered = vec![0.0; segment];
+        for ((t, x), w) in tapered.iter_mut().zip(piece).zip(&weights) {
+            *t = (x - mean) * w;
+        }
+        for (d, x) in density.iter_mut().zip(fft::real(&tapered)) {
+            *d += x.norm_sqr();
+        }
+        segments += 1;
+        start += (length / 2).max(1);
+    }
+
+    let last = density.len() - 1;
+    density
+        .iter()
+        .enumerate()
+        .map(|(k, d)| {
+            // both halves of the spectrum but for DC and Nyquist, which have no mirror
+            let sides = if k == 0 || k == last { 1.0 } else { 2.0 };
+            let f = k as Float * rate / segment as Float;
+            [f, sides * d / (segments as Float * rate * power)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hh::{self, Noise, Params, Pulse, Setup},
+        rng::Rng,
+        spikes,
+    };
+
+    #[test]
+    fn finds_a_sine_and_keeps_the_variance() {
+        let dt = 0.1;
+        let mut rng = Rng::new(7);
+        let samples: Vec<Float> = (0..20_000)
+            .map(|k| {
+                let t = k as Float * dt * 1e-3;
+                3.0 * (2.0 * PI * 80.0 * t).sin() + rng.normal()
+            })
+            .collect();
+        for window in Window::ALL {
+            let psd = welch(&samples, dt, 1024, window);
+            let peak = psd.iter().max_by(|a, b| a[1].total_cmp(&b[1])).unwrap();
+            assert!((peak[0] - 80.0).abs() < 10.0, "{}", window.name());
+
+            // Parseval: the density integrates to the variance, 9/2 + 1
+            let df = psd[1][0];
+            let variance: Float = psd.iter().map(|p| p[1] * df).sum();
+            assert!((variance - 5.5).abs() < 0.3, "{} {variance}", window.name());
+        }
+    }
+
+    #[test]
+    fn noise_reveals_subthreshold_resonance() {
+        let setup = Setup {
+            end: 4000.0,
+            dt: 0.025,
+            pulse: Pulse {
+                start: 0.0,
+                end: 0.0,
+                magnitude: 0.0,
+            },
+            noise: Some(Noise::default()),
+            ..Default::default()
+        };
+        let params = Params::default();
+        let v: Vec<Float> = hh::simulate(&setup, &params)
+            .iter()
+            .map(|a| a.v())
+            .collect();
+        assert!(v.iter().all(|v| *v < spikes::THRESHOLD));
+        let psd = welch(&v, setup.dt, 8192, Window::Hann);
+        let peak = psd
+            .iter()
+            .filter(|p| p[0] > 5.0)
+            .max_by(|a, b| a[1].total_cmp(&b[1]))
+            .unwrap();
+        assert!(peak[0] > 40.0 && peak[0] < 100.0, "{peak:?}");
+    }
+}
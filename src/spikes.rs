@@ -0,0 +1,58 @@
+use crate::{Float, hh::Axon};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Spike {
+    /// upward threshold crossing, linearly interpolated between samples
+    pub t: Float,
+    pub peak: Float,
+}
+
+/// Voltage threshold, relative to rest, above which an excursion counts as a spike.
+pub const THRESHOLD: Float = 50.0;
+
+/// Every excursion of `(t, v)` samples above `threshold`. A spike still above threshold at the
+/// end of the samples is kept, with the highest voltage seen so far as its peak.
+pub fn detect(samples: impl IntoIterator<Item = (Float, Float)>, threshold: Float) -> Vec<Spike> {
+    let mut spikes = Vec::new();
+    let mut prev: Option<(Float, Float)> = None;
+    let mut current: Option<Spike> = None;
+
+    for (t, v) in samples {
+        match (&mut current, prev) {
+            (Some(spike), _) => {
+                spike.peak = spike.peak.max(v);
+                if v < threshold {
+                    spikes.extend(current.take());
+                }
+            }
+            (None, Some(before)) => {
+                if let Some(t) = crossing(before, (t, v), threshold) {
+                    current = Some(Spike { t, peak: v });
+                }
+            }
+            _ => {}
+        }
+        prev = Some((t, v));
+    }
+    spikes.extend(current);
+    spikes
+}
+
+/// Time of an upward crossing of `threshold` between two `(t, v)` samples, linearly
+/// interpolated.
+pub fn crossing(
+    (t0, v0): (Float, Float),
+    (t1, v1): (Float, Float),
+    threshold: Float,
+) -> Option<Float> {
+    (v0 < threshold && v1 >= threshold).then(|| t0 + (t1 - t0) * (threshold - v0) / (v1 - v0))
+}
+
+/// Spikes of a simulated `history`, sampled every `dt` from 0, above `THRESHOLD`.
+pub fn of_history(history: &[Axon], dt: Float) -> Vec<Spike> {
+    let v = history
+        .iter()
+        .enumerate()
+        .map(|(k, a)| (k as Float * dt, a.v()));
+    detect(v, THRESHOLD)
+}
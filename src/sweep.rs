@@ -0,0 +1,443 @@
+use crate::{
+    Float,
+    hh::{self, Params, Setup},
+    spikes,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+#[cfg(target_arch = "wasm32")]
+use crate::FRAME_BUDGET;
+
+/// A quantity a sweep varies.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Knob {
+    /// pulse magnitude
+    Amplitude,
+    /// pulse length, keeping its start
+    Duration,
+    GNa,
+    GK,
+    GL,
+    Temperature,
+    V0,
+    Dt,
+}
+
+impl Knob {
+    pub const ALL: [Knob; 8] = [
+        Knob::Amplitude,
+        Knob::Duration,
+        Knob::GNa,
+        Knob::GK,
+        Knob::GL,
+        Knob::Temperature,
+        Knob::V0,
+        Knob::Dt,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Knob::Amplitude => "stimulus amplitude",
+            Knob::Duration => "stimulus duration",
+            Knob::GNa => "g_Na",
+            Knob::GK => "g_K",
+            Knob::GL => "g_L",
+            Knob::Temperature => "temperature",
+            Knob::V0 => "initial voltage",
+            Knob::Dt => "time step",
+        }
+    }
+
+    /// a sensible range to sweep by default
+    pub fn range(&self) -> (Float, Float) {
+        match self {
+            Knob::Amplitude => (0.0, 20.0),
+            Knob::Duration => (0.1, 5.0),
+            Knob::GNa => (0.0, 240.0),
+            Knob::GK => (0.0, 72.0),
+            Knob::GL => (0.0, 1.0),
+            Knob::Temperature => (0.0, 30.0),
+            Knob::V0 => (-20.0, 20.0),
+            Knob::Dt => (0.005, 0.1),
+        }
+    }
+
+    /// values the knob may take, as its editor elsewhere allows; a time step much smaller
+    /// would take hours and gigabytes to simulate
+    pub fn bounds(&self) -> (Float, Float) {
+        match self {
+            Knob::Amplitude => (Float::NEG_INFINITY, Float::INFINITY),
+            Knob::Duration | Knob::GNa | Knob::GK | Knob::GL => (0.0, Float::INFINITY),
+            Knob::Temperature => (-10.0, 50.0),
+            Knob::V0 => (hh::consts::E_K, hh::consts::E_NA),
+            Knob::Dt => (0.001, 1.0),
+        }
+    }
+
+    /// set the knob to `x`, clamped to its bounds
+    pub fn set(&self, setup: &mut Setup, params: &mut Params, x: Float) {
+        let (lo, hi) = self.bounds();
+        let x = x.clamp(lo, hi);
+        match self {
+            Knob::Amplitude => setup.pulse.magnitude = x,
+            Knob::Duration => setup.pulse.end = setup.pulse.start + x,
+            Knob::GNa => params.g_na = x,
+            Knob::GK => params.g_k = x,
+            Knob::GL => params.g_l = x,
+            Knob::Temperature => params.temperature = x,
+            Knob::V0 => setup.v0 = x,
+            Knob::Dt => setup.dt = x,
+        }
+    }
+}
+
+/// Evenly spaced values of a knob, ends included.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Axis {
+    pub knob: Knob,
+    pub from: Float,
+    pub to: Float,
+    pub points: usize,
+}
+
+impl Axis {
+    pub fn new(knob: Knob) -> Self {
+        let (from, to) = knob.range();
+        Self {
+            knob,
+            from,
+            to,
+            points: 21,
+        }
+    }
+
+    /// distance between neighbouring values, 1 for a single one
+    pub fn step(&self) -> Float {
+        if self.points > 1 {
+            (self.to - self.from) / (self.points - 1) as Float
+        } else {
+            1.0
+        }
+    }
+
+    pub fn value(&self, idx: usize) -> Float {
+        self.from + self.step() * idx as Float
+    }
+
+    /// index of the value nearest `x`, if within half a step of one
+    pub fn index(&self, x: Float) -> Option<usize> {
+        let idx = ((x - self.from) / self.step()).round();
+        (idx >= 0.0 && idx < self.points as Float).then_some(idx as usize)
+    }
+}
+
+/// What is computed from each run of a sweep.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Metric {
+    SpikeCount,
+    /// ms from the pulse start to the first spike, NaN without one
+    FirstSpikeLatency,
+    PeakV,
+    /// 1 when the integration blew up, else 0
+    Diverged,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 4] = [
+        Metric::SpikeCount,
+        Metric::FirstSpikeLatency,
+        Metric::PeakV,
+        Metric::Diverged,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::SpikeCount => "spike count",
+            Metric::FirstSpikeLatency => "first spike latency",
+            Metric::PeakV => "peak V",
+            Metric::Diverged => "diverged",
+        }
+    }
+
+    pub fn evaluate(&self, setup: &Setup, params: &Params) -> Float {
+        let history = hh::simulate(setup, params);
+        let v = || history.iter().map(|a| a.v());
+        match self {
+            Metric::SpikeCount => spikes::of_history(&history, setup.dt).len() as Float,
+            Metric::FirstSpikeLatency => {
+                let start = match setup.replay {
+                    Some(_) => 0.0,
+                    None => setup.pulse.start,
+                };
+                spikes::of_history(&history, setup.dt)
+                    .iter()
+                    .find(|s| s.t >= start)
+                    .map_or(Float::NAN, |s| s.t - start)
+            }
+            Metric::PeakV => v().fold(Float::NEG_INFINITY, Float::max),
+            Metric::Diverged => {
+                let diverged = v().any(|v| !v.is_finite() || v.abs() > 1e3);
+                Float::from(u8::from(diverged))
+            }
+        }
+    }
+}
+
+/// Everything a sweep needs to evaluate its points, independently of one another.
+#[derive(Clone)]
+pub struct Plan {
+    pub x: Axis,
+    /// second knob, for a 2-D sweep
+    pub y: Option<Axis>,
+    pub metric: Metric,
+    pub setup: Setup,
+    pub params: Params,
+}
+
+impl Plan {
+    pub fn len(&self) -> usize {
+        self.x.points * self.y.as_ref().map_or(1, |y| y.points)
+    }
+
+    /// setup and parameters of point `idx`, counting along `x` first
+    pub fn point(&self, idx: usize) -> (Setup, Params) {
+        let (mut setup, mut params) = (self.setup.clone(), self.params.clone());
+        let (ix, iy) = (idx % self.x.points, idx / self.x.points);
+        self.x.knob.set(&mut setup, &mut params, self.x.value(ix));
+        if let Some(y) = &self.y {
+            y.knob.set(&mut setup, &mut params, y.value(iy));
+        }
+        (setup, params)
+    }
+
+    pub fn evaluate(&self, idx: usize) -> Float {
+        let (setup, params) = self.point(idx);
+        self.metric.evaluate(&setup, &params)
+    }
+
+    /// point nearest to plot coordinates `x`, `y`; `y` is ignored by a 1-D sweep
+    pub fn at(&self, x: Float, y: Float) -> Option<usize> {
+        let ix = self.x.index(x)?;
+        let iy = match &self.y {
+            Some(axis) => axis.index(y)?,
+            None => 0,
+        };
+        Some(iy * self.x.points + ix)
+    }
+}
+
+/// Evaluates every point of a plan, in parallel on native builds. Dropping it stops the
+/// threads.
+pub struct Sweep {
+    pub plan: Plan,
+    /// metric of each point, once evaluated
+    pub values: Vec<Option<Float>>,
+    pub done: usize,
+    #[cfg(not(target_arch = "wasm32"))]
+    results: mpsc::Receiver<(usize, Float)>,
+    #[cfg(not(target_arch = "wasm32"))]
+    cancelled: Arc<AtomicBool>,
+    /// next point to evaluate
+    #[cfg(target_arch = "wasm32")]
+    next: usize,
+}
+
+impl Sweep {
+    pub fn start(plan: Plan) -> Self {
+        let values = vec![None; plan.len()];
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (sender, results) = mpsc::channel();
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let next = Arc::new(AtomicUsize::new(0));
+            let threads = thread::available_parallelism().map_or(1, |n| n.get());
+            for _ in 0..threads.min(plan.len()) {
+                let (plan, sender) = (plan.clone(), sender.clone());
+                let (cancelled, next) = (cancelled.clone(), next.clone());
+                thread::spawn(move || {
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        if idx >= plan.len() || cancelled.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        // a panicking point fails alone rather than stalling the sweep
+                        let value = panic::catch_unwind(AssertUnwindSafe(|| plan.evaluate(idx)));
+                        if sender.send((idx, value.unwrap_or(Float::NAN))).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+            Self {
+                plan,
+                values,
+                done: 0,
+                results,
+                cancelled,
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        Self {
+            plan,
+            values,
+            done: 0,
+            next: 0,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.done == self.plan.len()
+    }
+
+    /// collect whatever the threads have finished since the last call, without waiting; should
+    /// every thread have died, the points left fail as NaN
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn step(&mut self) {
+        loop {
+            match self.results.try_recv() {
+                Ok((idx, value)) => {
+                    self.values[idx] = Some(value);
+                    self.done += 1;
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    for value in self.values.iter_mut().filter(|v| v.is_none()) {
+                        *value = Some(Float::NAN);
+                    }
+                    self.done = self.values.len();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// evaluate points for `FRAME_BUDGET` seconds, as there are no worker threads on wasm
+    #[cfg(target_arch = "wasm32")]
+    pub fn step(&mut self) {
+        let start = miniquad::date::now();
+        while self.next < self.plan.len() && miniquad::date::now() - start < FRAME_BUDGET {
+            self.values[self.next] = Some(self.plan.evaluate(self.next));
+            self.next += 1;
+            self.done += 1;
+        }
+    }
+
+    /// smallest and largest finite value so far
+    pub fn range(&self) -> Option<(Float, Float)> {
+        let finite = self.values.iter().flatten().filter(|v| v.is_finite());
+        finite.fold(None, |range, &v| match range {
+            None => Some((v, v)),
+            Some((lo, hi)) => Some((Float::min(lo, v), Float::max(hi, v))),
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Sweep {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn wait(sweep: &mut Sweep) {
+        while !sweep.finished() {
+            sweep.step();
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn amplitude_sweep_finds_threshold() {
+        let plan = Plan {
+            x: Axis {
+                points: 11,
+                ..Axis::new(Knob::Amplitude)
+            },
+            y: None,
+            metric: Metric::SpikeCount,
+            setup: Setup::default(),
+            params: Params::default(),
+        };
+        let mut sweep = Sweep::start(plan);
+        wait(&mut sweep);
+        let counts: Vec<Float> = sweep.values.iter().map(|v| v.unwrap()).collect();
+        assert_eq!(counts[0], 0.0);
+        assert_eq!(counts[10], 1.0);
+        // no spike below threshold, one above
+        assert!(counts.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(sweep.range(), Some((0.0, 1.0)));
+    }
+
+    #[test]
+    fn grid_matches_serial_evaluation() {
+        let plan = Plan {
+            x: Axis {
+                points: 4,
+                ..Axis::new(Knob::Amplitude)
+            },
+            y: Some(Axis {
+                knob: Knob::GK,
+                from: 18.0,
+                to: 54.0,
+                points: 3,
+            }),
+            metric: Metric::PeakV,
+            setup: Setup::default(),
+            params: Params::default(),
+        };
+        let mut sweep = Sweep::start(plan.clone());
+        wait(&mut sweep);
+        assert_eq!(sweep.values.len(), 12);
+        for (idx, value) in sweep.values.iter().enumerate() {
+            assert_eq!(value.unwrap(), plan.evaluate(idx));
+        }
+
+        let (setup, params) = plan.point(7);
+        assert_eq!(setup.pulse.magnitude, plan.x.value(3));
+        assert_eq!(params.g_k, 36.0);
+        assert_eq!(
+            plan.at(plan.x.value(3) + 0.4 * plan.x.step(), 37.0),
+            Some(7)
+        );
+        assert_eq!(plan.at(-5.0, 36.0), None);
+    }
+
+    #[test]
+    fn knobs_stay_in_bounds() {
+        let plan = Plan {
+            x: Axis {
+                knob: Knob::Dt,
+                from: -0.1,
+                to: 0.01,
+                points: 2,
+            },
+            y: None,
+            metric: Metric::PeakV,
+            setup: Setup {
+                end: 5.0,
+                ..Setup::default()
+            },
+            params: Params::default(),
+        };
+        assert_eq!(plan.point(0).0.dt, 0.001);
+        let mut sweep = Sweep::start(plan);
+        wait(&mut sweep);
+        assert!(sweep.values.iter().all(|v| v.unwrap().is_finite()));
+    }
+}
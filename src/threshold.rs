@@ -0,0 +1,543 @@
+use crate::{
+    Float,
+    hh::{self, Axon, Params, Pulse, Setup},
+    spikes,
+};
+
+/// ms simulated after a pulse for a spike to show up, as latency grows near threshold
+const SETTLE: Float = 30.0;
+
+/// relative precision of a threshold search
+const TOLERANCE: Float = 1e-3;
+
+/// Sign of the stimulating current.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Polarity {
+    Depolarizing,
+    /// firing on release from hyperpolarization, i.e. anode break excitation
+    Hyperpolarizing,
+}
+
+impl Polarity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Polarity::Depolarizing => "depolarizing",
+            Polarity::Hyperpolarizing => "hyperpolarizing (anode break)",
+        }
+    }
+
+    fn sign(&self) -> Float {
+        match self {
+            Polarity::Depolarizing => 1.0,
+            Polarity::Hyperpolarizing => -1.0,
+        }
+    }
+}
+
+/// whether a run spikes after `from`
+pub fn fires(setup: &Setup, params: &Params, from: Float) -> bool {
+    spikes::of_history(&hh::simulate(setup, params), setup.dt)
+        .iter()
+        .any(|s| s.t >= from)
+}
+
+/// Smallest magnitude in `0..=max` that fires, by bisection, one trial at a time: `trial` is
+/// the magnitude to simulate next and `record` takes whether it fired. The outcome is None if
+/// even `max` does not fire, or if zero already does.
+struct Bisection {
+    max: Float,
+    lo: Float,
+    hi: Float,
+    /// trials recorded so far, the first two being zero and `max`
+    trials: usize,
+    outcome: Option<Option<Float>>,
+}
+
+impl Bisection {
+    fn new(max: Float) -> Self {
+        Self {
+            max,
+            lo: 0.0,
+            hi: max,
+            trials: 0,
+            outcome: None,
+        }
+    }
+
+    fn trial(&self) -> Float {
+        match self.trials {
+            0 => 0.0,
+            1 => self.max,
+            _ => 0.5 * (self.lo + self.hi),
+        }
+    }
+
+    fn record(&mut self, fired: bool) {
+        match (self.trials, fired) {
+            (0, true) | (1, false) => self.outcome = Some(None),
+            (0 | 1, _) => {}
+            (_, true) => self.hi = self.trial(),
+            (_, false) => self.lo = self.trial(),
+        }
+        self.trials += 1;
+        if self.outcome.is_none() && self.trials >= 2 && self.hi - self.lo <= TOLERANCE * self.hi {
+            self.outcome = Some(Some(self.hi));
+        }
+    }
+}
+
+/// `setup` with a lone pulse of `duration` ms, simulated long enough after it for a spike to
+/// show up
+fn pulse_setup(setup: &Setup, duration: Float) -> Setup {
+    let mut setup = Setup {
+        replay: None,
+        ..setup.clone()
+    };
+    setup.pulse.end = setup.pulse.start + duration;
+    setup.end = setup.pulse.end + SETTLE;
+    setup
+}
+
+/// `count` durations spaced evenly on a log scale from `from` to `to`
+pub fn log_spaced(from: Float, to: Float, count: usize) -> Vec<Float> {
+    if count < 2 {
+        return vec![from];
+    }
+    let ratio = (to / from).ln() / (count - 1) as Float;
+    (0..count)
+        .map(|k| from * (ratio * k as Float).exp())
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Law {
+    Lapicque,
+    Weiss,
+}
+
+impl Law {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Law::Lapicque => "Lapicque",
+            Law::Weiss => "Weiss",
+        }
+    }
+
+    pub fn fit(&self, durations: &[Float], thresholds: &[Float]) -> Option<Fit> {
+        match self {
+            Law::Lapicque => lapicque(durations, thresholds),
+            Law::Weiss => weiss(durations, thresholds),
+        }
+    }
+}
+
+/// A strength–duration law fitted to thresholds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Fit {
+    pub law: Law,
+    pub rheobase: Float,
+    pub chronaxie: Float,
+    /// root mean square of the relative residuals
+    pub error: Float,
+}
+
+impl Fit {
+    /// threshold the law predicts for a pulse of `duration` ms
+    pub fn threshold(&self, duration: Float) -> Float {
+        match self.law {
+            Law::Lapicque => {
+                let tau = self.chronaxie / Float::ln(2.0);
+                self.rheobase / (1.0 - (-duration / tau).exp())
+            }
+            Law::Weiss => self.rheobase * (1.0 + self.chronaxie / duration),
+        }
+    }
+}
+
+/// Lapicque's `I = I_rh / (1 - exp(-d / τ))`, whose chronaxie is `τ ln 2`.
+fn lapicque(durations: &[Float], thresholds: &[Float]) -> Option<Fit> {
+    if durations.len() < 2 {
+        return None;
+    }
+    // for a given τ the best rheobase has a closed form, leaving a 1-D search over log τ
+    let fit = |log_tau: Float| {
+        let tau = log_tau.exp();
+        let shape: Vec<Float> = durations
+            .iter()
+            .zip(thresholds)
+            .map(|(d, i)| 1.0 / (1.0 - (-d / tau).exp()) / i)
+            .collect();
+        let rheobase = shape.iter().sum::<Float>() / shape.iter().map(|g| g * g).sum::<Float>();
+        let error = shape
+            .iter()
+            .map(|g| (rheobase * g - 1.0).powi(2))
+            .sum::<Float>()
+            / shape.len() as Float;
+        (rheobase, tau, error.sqrt())
+    };
+
+    let ratio = (5.0_f64.sqrt() - 1.0) / 2.0;
+    let (mut a, mut b) = ((1e-3 as Float).ln(), (1e3 as Float).ln());
+    while b - a > 1e-9 {
+        let (c, d) = (b - ratio * (b - a), a + ratio * (b - a));
+        if fit(c).2 < fit(d).2 {
+            b = d;
+        } else {
+            a = c;
+        }
+    }
+    let (rheobase, tau, error) = fit(0.5 * (a + b));
+    Some(Fit {
+        law: Law::Lapicque,
+        rheobase,
+        chronaxie: tau * Float::ln(2.0),
+        error,
+    })
+}
+
+/// Weiss's `I = I_rh (1 + c / d)`, fitted as the linear charge `I d = I_rh d + I_rh c`.
+fn weiss(durations: &[Float], thresholds: &[Float]) -> Option<Fit> {
+    let n = durations.len() as Float;
+    if durations.len() < 2 {
+        return None;
+    }
+    let charge: Vec<Float> = durations
+        .iter()
+        .zip(thresholds)
+        .map(|(d, i)| d * i)
+        .collect();
+    let (mean_d, mean_q) = (
+        durations.iter().sum::<Float>() / n,
+        charge.iter().sum::<Float>() / n,
+    );
+    let (mut sdq, mut sdd) = (0.0, 0.0);
+    for (d, q) in durations.iter().zip(&charge) {
+        sdq += (d - mean_d) * (q - mean_q);
+        sdd += (d - mean_d) * (d - mean_d);
+    }
+    let rheobase = sdq / sdd;
+    let chronaxie = (mean_q - rheobase * mean_d) / rheobase;
+    let error = durations
+        .iter()
+        .zip(thresholds)
+        .map(|(d, i)| (rheobase * (1.0 + chronaxie / d) / i - 1.0).powi(2))
+        .sum::<Float>()
+        / n;
+    (rheobase.is_finite() && chronaxie.is_finite()).then_some(Fit {
+        law: Law::Weiss,
+        rheobase,
+        chronaxie,
+        error: error.sqrt(),
+    })
+}
+
+/// Finds the threshold of each duration in turn, one simulation per `step`, so it can run
+/// alongside the UI.
+pub struct StrengthDuration {
+    pub setup: Setup,
+    pub params: Params,
+    pub polarity: Polarity,
+    /// largest magnitude tried
+    pub max: Float,
+    pub durations: Vec<Float>,
+    /// one per duration done so far, None where `max` did not fire
+    pub thresholds: Vec<Option<Float>>,
+    /// search for the threshold of the next duration
+    bisection: Bisection,
+}
+
+impl StrengthDuration {
+    pub fn new(
+        setup: &Setup,
+        params: &Params,
+        polarity: Polarity,
+        max: Float,
+        durations: Vec<Float>,
+    ) -> Self {
+        Self {
+            setup: setup.clone(),
+            params: params.clone(),
+            polarity,
+            max,
+            durations,
+            thresholds: Vec::new(),
+            bisection: Bisection::new(max),
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.thresholds.len() == self.durations.len()
+    }
+
+    pub fn step(&mut self) {
+        let Some(&duration) = self.durations.get(self.thresholds.len()) else {
+            return;
+        };
+        let mut setup = pulse_setup(&self.setup, duration);
+        setup.pulse.magnitude = self.polarity.sign() * self.bisection.trial();
+        let from = setup.pulse.start;
+        self.bisection.record(fires(&setup, &self.params, from));
+        if let Some(threshold) = self.bisection.outcome {
+            self.thresholds.push(threshold);
+            self.bisection = Bisection::new(self.max);
+        }
+    }
+
+    /// durations that fired, with their thresholds
+    pub fn found(&self) -> (Vec<Float>, Vec<Float>) {
+        self.durations
+            .iter()
+            .zip(&self.thresholds)
+            .filter_map(|(&d, &i)| Some((d, i?)))
+            .unzip()
+    }
+}
+
+/// What a `PairedPulse` step works on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    Conditioning,
+    Control,
+    Intervals,
+}
+
+/// Threshold of a test pulse following a conditioning pulse, for each interval between their
+/// onsets in turn, after that of the test pulse alone. One simulation per `step`.
+pub struct PairedPulse {
+    /// `setup.pulse` is the conditioning pulse
+    pub setup: Setup,
+    pub params: Params,
+    /// ms
+    pub test_duration: Float,
+    /// largest test magnitude tried
+    pub max: Float,
+    pub intervals: Vec<Float>,
+    /// one per interval done so far, None where no magnitude up to `max` fired
+    pub thresholds: Vec<Option<Float>>,
+    /// threshold of the test pulse on its own
+    pub control: Option<Float>,
+    /// spikes fired by the conditioning pulse on its own
+    pub conditioning_spikes: usize,
+    /// the conditioning pulse on its own, from its onset to past the last interval
+    pub conditioning: Vec<Axon>,
+    stage: Stage,
+    /// search for the threshold of the control or of the next interval
+    bisection: Bisection,
+    /// spikes of the conditioning pulse followed by a test pulse of zero, at the next interval
+    alone: Option<usize>,
+}
+
+impl PairedPulse {
+    pub fn new(
+        setup: &Setup,
+        params: &Params,
+        test_duration: Float,
+        max: Float,
+        intervals: Vec<Float>,
+    ) -> Self {
+        Self {
+            setup: Setup {
+                replay: None,
+                ..setup.clone()
+            },
+            params: params.clone(),
+            test_duration,
+            max,
+            intervals,
+            thresholds: Vec::new(),
+            control: None,
+            conditioning_spikes: 0,
+            conditioning: Vec::new(),
+            stage: Stage::Conditioning,
+            bisection: Bisection::new(max),
+            alone: None,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.stage == Stage::Intervals && self.thresholds.len() == self.intervals.len()
+    }
+
+    /// whether the conditioning pulse and the test pulse alone have been measured
+    pub fn has_control(&self) -> bool {
+        self.stage == Stage::Intervals
+    }
+
+    /// fraction of the thresholds found, the control's included
+    pub fn progress(&self) -> Float {
+        let found = self.thresholds.len() + usize::from(self.has_control());
+        found as Float / (self.intervals.len() + 1) as Float
+    }
+
+    pub fn step(&mut self) {
+        match self.stage {
+            Stage::Conditioning => {
+                let last = self.intervals.iter().copied().fold(0.0, Float::max);
+                let setup = Setup {
+                    end: self.setup.pulse.start + last + self.test_duration + SETTLE,
+                    ..self.setup.clone()
+                };
+                let history = hh::simulate(&setup, &self.params);
+                let onset = (setup.pulse.start / setup.dt).round() as usize;
+                self.conditioning_spikes = spikes::of_history(&history, setup.dt).len();
+                self.conditioning = history.get(onset..).unwrap_or_default().to_vec();
+                self.stage = Stage::Control;
+            }
+            Stage::Control => {
+                let mut setup = pulse_setup(&self.setup, self.test_duration);
+                setup.pulse.magnitude = self.bisection.trial();
+                let from = setup.pulse.start;
+                self.bisection.record(fires(&setup, &self.params, from));
+                if let Some(control) = self.bisection.outcome {
+                    self.control = control;
+                    self.bisection = Bisection::new(self.max);
+                    self.stage = Stage::Intervals;
+                }
+            }
+            Stage::Intervals => self.test(),
+        }
+    }
+
+    /// one trial of the test pulse at the next interval
+    fn test(&mut self) {
+        let Some(&interval) = self.intervals.get(self.thresholds.len()) else {
+            return;
+        };
+        let start = self.setup.pulse.start + interval;
+        let setup = Setup {
+            end: start + self.test_duration + SETTLE,
+            ..self.setup.clone()
+        };
+        let spikes = |magnitude: Float| {
+            let test = Pulse {
+                start,
+                end: start + self.test_duration,
+                magnitude,
+            };
+            let current = |t, _: &Axon| setup.pulse.current(t) + test.current(t);
+            spikes::of_history(&hh::simulate_with(&setup, &self.params, current), setup.dt).len()
+        };
+        // the test pulse fires when it adds a spike to those of the conditioning pulse, the
+        // first trial being a test pulse of zero
+        let count = spikes(self.bisection.trial());
+        let alone = *self.alone.get_or_insert(count);
+        self.bisection.record(count > alone);
+        if let Some(threshold) = self.bisection.outcome {
+            self.thresholds.push(threshold);
+            self.bisection = Bisection::new(self.max);
+            self.alone = None;
+        }
+    }
+
+    /// Bounds on the absolute refractory period: the longest interval at which the test pulse
+    /// did not fire before any at which it did, and the first one at which it did.
+    pub fn absolute_refractory(&self) -> Option<(Float, Float)> {
+        let first = self.thresholds.iter().position(Option::is_some)?;
+        (first > 0).then(|| (self.intervals[first - 1], self.intervals[first]))
+    }
+
+    /// Time constant of the threshold's return to control, from `θ / θ₀ - 1 ∝ exp(-Δ / τ)`
+    /// fitted by least squares on its logarithm.
+    pub fn recovery_tau(&self) -> Option<Float> {
+        let control = self.control?;
+        let (x, y): (Vec<Float>, Vec<Float>) = self
+            .intervals
+            .iter()
+            .zip(&self.thresholds)
+            .filter_map(|(&d, &theta)| {
+                let excess = theta? / control - 1.0;
+                (excess > 1e-2).then(|| (d, excess.ln()))
+            })
+            .unzip();
+        if x.len() < 2 {
+            return None;
+        }
+        let n = x.len() as Float;
+        let (mean_x, mean_y) = (x.iter().sum::<Float>() / n, y.iter().sum::<Float>() / n);
+        let (mut sxy, mut sxx) = (0.0, 0.0);
+        for (x, y) in x.iter().zip(&y) {
+            sxy += (x - mean_x) * (y - mean_y);
+            sxx += (x - mean_x) * (x - mean_x);
+        }
+        let tau = -sxx / sxy;
+        (tau.is_finite() && tau > 0.0).then_some(tau)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_recover_their_laws() {
+        let durations = log_spaced(0.05, 20.0, 12);
+        let law = |d: Float| 3.0 / (1.0 - (-d / 0.8).exp());
+        let thresholds: Vec<Float> = durations.iter().map(|&d| law(d)).collect();
+        let fit = lapicque(&durations, &thresholds).unwrap();
+        assert!((fit.threshold(1.5) - law(1.5)).abs() < 1e-6);
+        assert!((fit.rheobase - 3.0).abs() < 1e-6);
+        assert!((fit.chronaxie - 0.8 * Float::ln(2.0)).abs() < 1e-6);
+        assert!(fit.error < 1e-9);
+
+        let thresholds: Vec<Float> = durations.iter().map(|d| 2.0 * (1.0 + 0.4 / d)).collect();
+        let fit = weiss(&durations, &thresholds).unwrap();
+        assert!((fit.rheobase - 2.0).abs() < 1e-9);
+        assert!((fit.chronaxie - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn threshold_falls_with_duration() {
+        let find = |polarity, max, durations| {
+            let (setup, params) = (Setup::default(), Params::default());
+            let mut search = StrengthDuration::new(&setup, &params, polarity, max, durations);
+            while !search.finished() {
+                search.step();
+            }
+            search
+        };
+        let search = find(Polarity::Depolarizing, 200.0, vec![0.2, 1.0, 5.0]);
+        let (_, thresholds) = search.found();
+        assert_eq!(thresholds.len(), 3);
+        assert!(thresholds.windows(2).all(|w| w[0] > w[1]));
+
+        // just above threshold fires, just below does not
+        let mut setup = Setup::default();
+        setup.pulse.end = setup.pulse.start + 1.0;
+        setup.end = setup.pulse.end + SETTLE;
+        setup.pulse.magnitude = thresholds[1] * 1.01;
+        assert!(fires(&setup, &search.params, 0.0));
+        setup.pulse.magnitude = thresholds[1] * 0.99;
+        assert!(!fires(&setup, &search.params, 0.0));
+
+        // a long enough hyperpolarization fires on release
+        let anode = find(Polarity::Hyperpolarizing, 100.0, vec![20.0]);
+        assert_eq!(anode.found().1.len(), 1);
+    }
+
+    #[test]
+    fn paired_pulse_refractoriness() {
+        let mut protocol = PairedPulse::new(
+            &Setup::default(),
+            &Params::default(),
+            0.5,
+            200.0,
+            vec![1.0, 3.0, 6.0, 9.0, 12.0, 16.0, 20.0],
+        );
+        while !protocol.finished() {
+            protocol.step();
+        }
+        assert_eq!(protocol.conditioning_spikes, 1);
+        assert_eq!(protocol.conditioning[0].v(), 0.0);
+        let control = protocol.control.unwrap();
+        let (below, above) = protocol.absolute_refractory().unwrap();
+        assert!(below < above && above <= 9.0);
+
+        // relative refractoriness wears off
+        let found: Vec<Float> = protocol.thresholds.iter().flatten().copied().collect();
+        assert!(found.windows(2).all(|w| w[0] >= w[1]));
+        assert!(found[0] > 1.5 * control);
+        assert!(*found.last().unwrap() < 1.3 * control);
+        let tau = protocol.recovery_tau().unwrap();
+        assert!((1.0..20.0).contains(&tau), "{tau}");
+    }
+}
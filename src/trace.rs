@@ -0,0 +1,541 @@
+use crate::Float;
+
+/// One recorded sweep. Time is in ms, voltage in mV and current in the unit it was recorded in.
+#[derive(Clone, Default)]
+pub struct Sweep {
+    pub t: Vec<Float>,
+    pub v: Vec<Float>,
+    pub i: Option<Vec<Float>>,
+}
+
+impl Sweep {
+    pub fn duration(&self) -> Float {
+        match (self.t.first(), self.t.last()) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Trace {
+    pub name: String,
+    pub current_unit: String,
+    pub sweeps: Vec<Sweep>,
+}
+
+/// Load a trace from disk, picking the parser by file extension.
+pub fn load(path: &str) -> Result<Trace, String> {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path).to_string();
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let bytes = std::fs::read(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    let mut trace = match extension.as_str() {
+        "abf" => parse_abf(&bytes)?,
+        "atf" => parse_atf(&String::from_utf8_lossy(&bytes))?,
+        _ => parse_csv(&String::from_utf8_lossy(&bytes))?,
+    };
+    trace.name = name;
+    Ok(trace)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Quantity {
+    Time,
+    Voltage,
+    Current,
+}
+
+/// Column header, classified by name and unit, with the factor to convert into ms / mV.
+struct Column {
+    quantity: Quantity,
+    scale: Float,
+    unit: String,
+}
+
+fn classify(title: &str, index: usize) -> Column {
+    let title = title.trim().trim_matches('"');
+    let unit = title
+        .rsplit_once('(')
+        .and_then(|(_, rest)| rest.split_once(')'))
+        .map(|(unit, _)| unit.trim().to_string())
+        .unwrap_or_default();
+    let lower = title.to_ascii_lowercase();
+
+    let quantity = match unit.as_str() {
+        "s" | "ms" | "us" | "µs" => Quantity::Time,
+        "V" | "mV" | "uV" | "µV" => Quantity::Voltage,
+        "A" | "mA" | "uA" | "µA" | "nA" | "pA" => Quantity::Current,
+        _ if lower.starts_with("time") || lower == "t" => Quantity::Time,
+        _ if lower.starts_with('i') || lower.contains("current") || lower.contains("cmd") => {
+            Quantity::Current
+        }
+        _ if index == 0 => Quantity::Time,
+        _ => Quantity::Voltage,
+    };
+    let scale = match unit.as_str() {
+        "s" | "V" => 1e3,
+        "us" | "µs" | "uV" | "µV" => 1e-3,
+        _ => 1.0,
+    };
+
+    Column {
+        quantity,
+        scale,
+        unit,
+    }
+}
+
+/// Split columns into sweeps: every voltage column starts a sweep, current columns attach to the
+/// sweep of the voltage column before them, or to every sweep if there is no such column.
+fn sweeps_from_columns(columns: &[Column], data: &[Vec<Float>]) -> Result<Trace, String> {
+    let mut trace = Trace::default();
+    let mut time: Option<Vec<Float>> = None;
+    let mut orphan_current: Option<Vec<Float>> = None;
+
+    for (column, values) in columns.iter().zip(data) {
+        let values: Vec<Float> = values.iter().map(|x| x * column.scale).collect();
+        match column.quantity {
+            Quantity::Time => time = Some(values),
+            Quantity::Voltage => {
+                let t = time
+                    .clone()
+                    .ok_or("voltage column appears before any time column")?;
+                trace.sweeps.push(Sweep {
+                    t,
+                    v: values,
+                    i: None,
+                });
+            }
+            Quantity::Current => {
+                trace.current_unit.clone_from(&column.unit);
+                match trace.sweeps.last_mut() {
+                    Some(sweep) if sweep.i.is_none() => sweep.i = Some(values),
+                    _ => orphan_current = Some(values),
+                }
+            }
+        }
+    }
+
+    if let Some(current) = orphan_current {
+        for sweep in trace.sweeps.iter_mut().filter(|s| s.i.is_none()) {
+            sweep.i = Some(current.clone());
+        }
+    }
+    if trace.sweeps.is_empty() {
+        return Err("no voltage column found".to_string());
+    }
+    Ok(trace)
+}
+
+fn parse_table<'a>(
+    header: Option<&str>,
+    rows: impl Iterator<Item = &'a str>,
+    separator: impl Fn(char) -> bool + Copy,
+) -> Result<Trace, String> {
+    let mut data: Vec<Vec<Float>> = Vec::new();
+    for (row, line) in rows.enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(separator).filter(|f| !f.is_empty()).collect();
+        if data.is_empty() {
+            data.resize(fields.len(), Vec::new());
+        }
+        if fields.len() != data.len() {
+            return Err(format!(
+                "row {} has {} fields, expected {}",
+                row + 1,
+                fields.len(),
+                data.len()
+            ));
+        }
+        for (column, field) in data.iter_mut().zip(fields) {
+            column.push(
+                field
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("row {}: cannot parse `{field}`", row + 1))?,
+            );
+        }
+    }
+
+    let titles: Vec<&str> = match header {
+        Some(header) => header.split(separator).filter(|f| !f.is_empty()).collect(),
+        None => vec![""; data.len()],
+    };
+    if titles.len() != data.len() {
+        return Err("header and data have different numbers of columns".to_string());
+    }
+    let columns: Vec<Column> = titles
+        .iter()
+        .enumerate()
+        .map(|(index, title)| classify(title, index))
+        .collect();
+    sweeps_from_columns(&columns, &data)
+}
+
+/// Comma, semicolon or tab separated columns with an optional header row.
+///
+/// The first column is time. Columns are classified by header name or unit, e.g. `t (ms)`,
+/// `V1 (mV)`, `I (pA)`; without a header, every other column is a voltage sweep.
+pub fn parse_csv(text: &str) -> Result<Trace, String> {
+    let separator = |c: char| c == ',' || c == ';' || c == '\t';
+    let mut lines = text
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'));
+    let first = lines.next().ok_or("file is empty")?;
+
+    let has_header = first
+        .split(separator)
+        .any(|field| field.trim().parse::<Float>().is_err());
+    if has_header {
+        parse_table(Some(first), lines, separator)
+    } else {
+        parse_table(None, std::iter::once(first).chain(lines), separator)
+    }
+}
+
+/// Axon Text File: an `ATF` signature line, a line with the optional header record and column
+/// counts, the header records, a line of column titles and then tab separated data.
+pub fn parse_atf(text: &str) -> Result<Trace, String> {
+    let mut lines = text.lines();
+    if !lines.next().is_some_and(|l| l.starts_with("ATF")) {
+        return Err("missing ATF signature".to_string());
+    }
+    let counts: Vec<usize> = lines
+        .next()
+        .ok_or("missing record counts")?
+        .split_whitespace()
+        .map(|n| n.parse().map_err(|_| "malformed record counts".to_string()))
+        .collect::<Result<_, _>>()?;
+    let headers = *counts.first().ok_or("malformed record counts")?;
+    let mut lines = lines.skip(headers);
+    let titles = lines.next().ok_or("missing column titles")?;
+
+    parse_table(Some(titles), lines, |c| c == '\t' || c == ',')
+}
+
+/// Little-endian field access into an in-memory file.
+struct Bytes<'a>(&'a [u8]);
+
+impl Bytes<'_> {
+    fn get<const N: usize>(&self, offset: usize) -> Result<[u8; N], String> {
+        self.0
+            .get(offset..offset + N)
+            .and_then(|s| s.try_into().ok())
+            .ok_or_else(|| format!("file truncated at byte {offset}"))
+    }
+    fn i16(&self, offset: usize) -> Result<i16, String> {
+        self.get(offset).map(i16::from_le_bytes)
+    }
+    fn i32(&self, offset: usize) -> Result<i32, String> {
+        self.get(offset).map(i32::from_le_bytes)
+    }
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        self.get(offset).map(u32::from_le_bytes)
+    }
+    fn i64(&self, offset: usize) -> Result<i64, String> {
+        self.get(offset).map(i64::from_le_bytes)
+    }
+    fn f32(&self, offset: usize) -> Result<f32, String> {
+        self.get(offset).map(f32::from_le_bytes)
+    }
+    fn str(&self, offset: usize, len: usize) -> Result<String, String> {
+        let bytes = self
+            .0
+            .get(offset..offset + len)
+            .ok_or_else(|| format!("file truncated at byte {offset}"))?;
+        Ok(String::from_utf8_lossy(bytes)
+            .trim_matches(['\0', ' '])
+            .to_string())
+    }
+}
+
+const ABF_BLOCK: usize = 512;
+
+/// Everything needed to turn the raw data section of an ABF file into sweeps.
+struct AbfLayout {
+    data_offset: usize,
+    float_data: bool,
+    gap_free: bool,
+    sweeps: usize,
+    channels: usize,
+    samples_per_sweep: usize,
+    /// per channel sample interval in µs
+    interval: Float,
+    /// per channel multiplier and offset from raw integers to physical values
+    gain: Vec<(Float, Float)>,
+    units: Vec<String>,
+}
+
+fn abf1_layout(file: &Bytes) -> Result<AbfLayout, String> {
+    let channels = file.i16(120)?.max(1) as usize;
+    let adc_range = file.f32(244)? as Float;
+    let adc_resolution = file.i32(252)? as Float;
+
+    let mut gain = Vec::new();
+    let mut units = Vec::new();
+    for logical in 0..channels {
+        let physical = file.i16(410 + 2 * logical)?.clamp(0, 15) as usize;
+        let telegraph = if file.i16(4512 + 2 * physical)? != 0 {
+            file.f32(4576 + 4 * physical)? as Float
+        } else {
+            1.0
+        };
+        let scale = file.f32(922 + 4 * physical)? as Float
+            * file.f32(1050 + 4 * physical)? as Float
+            * file.f32(730 + 4 * physical)? as Float
+            * telegraph;
+        let offset =
+            file.f32(986 + 4 * physical)? as Float - file.f32(1114 + 4 * physical)? as Float;
+        gain.push((adc_range / adc_resolution / scale, offset));
+        units.push(file.str(602 + 8 * physical, 8)?);
+    }
+
+    let operation_mode = file.i16(8)?;
+    let total = file.i32(10)?.max(0) as usize;
+    let samples_per_sweep = if operation_mode == 3 {
+        total / channels
+    } else {
+        file.i32(138)?.max(0) as usize / channels
+    };
+    Ok(AbfLayout {
+        data_offset: file.i32(40)?.max(0) as usize * ABF_BLOCK,
+        float_data: file.i16(100)? == 1,
+        gap_free: operation_mode == 3,
+        sweeps: file.i32(16)?.max(1) as usize,
+        channels,
+        samples_per_sweep,
+        interval: file.f32(122)? as Float * channels as Float,
+        gain,
+        units,
+    })
+}
+
+fn abf2_layout(file: &Bytes) -> Result<AbfLayout, String> {
+    let section = |index: usize| -> Result<(usize, usize, usize), String> {
+        let offset = 76 + 16 * index;
+        Ok((
+            file.u32(offset)? as usize * ABF_BLOCK,
+            file.u32(offset + 4)? as usize,
+            file.i64(offset + 8)?.max(0) as usize,
+        ))
+    };
+    let (protocol, _, _) = section(0)?;
+    let (adc, adc_size, channels) = section(1)?;
+    let (strings, strings_size, _) = section(9)?;
+    let (data, _, data_entries) = section(10)?;
+    let channels = channels.max(1);
+
+    let adc_range = file.f32(protocol + 110)? as Float;
+    let adc_resolution = file.i32(protocol + 118)? as Float;
+
+    // the strings section starts with the creator name, then the indexed strings
+    let strings: Vec<String> = file
+        .0
+        .get(strings..strings + strings_size)
+        .unwrap_or_default()
+        .split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).trim().to_string())
+        .collect();
+
+    let mut gain = Vec::new();
+    let mut units = Vec::new();
+    for channel in 0..channels {
+        let entry = adc + channel * adc_size;
+        let telegraph = if file.i16(entry + 2)? != 0 {
+            file.f32(entry + 6)? as Float
+        } else {
+            1.0
+        };
+        let scale = file.f32(entry + 40)? as Float
+            * file.f32(entry + 48)? as Float
+            * file.f32(entry + 28)? as Float
+            * telegraph;
+        let offset = file.f32(entry + 44)? as Float - file.f32(entry + 52)? as Float;
+        gain.push((adc_range / adc_resolution / scale, offset));
+        let unit_index = file.i32(entry + 78)?.max(0) as usize;
+        units.push(strings.get(unit_index).cloned().unwrap_or_default());
+    }
+
+    let gap_free = file.i16(protocol)? == 3;
+    let samples_per_sweep = if gap_free {
+        data_entries / channels
+    } else {
+        file.i32(protocol + 22)?.max(0) as usize / channels
+    };
+    Ok(AbfLayout {
+        data_offset: data,
+        float_data: file.i16(30)? == 1,
+        gap_free,
+        sweeps: file.u32(12)?.max(1) as usize,
+        channels,
+        samples_per_sweep,
+        interval: file.f32(protocol + 2)? as Float,
+        gain,
+        units,
+    })
+}
+
+/// Axon Binary Format, both the fixed-header ABF1 and the sectioned ABF2 layout.
+///
+/// Channels with a voltage unit become sweeps, the first channel with a current unit (or the
+/// second channel, if units are unknown) is attached as the current trace.
+pub fn parse_abf(bytes: &[u8]) -> Result<Trace, String> {
+    let file = Bytes(bytes);
+    let layout = match &file.get::<4>(0)? {
+        b"ABF " => abf1_layout(&file)?,
+        b"ABF2" => abf2_layout(&file)?,
+        _ => return Err("not an ABF file".to_string()),
+    };
+    if layout.samples_per_sweep == 0 || layout.interval <= 0.0 {
+        return Err("ABF file holds no samples".to_string());
+    }
+
+    let sample_size = if layout.float_data { 4 } else { 2 };
+    let sweeps = if layout.gap_free { 1 } else { layout.sweeps };
+    // check the header against the file before allocating anything it sizes
+    let needed = layout
+        .samples_per_sweep
+        .checked_mul(sweeps)
+        .and_then(|n| n.checked_mul(layout.channels))
+        .and_then(|n| n.checked_mul(sample_size));
+    let available = bytes.len().saturating_sub(layout.data_offset);
+    if needed.is_none_or(|needed| needed > available) {
+        return Err("ABF file is shorter than its header says".to_string());
+    }
+    let sample = |index: usize, channel: usize| -> Result<Float, String> {
+        let offset = layout.data_offset + index * sample_size;
+        if layout.float_data {
+            file.f32(offset).map(Float::from)
+        } else {
+            let (scale, shift) = layout.gain[channel];
+            file.i16(offset).map(|raw| raw as Float * scale + shift)
+        }
+    };
+
+    let is_current = |unit: &str| unit.ends_with('A');
+    let voltage = (0..layout.channels)
+        .find(|&c| !is_current(&layout.units[c]))
+        .unwrap_or(0);
+    let current = (0..layout.channels)
+        .find(|&c| c != voltage && is_current(&layout.units[c]))
+        .or((layout.channels > 1).then_some(1 - voltage.min(1)));
+    let voltage_scale = if layout.units[voltage] == "V" {
+        1e3
+    } else {
+        1.0
+    };
+
+    let dt = layout.interval / 1e3;
+    let mut trace = Trace {
+        current_unit: current.map(|c| layout.units[c].clone()).unwrap_or_default(),
+        ..Default::default()
+    };
+    for sweep in 0..sweeps {
+        let first = sweep * layout.samples_per_sweep * layout.channels;
+        let channel = |c: usize| -> Result<Vec<Float>, String> {
+            (0..layout.samples_per_sweep)
+                .map(|k| sample(first + k * layout.channels + c, c))
+                .collect()
+        };
+        trace.sweeps.push(Sweep {
+            t: (0..layout.samples_per_sweep)
+                .map(|k| k as Float * dt)
+                .collect(),
+            v: channel(voltage)?
+                .into_iter()
+                .map(|v| v * voltage_scale)
+                .collect(),
+            i: current.map(channel).transpose()?,
+        });
+    }
+    Ok(trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_with_header() {
+        let trace = parse_csv("time (s),V (mV),I (pA)\n0,-65,0\n0.001,-64,100\n").unwrap();
+        assert_eq!(trace.sweeps.len(), 1);
+        assert_eq!(trace.current_unit, "pA");
+        let sweep = &trace.sweeps[0];
+        assert_eq!(sweep.t, vec![0.0, 1.0]);
+        assert_eq!(sweep.v, vec![-65.0, -64.0]);
+        assert_eq!(sweep.i, Some(vec![0.0, 100.0]));
+    }
+
+    #[test]
+    fn csv_without_header() {
+        let trace = parse_csv("0;1;2\n0.5;3;4\n").unwrap();
+        assert_eq!(trace.sweeps.len(), 2);
+        assert_eq!(trace.sweeps[1].v, vec![2.0, 4.0]);
+        assert!(trace.sweeps[1].i.is_none());
+    }
+
+    #[test]
+    fn atf() {
+        let text = "ATF\t1.0\n1\t3\n\"AcquisitionMode=Episodic Stimulation\"\n\
+                    \"Time (ms)\"\t\"Trace #1 (mV)\"\t\"Trace #2 (mV)\"\n\
+                    0\t-70\t-60\n0.1\t-71\t-61\n";
+        let trace = parse_atf(text).unwrap();
+        assert_eq!(trace.sweeps.len(), 2);
+        assert_eq!(trace.sweeps[0].v, vec![-70.0, -71.0]);
+        assert_eq!(trace.sweeps[1].t, vec![0.0, 0.1]);
+    }
+
+    #[test]
+    fn abf2_episodic() {
+        let mut file = vec![0u8; 5 * ABF_BLOCK];
+        let mut put =
+            |offset: usize, bytes: &[u8]| file[offset..offset + bytes.len()].copy_from_slice(bytes);
+        put(0, b"ABF2");
+        put(12, &2u32.to_le_bytes());
+        // protocol in block 1, ADC in block 2, strings in block 3 and data after that
+        put(76, &1u32.to_le_bytes());
+        put(92, &2u32.to_le_bytes());
+        put(96, &128u32.to_le_bytes());
+        put(100, &2i64.to_le_bytes());
+        put(220, &3u32.to_le_bytes());
+        put(224, &16u32.to_le_bytes());
+        put(236, &4u32.to_le_bytes());
+        put(248, &8i64.to_le_bytes());
+
+        let protocol = ABF_BLOCK;
+        put(protocol, &5i16.to_le_bytes());
+        put(protocol + 2, &100f32.to_le_bytes());
+        put(protocol + 22, &4i32.to_le_bytes());
+        put(protocol + 110, &10f32.to_le_bytes());
+        put(protocol + 118, &10i32.to_le_bytes());
+        for channel in 0..2 {
+            let entry = 2 * ABF_BLOCK + channel * 128;
+            for gain in [28, 40, 48] {
+                put(entry + gain, &1f32.to_le_bytes());
+            }
+            put(entry + 78, &(channel as i32 + 1).to_le_bytes());
+        }
+        put(3 * ABF_BLOCK, b"clampex\0mV\0pA\0");
+        for (k, raw) in [1i16, 2, 3, 4, 5, 6, 7, 8].iter().enumerate() {
+            put(4 * ABF_BLOCK + 2 * k, &raw.to_le_bytes());
+        }
+
+        let trace = parse_abf(&file).unwrap();
+        assert_eq!(trace.current_unit, "pA");
+        assert_eq!(trace.sweeps.len(), 2);
+        assert_eq!(trace.sweeps[0].t, vec![0.0, 0.1]);
+        assert_eq!(trace.sweeps[0].v, vec![1.0, 3.0]);
+        assert_eq!(trace.sweeps[1].v, vec![5.0, 7.0]);
+        assert_eq!(trace.sweeps[1].i, Some(vec![6.0, 8.0]));
+
+        file.truncate(4 * ABF_BLOCK + 14);
+        assert!(parse_abf(&file).is_err());
+    }
+}